@@ -0,0 +1,732 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::entity::Entity;
+use crate::error::{AppError, Result};
+use crate::journal;
+use crate::priority::Priority;
+use crate::recurrence;
+use crate::schedule;
+use crate::tag;
+use crate::task_item::TaskItemStatus;
+
+// The whole database as one JSON document, for backups and for moving data between
+// machines. Version 1 looks like:
+//
+//     {
+//       "format": "yuno-tasks",
+//       "version": 1,
+//       "exported_at": "2026-10-18 09:30:00",           (UTC)
+//       "projects":   [{ "id", "title", "description", "rank", "created_at", "updated_at",
+//                        "completed_at", "deleted_at" }],
+//       "tasks":      [{ "id", "project_id", "title", "description", "rank", "start_at", "due_at",
+//                        "priority", "recurrence", "recurred_id", "created_at", "updated_at",
+//                        "completed_at", "deleted_at" }],
+//       "task_items": [{ "id", "content", "status", "start_at", "due_at", "priority", "recurrence",
+//                        "recurred_id", "created_at", "updated_at", "completed_at", "deleted_at" }],
+//       "task_item_links":   [{ "task_id", "item_id", "rank", "parent_id" }],
+//       "tags":              [{ "id", "name", "color" }],
+//       "taggings":          [{ "tag_id", "entity", "entity_id" }],
+//       "task_dependencies": [{ "task_id", "depends_on_id" }]
+//     }
+//
+// Ids only tie the document together; merging gives every row a new one. Ranks order
+// projects, a project's tasks and the items under one parent of a task (see rank.rs);
+// a link's rank is null while its item is closed. Statuses are "Pending", "InProgress",
+// "Blocked", "Deferred", "Completed" or "Canceled", priorities "P0".."P3" or null, and
+// dates "YYYY-MM-DD HH:MM:SS" (start/due in local time, the rest UTC; a bare date is
+// read as its first second, or a due date as its last). Trashed rows are included with
+// their `deleted_at`. The undo journal and change history are not.
+// The tags, taggings and task_dependencies lists may be left out.
+
+pub const FORMAT: &str = "yuno-tasks";
+pub const VERSION: i64 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Document {
+    pub format: String,
+    pub version: i64,
+    pub exported_at: Option<String>,
+    pub projects: Vec<ProjectRecord>,
+    pub tasks: Vec<TaskRecord>,
+    pub task_items: Vec<TaskItemRecord>,
+    pub task_item_links: Vec<LinkRecord>,
+    #[serde(default)]
+    pub tags: Vec<TagRecord>,
+    #[serde(default)]
+    pub taggings: Vec<TaggingRecord>,
+    #[serde(default)]
+    pub task_dependencies: Vec<DependencyRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectRecord {
+    pub id: i64,
+    pub title: String,
+    pub description: Option<String>,
+    pub rank: f64,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub completed_at: Option<String>,
+    pub deleted_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskRecord {
+    pub id: i64,
+    pub project_id: i64,
+    pub title: String,
+    pub description: Option<String>,
+    pub rank: f64,
+    pub start_at: Option<String>,
+    pub due_at: Option<String>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<String>,
+    pub recurred_id: Option<i64>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub completed_at: Option<String>,
+    pub deleted_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskItemRecord {
+    pub id: i64,
+    pub content: String,
+    pub status: TaskItemStatus,
+    pub start_at: Option<String>,
+    pub due_at: Option<String>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<String>,
+    pub recurred_id: Option<i64>,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub completed_at: Option<String>,
+    pub deleted_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LinkRecord {
+    pub task_id: i64,
+    pub item_id: i64,
+    pub rank: Option<f64>,
+    pub parent_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagRecord {
+    pub id: i64,
+    pub name: String,
+    pub color: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaggingRecord {
+    pub tag_id: i64,
+    pub entity: Entity,
+    pub entity_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DependencyRecord {
+    pub task_id: i64,
+    pub depends_on_id: i64,
+}
+
+pub fn export_json(conn: &Connection) -> Result<String> {
+    let document = Document {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        exported_at: Some(conn.prepare_cached("SELECT datetime('now')")?.query_row([], |row| row.get(0))?),
+        projects: conn.prepare_cached(
+            "SELECT id, title, description, rank, created_at, updated_at, completed_at, deleted_at FROM projects ORDER BY id"
+        )?
+            .query_map([], |row| Ok(ProjectRecord {
+                id: row.get(0)?,
+                title: row.get(1)?,
+                description: row.get(2)?,
+                rank: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
+                completed_at: row.get(6)?,
+                deleted_at: row.get(7)?,
+            }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+        tasks: conn.prepare_cached(
+            "SELECT id, project_id, title, description, rank, start_at, due_at, priority, recurrence, recurred_id,
+                    created_at, updated_at, completed_at, deleted_at
+             FROM tasks ORDER BY id"
+        )?
+            .query_map([], |row| Ok(TaskRecord {
+                id: row.get(0)?,
+                project_id: row.get(1)?,
+                title: row.get(2)?,
+                description: row.get(3)?,
+                rank: row.get(4)?,
+                start_at: row.get(5)?,
+                due_at: row.get(6)?,
+                priority: row.get(7)?,
+                recurrence: row.get(8)?,
+                recurred_id: row.get(9)?,
+                created_at: row.get(10)?,
+                updated_at: row.get(11)?,
+                completed_at: row.get(12)?,
+                deleted_at: row.get(13)?,
+            }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+        task_items: conn.prepare_cached(
+            "SELECT id, content, status, start_at, due_at, priority, recurrence, recurred_id,
+                    created_at, updated_at, completed_at, deleted_at
+             FROM task_items ORDER BY id"
+        )?
+            .query_map([], |row| Ok(TaskItemRecord {
+                id: row.get(0)?,
+                content: row.get(1)?,
+                status: row.get(2)?,
+                start_at: row.get(3)?,
+                due_at: row.get(4)?,
+                priority: row.get(5)?,
+                recurrence: row.get(6)?,
+                recurred_id: row.get(7)?,
+                created_at: row.get(8)?,
+                updated_at: row.get(9)?,
+                completed_at: row.get(10)?,
+                deleted_at: row.get(11)?,
+            }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+        task_item_links: conn.prepare_cached("SELECT task_id, item_id, rank, parent_id FROM task_item_links ORDER BY task_id, item_id")?
+            .query_map([], |row| Ok(LinkRecord { task_id: row.get(0)?, item_id: row.get(1)?, rank: row.get(2)?, parent_id: row.get(3)? }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+        tags: conn.prepare_cached("SELECT id, name, color FROM tags ORDER BY id")?
+            .query_map([], |row| Ok(TagRecord { id: row.get(0)?, name: row.get(1)?, color: row.get(2)? }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+        taggings: conn.prepare_cached("SELECT tag_id, entity, entity_id FROM taggings ORDER BY tag_id, entity, entity_id")?
+            .query_map([], |row| Ok(TaggingRecord { tag_id: row.get(0)?, entity: row.get(1)?, entity_id: row.get(2)? }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+        task_dependencies: conn.prepare_cached("SELECT task_id, depends_on_id FROM task_dependencies ORDER BY task_id, depends_on_id")?
+            .query_map([], |row| Ok(DependencyRecord { task_id: row.get(0)?, depends_on_id: row.get(1)? }))?
+            .collect::<rusqlite::Result<Vec<_>>>()?,
+    };
+
+    serde_json::to_string_pretty(&document).map_err(|e| AppError::Storage { message: e.to_string() })
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    // Everything in the database is removed first and the document's ids are kept
+    Replace,
+    // The document is added next to what's there, under new ids; tags are matched by name
+    MergeAsNew,
+}
+
+// Row counts per table
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+pub struct Counts {
+    pub projects: usize,
+    pub tasks: usize,
+    pub task_items: usize,
+    pub task_item_links: usize,
+    pub tags: usize,
+    pub taggings: usize,
+    pub task_dependencies: usize,
+}
+
+// What an import did, or for a dry run what it would do
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    pub dry_run: bool,
+    // Rows the document adds; for merges, tags that matched an existing name aren't counted
+    pub imported: Counts,
+    // Rows a replace removed, or would remove
+    pub replaced: Counts,
+}
+
+// Imports as one unit: a document that fails any check leaves the database untouched.
+// The import clears the undo journal, whose entries can't span it. A dry run makes the
+// import and rolls it back, so the database's own constraints get a say.
+pub fn import_json(conn: &Connection, json: &str, mode: ImportMode, dry_run: bool) -> Result<ImportReport> {
+    let mut document: Document = serde_json::from_str(json)
+        .map_err(|e| AppError::validation("document", format!("not a readable export: {}", e)))?;
+    if document.format != FORMAT {
+        return Err(AppError::validation("document", format!("format is {:?}, expected {:?}", document.format, FORMAT)));
+    }
+    if document.version != VERSION {
+        return Err(AppError::validation(
+            "document",
+            format!("version {} is not supported, this build reads version {}", document.version, VERSION),
+        ));
+    }
+    check(conn, &mut document)?;
+
+    let replaced = match mode {
+        ImportMode::Replace => counts(conn)?,
+        ImportMode::MergeAsNew => Counts::default(),
+    };
+    if dry_run {
+        conn.execute_batch("SAVEPOINT dry_run")?;
+    }
+    let imported = match mode {
+        ImportMode::Replace => clear(conn).and_then(|()| insert(conn, &document, false)),
+        ImportMode::MergeAsNew => insert(conn, &document, true),
+    };
+    if dry_run {
+        conn.execute_batch("ROLLBACK TO dry_run; RELEASE dry_run")?;
+    } else if imported.is_ok() {
+        journal::clear(conn)?;
+    }
+
+    Ok(ImportReport { mode, dry_run, imported: imported?, replaced })
+}
+
+fn invalid(reason: impl Into<String>) -> AppError {
+    AppError::validation("document", reason)
+}
+
+// Everything the schema wouldn't catch by itself, or would only report as a bare
+// constraint failure. Dates are normalised in place.
+fn check(conn: &Connection, document: &mut Document) -> Result<()> {
+    fn unique(name: &str, ids: impl Iterator<Item = i64>) -> Result<HashSet<i64>> {
+        let mut seen = HashSet::new();
+        for id in ids {
+            if !seen.insert(id) {
+                return Err(invalid(format!("{} id {} appears twice", name, id)));
+            }
+        }
+        Ok(seen)
+    }
+    let projects = unique("project", document.projects.iter().map(|project| project.id))?;
+    let tasks = unique("task", document.tasks.iter().map(|task| task.id))?;
+    let items = unique("task item", document.task_items.iter().map(|item| item.id))?;
+    let tags = unique("tag", document.tags.iter().map(|tag| tag.id))?;
+
+    for project in &mut document.projects {
+        require_text("project", project.id, &project.title)?;
+        normalize_timestamps(conn, "project", project.id, [
+            &mut project.created_at, &mut project.updated_at, &mut project.completed_at, &mut project.deleted_at,
+        ])?;
+    }
+    for task in &mut document.tasks {
+        require_text("task", task.id, &task.title)?;
+        if !projects.contains(&task.project_id) {
+            return Err(invalid(format!("task {} belongs to missing project {}", task.id, task.project_id)));
+        }
+        recurrence::normalize(task.recurrence.as_deref())?;
+        (task.start_at, task.due_at) = schedule::normalize_dates(conn, task.start_at.as_deref(), task.due_at.as_deref())
+            .map_err(|e| in_row("task", task.id, e))?;
+        normalize_timestamps(conn, "task", task.id, [
+            &mut task.created_at, &mut task.updated_at, &mut task.completed_at, &mut task.deleted_at,
+        ])?;
+    }
+    for item in &mut document.task_items {
+        require_text("task item", item.id, &item.content)?;
+        recurrence::normalize(item.recurrence.as_deref())?;
+        (item.start_at, item.due_at) = schedule::normalize_dates(conn, item.start_at.as_deref(), item.due_at.as_deref())
+            .map_err(|e| in_row("task item", item.id, e))?;
+        normalize_timestamps(conn, "task item", item.id, [
+            &mut item.created_at, &mut item.updated_at, &mut item.completed_at, &mut item.deleted_at,
+        ])?;
+    }
+
+    let mut linked = HashSet::new();
+    for link in &document.task_item_links {
+        if !tasks.contains(&link.task_id) || !items.contains(&link.item_id) {
+            return Err(invalid(format!("link of task {} and item {} points at a missing row", link.task_id, link.item_id)));
+        }
+        if !linked.insert((link.task_id, link.item_id)) {
+            return Err(invalid(format!("task {} links item {} twice", link.task_id, link.item_id)));
+        }
+    }
+    // Each task's items form their own tree
+    let mut parents: HashMap<i64, HashMap<i64, Vec<i64>>> = HashMap::new();
+    for link in &document.task_item_links {
+        if let Some(parent_id) = link.parent_id {
+            if !linked.contains(&(link.task_id, parent_id)) {
+                return Err(invalid(format!("item {} is nested under item {}, which task {} doesn't link", link.item_id, parent_id, link.task_id)));
+            }
+            parents.entry(link.task_id).or_default().insert(link.item_id, vec![parent_id]);
+        }
+    }
+    for (task_id, parents) in &parents {
+        if let Some(item_id) = find_cycle(parents) {
+            return Err(invalid(format!("item {} of task {} is nested under itself", item_id, task_id)));
+        }
+    }
+
+    let mut names = HashSet::new();
+    for tag in &document.tags {
+        require_text("tag", tag.id, &tag.name)?;
+        tag::require_color(&tag.color)?;
+        if !names.insert(tag.name.trim().to_lowercase()) {
+            return Err(invalid(format!("tag name {:?} appears twice", tag.name)));
+        }
+    }
+    for tagging in &document.taggings {
+        let target = match tagging.entity {
+            Entity::Project => &projects,
+            Entity::Task => &tasks,
+            Entity::TaskItem => &items,
+            Entity::Tag => return Err(invalid("tags can't be tagged")),
+        };
+        if !tags.contains(&tagging.tag_id) || !target.contains(&tagging.entity_id) {
+            return Err(invalid(format!("tagging of {} {} with tag {} points at a missing row", tagging.entity, tagging.entity_id, tagging.tag_id)));
+        }
+    }
+
+    let mut prerequisites: HashMap<i64, Vec<i64>> = HashMap::new();
+    for dependency in &document.task_dependencies {
+        if !tasks.contains(&dependency.task_id) || !tasks.contains(&dependency.depends_on_id) {
+            return Err(invalid(format!("dependency of task {} on task {} points at a missing task", dependency.task_id, dependency.depends_on_id)));
+        }
+        prerequisites.entry(dependency.task_id).or_default().push(dependency.depends_on_id);
+    }
+    if let Some(task_id) = find_cycle(&prerequisites) {
+        return Err(invalid(format!("task {} depends on itself through its dependencies", task_id)));
+    }
+    Ok(())
+}
+
+// Puts which row a field error came from into it
+fn in_row(name: &str, id: i64, error: AppError) -> AppError {
+    invalid(format!("{} {}: {}", name, id, error))
+}
+
+fn normalize_timestamps<const N: usize>(conn: &Connection, name: &str, id: i64, timestamps: [&mut Option<String>; N]) -> Result<()> {
+    for timestamp in timestamps {
+        *timestamp = schedule::normalize(conn, "timestamp", timestamp.as_deref(), false)
+            .map_err(|e| in_row(name, id, e))?;
+    }
+    Ok(())
+}
+
+fn require_text(name: &str, id: i64, text: &str) -> Result<()> {
+    if text.trim().is_empty() {
+        return Err(invalid(format!("{} {} has no text", name, id)));
+    }
+    Ok(())
+}
+
+// A row on a cycle of `edges` (a task's prerequisites, or an item's parent), if there is one
+fn find_cycle(edges: &HashMap<i64, Vec<i64>>) -> Option<i64> {
+    // 1 while a row's edges are being walked, 2 once they're known to be acyclic
+    fn visit(id: i64, edges: &HashMap<i64, Vec<i64>>, state: &mut HashMap<i64, u8>) -> bool {
+        match state.get(&id) {
+            Some(1) => return true,
+            Some(_) => return false,
+            None => {}
+        }
+        state.insert(id, 1);
+        let cyclic = edges.get(&id).into_iter().flatten().any(|&next| visit(next, edges, state));
+        state.insert(id, 2);
+        cyclic
+    }
+
+    let mut state = HashMap::new();
+    edges.keys().copied().find(|&id| visit(id, edges, &mut state))
+}
+
+fn counts(conn: &Connection) -> Result<Counts> {
+    let count = |table: &str| -> Result<usize> {
+        let count: i64 = conn.prepare_cached(&format!("SELECT COUNT(*) FROM {}", table))?.query_row([], |row| row.get(0))?;
+        Ok(count as usize)
+    };
+    Ok(Counts {
+        projects: count("projects")?,
+        tasks: count("tasks")?,
+        task_items: count("task_items")?,
+        task_item_links: count("task_item_links")?,
+        tags: count("tags")?,
+        taggings: count("taggings")?,
+        task_dependencies: count("task_dependencies")?,
+    })
+}
+
+// The change history goes too, since its rows describe the data being replaced
+fn clear(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "DELETE FROM history;
+         DELETE FROM task_dependencies;
+         DELETE FROM taggings;
+         DELETE FROM tags;
+         DELETE FROM task_item_links;
+         DELETE FROM task_items;
+         DELETE FROM tasks;
+         DELETE FROM projects;"
+    )?;
+    Ok(())
+}
+
+// Inserts the (already checked) document. With `fresh_ids` every row gets a new id and
+// projects go after the existing ones; otherwise the document's ids and ranks are kept.
+fn insert(conn: &Connection, document: &Document, fresh_ids: bool) -> Result<Counts> {
+    let mut imported = Counts::default();
+    let id = |fresh: i64, original: i64| if fresh_ids { fresh } else { original };
+
+    let rank_offset: f64 = if fresh_ids {
+        conn.prepare_cached("SELECT COALESCE(MAX(rank), 0) FROM projects")?.query_row([], |row| row.get(0))?
+    } else {
+        0.0
+    };
+    let mut projects = HashMap::new();
+    for project in &document.projects {
+        conn.prepare_cached(
+            "INSERT INTO projects (id, title, description, rank, created_at, updated_at, completed_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)"
+        )?.execute((
+            (!fresh_ids).then_some(project.id), &project.title, &project.description, rank_offset + project.rank,
+            &project.created_at, &project.updated_at, &project.completed_at, &project.deleted_at,
+        ))?;
+        projects.insert(project.id, id(conn.last_insert_rowid(), project.id));
+        imported.projects += 1;
+    }
+
+    let mut tasks = HashMap::new();
+    for task in &document.tasks {
+        conn.prepare_cached(
+            "INSERT INTO tasks (id, project_id, title, description, rank, start_at, due_at, priority, recurrence,
+                                created_at, updated_at, completed_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)"
+        )?.execute((
+            (!fresh_ids).then_some(task.id), projects[&task.project_id], &task.title, &task.description, task.rank,
+            &task.start_at, &task.due_at, task.priority, recurrence::normalize(task.recurrence.as_deref())?,
+            &task.created_at, &task.updated_at, &task.completed_at, &task.deleted_at,
+        ))?;
+        tasks.insert(task.id, id(conn.last_insert_rowid(), task.id));
+        imported.tasks += 1;
+    }
+
+    let mut items = HashMap::new();
+    for item in &document.task_items {
+        conn.prepare_cached(
+            "INSERT INTO task_items (id, content, status, start_at, due_at, priority, recurrence,
+                                     created_at, updated_at, completed_at, deleted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)"
+        )?.execute((
+            (!fresh_ids).then_some(item.id), &item.content, item.status, &item.start_at, &item.due_at, item.priority,
+            recurrence::normalize(item.recurrence.as_deref())?,
+            &item.created_at, &item.updated_at, &item.completed_at, &item.deleted_at,
+        ))?;
+        items.insert(item.id, id(conn.last_insert_rowid(), item.id));
+        imported.task_items += 1;
+    }
+
+    // Occurrences can point anywhere in the document, so they're filled in once every row has its id
+    for task in &document.tasks {
+        if let Some(recurred_id) = task.recurred_id.and_then(|recurred_id| tasks.get(&recurred_id)) {
+            conn.prepare_cached("UPDATE tasks SET recurred_id = ?1 WHERE id = ?2")?
+                .execute((recurred_id, tasks[&task.id]))?;
+        }
+    }
+    for item in &document.task_items {
+        if let Some(recurred_id) = item.recurred_id.and_then(|recurred_id| items.get(&recurred_id)) {
+            conn.prepare_cached("UPDATE task_items SET recurred_id = ?1 WHERE id = ?2")?
+                .execute((recurred_id, items[&item.id]))?;
+        }
+    }
+
+    for link in &document.task_item_links {
+        conn.prepare_cached("INSERT INTO task_item_links (task_id, item_id, rank, parent_id) VALUES (?1, ?2, ?3, ?4)")?
+            .execute((tasks[&link.task_id], items[&link.item_id], link.rank, link.parent_id.map(|parent_id| items[&parent_id])))?;
+        imported.task_item_links += 1;
+    }
+
+    let mut tags = HashMap::new();
+    for tag in &document.tags {
+        let existing: Option<i64> = if fresh_ids {
+            conn.prepare_cached("SELECT id FROM tags WHERE name = ?1")?
+                .query_row([tag.name.trim()], |row| row.get(0))
+                .optional()?
+        } else {
+            None
+        };
+        let tag_id = match existing {
+            Some(existing) => existing,
+            None => {
+                conn.prepare_cached("INSERT INTO tags (id, name, color) VALUES (?1, ?2, ?3)")?
+                    .execute(((!fresh_ids).then_some(tag.id), tag.name.trim(), &tag.color))?;
+                imported.tags += 1;
+                id(conn.last_insert_rowid(), tag.id)
+            }
+        };
+        tags.insert(tag.id, tag_id);
+    }
+    for tagging in &document.taggings {
+        let entity_id = match tagging.entity {
+            Entity::Project => projects[&tagging.entity_id],
+            Entity::Task => tasks[&tagging.entity_id],
+            _ => items[&tagging.entity_id],
+        };
+        imported.taggings += conn.prepare_cached("INSERT OR IGNORE INTO taggings (tag_id, entity, entity_id) VALUES (?1, ?2, ?3)")?
+            .execute((tags[&tagging.tag_id], tagging.entity, entity_id))?;
+    }
+
+    for dependency in &document.task_dependencies {
+        imported.task_dependencies += conn.prepare_cached("INSERT OR IGNORE INTO task_dependencies (task_id, depends_on_id) VALUES (?1, ?2)")?
+            .execute((tasks[&dependency.task_id], tasks[&dependency.depends_on_id]))?;
+    }
+
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::priority::SortOrder;
+    use crate::journal::Change;
+    use crate::task_item::TaskItemFilter;
+    use crate::{db, dependency, project, task, task_item};
+    use serde_json::{json, Value};
+
+    // A project with two tasks sharing an item, a subtask, a closed item, a tag and a
+    // dependency, next to a trashed project
+    fn fixture(conn: &Connection) {
+        let project_id = project::create_project(conn, "Home", Some("Chores")).unwrap();
+        let tasks = ["Kitchen", "Bathroom"].map(|title| task::create_task(conn, project_id, title, None, None, Some("2026-11-01")).unwrap());
+        let [shared, child, done] = ["Buy soap", "Compare brands", "Wipe counters"]
+            .map(|content| task_item::create_task_item(conn, content, None, None).unwrap());
+        for (task_id, item_id) in [(tasks[0], shared), (tasks[1], shared), (tasks[0], child), (tasks[0], done)] {
+            task_item::link_task_item(conn, task_id, item_id).unwrap();
+        }
+        task_item::nest_task_item(conn, tasks[0], child, Some(shared), None).unwrap();
+        task_item::update_task_item_content(conn, done, "Wipe counters", TaskItemStatus::Completed).unwrap();
+        let tag_id = tag::create_tag(conn, "weekend", None).unwrap();
+        tag::tag_entity(conn, tag_id, Entity::Task, tasks[0]).unwrap();
+        tag::tag_entity(conn, tag_id, Entity::TaskItem, shared).unwrap();
+        dependency::add_dependency(conn, tasks[1], tasks[0]).unwrap();
+        let trashed = project::create_project(conn, "Old", None).unwrap();
+        project::delete_project(conn, trashed).unwrap();
+    }
+
+    fn export(conn: &Connection) -> Value {
+        let mut document: Value = serde_json::from_str(&export_json(conn).unwrap()).unwrap();
+        document["exported_at"] = Value::Null;
+        document
+    }
+
+    fn rejects(conn: &Connection, document: &Value) -> String {
+        match import_json(conn, &document.to_string(), ImportMode::MergeAsNew, false) {
+            Err(AppError::Validation { field: "document", reason }) => reason,
+            other => panic!("expected the document to be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn replace_round_trips_an_export() {
+        let source = db::test_connection();
+        fixture(&source);
+        let document = export(&source);
+        assert!(document["task_item_links"].as_array().unwrap().iter().any(|link| link["rank"].is_null()));
+        assert!(document["task_item_links"].as_array().unwrap().iter().any(|link| !link["parent_id"].is_null()));
+
+        let target = db::test_connection();
+        project::create_project(&target, "Replaced", None).unwrap();
+        let report = import_json(&target, &document.to_string(), ImportMode::Replace, false).unwrap();
+        assert_eq!(report.replaced, Counts { projects: 1, ..Counts::default() });
+        assert_eq!(report.imported, Counts {
+            projects: 2, tasks: 2, task_items: 3, task_item_links: 4, tags: 1, taggings: 2, task_dependencies: 1,
+        });
+        assert_eq!(export(&target), document);
+        let history: i64 = target.query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0)).unwrap();
+        assert_eq!(history, 0);
+        assert_eq!(journal::undo(&target).unwrap(), None);
+    }
+
+    #[test]
+    fn merge_adds_a_copy_under_new_ids() {
+        let conn = db::test_connection();
+        fixture(&conn);
+        let document = export(&conn);
+
+        let report = import_json(&conn, &document.to_string(), ImportMode::MergeAsNew, false).unwrap();
+        assert_eq!(report.imported.tags, 0);
+        assert_eq!(report.imported.taggings, 2);
+        assert_eq!(tag::get_tags(&conn).unwrap().len(), 1);
+
+        let projects = project::get_all_projects(&conn, &[]).unwrap();
+        assert_eq!(projects.iter().map(|project| (project.title.as_str(), project.sequence)).collect::<Vec<_>>(), [("Home", 1), ("Home", 2)]);
+        let tasks = task::get_tasks(&conn, projects[1].id, SortOrder::Sequence, &[]).unwrap();
+        assert!(tasks.iter().all(|task| task.id > 2));
+        assert!(!tasks[0].blocked && tasks[1].blocked);
+        assert_eq!(dependency::get_prerequisites(&conn, tasks[1].id).unwrap()[0].id, tasks[0].id);
+
+        let items = task_item::get_task_items(&conn, tasks[0].id, &TaskItemFilter::default(), SortOrder::Sequence, None, None).unwrap().items;
+        let shape: Vec<(&str, i64, Option<i64>)> = items.iter().map(|item| (item.content.as_str(), item.depth, item.sequence)).collect();
+        assert_eq!(shape, [("Buy soap", 0, Some(1)), ("Compare brands", 1, Some(1)), ("Wipe counters", 0, None)]);
+        assert!(items.iter().all(|item| item.id > 3));
+        assert_eq!(task_item::get_item_links(&conn, items[0].id).unwrap().len(), 2);
+        let weekend = tag::get_tags(&conn).unwrap()[0].id;
+        assert_eq!(task::get_tasks(&conn, projects[1].id, SortOrder::Sequence, &[weekend]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn dry_run_changes_nothing() {
+        let conn = db::test_connection();
+        fixture(&conn);
+        journal::perform(&conn, "Rename project", Change::ProjectContent { id: 1, title: "Flat".to_string(), description: None }).unwrap();
+        let document = export(&conn);
+
+        for mode in [ImportMode::Replace, ImportMode::MergeAsNew] {
+            let report = import_json(&conn, &document.to_string(), mode, true).unwrap();
+            assert!(report.dry_run);
+            assert_eq!(report.imported.task_item_links, 4);
+            assert_eq!(report.replaced.projects, if mode == ImportMode::Replace { 2 } else { 0 });
+            assert_eq!(export(&conn), document);
+        }
+        // The journal is kept too
+        assert_eq!(journal::undo(&conn).unwrap().as_deref(), Some("Rename project"));
+    }
+
+    #[test]
+    fn rejects_inconsistent_documents() {
+        let conn = db::test_connection();
+        fixture(&conn);
+        let document = export(&conn);
+
+        let mut bad = document.clone();
+        bad["version"] = json!(VERSION + 1);
+        assert!(rejects(&conn, &bad).contains("version"));
+
+        let mut bad = document.clone();
+        let duplicate = bad["task_items"][0].clone();
+        bad["task_items"].as_array_mut().unwrap().push(duplicate);
+        assert!(rejects(&conn, &bad).contains("appears twice"));
+
+        let mut bad = document.clone();
+        bad["task_item_links"][0]["item_id"] = json!(99);
+        assert!(rejects(&conn, &bad).contains("missing row"));
+
+        let mut bad = document.clone();
+        let link = bad["task_item_links"].as_array_mut().unwrap().iter_mut().find(|link| !link["parent_id"].is_null()).unwrap();
+        link["parent_id"] = link["item_id"].clone();
+        assert!(rejects(&conn, &bad).contains("nested under itself"));
+
+        let mut bad = document.clone();
+        let dependency = bad["task_dependencies"][0].clone();
+        bad["task_dependencies"].as_array_mut().unwrap().push(json!({
+            "task_id": dependency["depends_on_id"], "depends_on_id": dependency["task_id"],
+        }));
+        assert!(rejects(&conn, &bad).contains("depends on itself"));
+
+        let mut bad = document.clone();
+        bad["tasks"][0]["due_at"] = json!("soon");
+        assert!(rejects(&conn, &bad).contains("due_at"));
+
+        assert_eq!(export(&conn), document);
+    }
+
+    #[test]
+    fn normalises_dates() {
+        let conn = db::test_connection();
+        fixture(&conn);
+        let mut document = export(&conn);
+        document["tasks"][0]["due_at"] = json!("2026-12-01");
+        document["tasks"][0]["start_at"] = json!("2026-11-30 08:00");
+
+        import_json(&conn, &document.to_string(), ImportMode::Replace, false).unwrap();
+        let task = task::get_task(&conn, document["tasks"][0]["id"].as_i64().unwrap()).unwrap();
+        assert_eq!(task.start_at.as_deref(), Some("2026-11-30 08:00:00"));
+        assert_eq!(task.due_at.as_deref(), Some("2026-12-01 23:59:59"));
+    }
+}
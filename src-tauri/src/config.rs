@@ -0,0 +1,51 @@
+use std::env;
+use std::path::PathBuf;
+use tauri::Manager;
+
+pub const DB_FILE_NAME: &str = "yuno-tasks.db";
+pub const DB_PATH_ENV: &str = "YUNO_TASKS_DB";
+pub const DB_PATH_FLAG: &str = "--db";
+
+pub const TRASH_RETENTION_DAYS: u32 = 30;
+pub const TRASH_RETENTION_ENV: &str = "YUNO_TASKS_TRASH_RETENTION_DAYS";
+pub const TRASH_RETENTION_FLAG: &str = "--trash-retention-days";
+
+#[derive(Debug)]
+pub struct Config {
+    pub db_path: PathBuf,
+    // Trashed rows older than this are purged on startup
+    pub trash_retention_days: u32,
+}
+
+impl Config {
+    // Each setting is resolved from its `--flag <value>`, then its env var,
+    // then the default (for the database: yuno-tasks.db in the app data directory)
+    pub fn resolve(app: &tauri::App) -> tauri::Result<Config> {
+        let db_path = match setting(DB_PATH_FLAG, DB_PATH_ENV) {
+            Some(path) => PathBuf::from(path),
+            None => app.path().app_data_dir()?.join(DB_FILE_NAME),
+        };
+
+        let trash_retention_days = match setting(TRASH_RETENTION_FLAG, TRASH_RETENTION_ENV) {
+            // An unreadable number falls back to the default rather than stopping startup
+            Some(days) => days.parse().unwrap_or(TRASH_RETENTION_DAYS),
+            None => TRASH_RETENTION_DAYS,
+        };
+
+        Ok(Config { db_path, trash_retention_days })
+    }
+}
+
+fn setting(flag: &str, env_var: &str) -> Option<String> {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == flag {
+            return args.next();
+        }
+        if let Some(value) = arg.strip_prefix(flag).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+
+    env::var(env_var).ok().filter(|value| !value.is_empty())
+}
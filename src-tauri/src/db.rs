@@ -0,0 +1,59 @@
+use rusqlite::Connection;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::error::Result;
+use crate::migrations::{self, MigrationError};
+
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+// The single connection shared by all commands, registered with `tauri::Builder::manage`.
+// Commands are short, so serialising them through one connection is cheaper than
+// re-opening the file (and re-parsing the schema) for every invoke.
+pub struct Database {
+    conn: Mutex<Connection>,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Database, MigrationError> {
+        let mut conn = Connection::open(path)?;
+
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+
+        migrations::migrate(&mut conn)?;
+
+        // SQLite ignores ON DELETE CASCADE unless this is set on every connection
+        conn.pragma_update(None, "foreign_keys", true)?;
+
+        Ok(Database { conn: Mutex::new(conn) })
+    }
+
+    pub fn conn(&self) -> MutexGuard<'_, Connection> {
+        // A panicking command can't leave the connection half-written: any open
+        // transaction is rolled back when it's dropped, so a poisoned lock is safe to reuse
+        self.conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Runs a mutating command in one transaction, so a failure part way through
+    // (including recording its undo entry) leaves nothing behind
+    pub fn write<T>(&self, f: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let value = f(&tx)?;
+        tx.commit()?;
+        Ok(value)
+    }
+}
+
+// A migrated in-memory database set up like `Database::open`, for unit tests
+#[cfg(test)]
+pub fn test_connection() -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    migrations::migrate(&mut conn).unwrap();
+    conn.pragma_update(None, "foreign_keys", true).unwrap();
+    conn
+}
@@ -0,0 +1,248 @@
+use rusqlite::Connection;
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+
+use crate::entity::Entity;
+use crate::error::{expect_changed, AppError, Result};
+use crate::history::{self, Action};
+use crate::priority::SortOrder;
+use crate::project;
+use crate::task::{self, Task};
+
+// `task_id` can't start until `depends_on_id` is done. A task is blocked while any of
+// its prerequisites is live and not yet completed. Trashed prerequisites don't count,
+// but their edges are kept for a restore and still take part in cycle checks. Nor do
+// prerequisites without live items, which can't complete (see task::refresh_completion)
+// and would otherwise block for good.
+
+pub fn add_dependency(conn: &Connection, task_id: i64, depends_on_id: i64) -> Result<()> {
+    task::get_task(conn, task_id)?;
+    let prerequisite = task::get_task(conn, depends_on_id)?;
+    if task_id == depends_on_id {
+        return Err(AppError::validation("depends_on_id", "a task can't depend on itself"));
+    }
+    if reaches(conn, depends_on_id, task_id)? {
+        return Err(AppError::conflict(format!(
+            "task {} already depends on task {}, the dependency would form a cycle",
+            depends_on_id, task_id
+        )));
+    }
+
+    let changed = conn.prepare_cached("INSERT OR IGNORE INTO task_dependencies (task_id, depends_on_id) VALUES (?1, ?2)")?
+        .execute((task_id, depends_on_id))?;
+    if changed > 0 {
+        history::record(
+            conn, Entity::Task, task_id, Action::Linked, None,
+            Some(json!({ "depends_on_id": depends_on_id, "title": prerequisite.title })),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn remove_dependency(conn: &Connection, task_id: i64, depends_on_id: i64) -> Result<()> {
+    let changed = conn.prepare_cached("DELETE FROM task_dependencies WHERE task_id = ?1 AND depends_on_id = ?2")?
+        .execute((task_id, depends_on_id))?;
+    expect_changed(changed, Entity::Task, task_id)?;
+
+    history::record(conn, Entity::Task, task_id, Action::Unlinked, Some(json!({ "depends_on_id": depends_on_id })), None)
+}
+
+pub fn depends_on(conn: &Connection, task_id: i64, depends_on_id: i64) -> Result<bool> {
+    let exists = conn.prepare_cached("SELECT EXISTS (SELECT 1 FROM task_dependencies WHERE task_id = ?1 AND depends_on_id = ?2)")?
+        .query_row((task_id, depends_on_id), |row| row.get(0))?;
+    Ok(exists)
+}
+
+// Whether `from` depends on `to`, directly or through other tasks
+fn reaches(conn: &Connection, from: i64, to: i64) -> Result<bool> {
+    let reached = conn.prepare_cached(
+        "WITH RECURSIVE prerequisites (id) AS (
+             SELECT depends_on_id FROM task_dependencies WHERE task_id = ?1
+             UNION
+             SELECT task_dependencies.depends_on_id FROM task_dependencies
+             JOIN prerequisites ON task_dependencies.task_id = prerequisites.id
+         )
+         SELECT EXISTS (SELECT 1 FROM prerequisites WHERE id = ?2)"
+    )?.query_row((from, to), |row| row.get(0))?;
+    Ok(reached)
+}
+
+// The live tasks this one waits on, done or not, in any project
+pub fn get_prerequisites(conn: &Connection, task_id: i64) -> Result<Vec<Task>> {
+    task::get_task(conn, task_id)?;
+    related(conn, "tasks.id IN (SELECT depends_on_id FROM task_dependencies WHERE task_id = ?1)", task_id)
+}
+
+// The live tasks waiting on this one
+pub fn get_dependents(conn: &Connection, task_id: i64) -> Result<Vec<Task>> {
+    task::get_task(conn, task_id)?;
+    related(conn, "tasks.id IN (SELECT task_id FROM task_dependencies WHERE depends_on_id = ?1)", task_id)
+}
+
+fn related(conn: &Connection, condition: &str, task_id: i64) -> Result<Vec<Task>> {
+    let tasks = conn.prepare_cached(&format!(
+        "SELECT {}
+         FROM tasks
+         JOIN projects ON projects.id = tasks.project_id
+         WHERE {} AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL
+         ORDER BY projects.rank, tasks.rank, tasks.id",
+        Task::COLUMNS, condition
+    ))?
+        .query_map([task_id], Task::from_row)?
+        .collect::<rusqlite::Result<Vec<Task>>>()?;
+    Ok(tasks)
+}
+
+// The project's unfinished tasks in an order they can be worked through: every task
+// after its prerequisites in the project, otherwise in the project's own order. The
+// next actionable task is the first one that isn't `blocked`; prerequisites in other
+// projects keep a task blocked without moving it.
+pub fn get_next_actionable(conn: &Connection, project_id: i64) -> Result<Vec<Task>> {
+    project::get_project(conn, project_id)?;
+    let tasks: Vec<Task> = task::get_tasks(conn, project_id, SortOrder::Sequence, &[])?
+        .into_iter()
+        .filter(|task| task.completed_at.is_none())
+        .collect();
+    let index: HashMap<i64, usize> = tasks.iter().enumerate().map(|(index, task)| (task.id, index)).collect();
+
+    let edges = conn.prepare_cached(
+        "SELECT task_dependencies.task_id, task_dependencies.depends_on_id
+         FROM task_dependencies
+         JOIN tasks ON tasks.id = task_dependencies.task_id
+         WHERE tasks.project_id = ?1"
+    )?
+        .query_map([project_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
+        .collect::<rusqlite::Result<Vec<(i64, i64)>>>()?;
+
+    let mut waiting_on = vec![0; tasks.len()];
+    let mut dependents = vec![Vec::new(); tasks.len()];
+    for (task_id, depends_on_id) in edges {
+        if let (Some(&dependent), Some(&prerequisite)) = (index.get(&task_id), index.get(&depends_on_id)) {
+            waiting_on[dependent] += 1;
+            dependents[prerequisite].push(dependent);
+        }
+    }
+
+    // Kahn's algorithm, always taking the earliest ready task in the project's order
+    let mut ready: BTreeSet<usize> = (0..tasks.len()).filter(|&index| waiting_on[index] == 0).collect();
+    let mut order = Vec::with_capacity(tasks.len());
+    while let Some(next) = ready.pop_first() {
+        order.push(next);
+        for &dependent in &dependents[next] {
+            waiting_on[dependent] -= 1;
+            if waiting_on[dependent] == 0 {
+                ready.insert(dependent);
+            }
+        }
+    }
+    // Cycles are rejected when dependencies are added; should one exist anyway its
+    // tasks still come back, after everything else
+    if order.len() < tasks.len() {
+        let placed: BTreeSet<usize> = order.iter().copied().collect();
+        order.extend((0..tasks.len()).filter(|index| !placed.contains(index)));
+    }
+
+    let mut tasks: Vec<Option<Task>> = tasks.into_iter().map(Some).collect();
+    Ok(order.into_iter().filter_map(|index| tasks[index].take()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::task_item::{self, TaskItemStatus};
+
+    // A task with one pending item, so it isn't done until the item is
+    fn task_with_item(conn: &Connection, project_id: i64, title: &str) -> (i64, i64) {
+        let task_id = task::create_task(conn, project_id, title, None, None, None).unwrap();
+        let item_id = task_item::create_task_item(conn, title, None, None).unwrap();
+        task_item::link_task_item(conn, task_id, item_id).unwrap();
+        (task_id, item_id)
+    }
+
+    fn blocked(conn: &Connection, task_id: i64) -> bool {
+        task::get_task(conn, task_id).unwrap().blocked
+    }
+
+    fn titles(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(|task| task.title.as_str()).collect()
+    }
+
+    #[test]
+    fn rejects_self_dependencies_and_cycles() {
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "Project", None).unwrap();
+        let [a, b, c] = ["A", "B", "C"].map(|title| task::create_task(&conn, project_id, title, None, None, None).unwrap());
+
+        assert!(matches!(add_dependency(&conn, a, a), Err(AppError::Validation { field: "depends_on_id", .. })));
+        add_dependency(&conn, b, a).unwrap();
+        add_dependency(&conn, c, b).unwrap();
+        assert!(matches!(add_dependency(&conn, a, c), Err(AppError::Conflict { .. })));
+        assert!(matches!(add_dependency(&conn, a, b), Err(AppError::Conflict { .. })));
+        assert!(matches!(add_dependency(&conn, a, 99), Err(AppError::NotFound { .. })));
+
+        // Adding an edge twice is a no-op, removing a missing one isn't
+        add_dependency(&conn, c, b).unwrap();
+        assert_eq!(titles(&get_prerequisites(&conn, c).unwrap()), ["B"]);
+        assert_eq!(titles(&get_dependents(&conn, a).unwrap()), ["B"]);
+        remove_dependency(&conn, c, b).unwrap();
+        assert!(matches!(remove_dependency(&conn, c, b), Err(AppError::NotFound { .. })));
+    }
+
+    #[test]
+    fn blocked_while_a_live_prerequisite_is_unfinished() {
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "Project", None).unwrap();
+        let (first, item_id) = task_with_item(&conn, project_id, "First");
+        let (second, _) = task_with_item(&conn, project_id, "Second");
+        add_dependency(&conn, second, first).unwrap();
+        assert!(blocked(&conn, second));
+        assert!(!blocked(&conn, first));
+
+        task_item::update_task_item_content(&conn, item_id, "First", TaskItemStatus::Completed).unwrap();
+        assert!(!blocked(&conn, second));
+        task_item::update_task_item_content(&conn, item_id, "First", TaskItemStatus::Pending).unwrap();
+        assert!(blocked(&conn, second));
+
+        task::delete_task(&conn, first).unwrap();
+        assert!(!blocked(&conn, second));
+        task::restore_task(&conn, first).unwrap();
+        assert!(blocked(&conn, second));
+    }
+
+    #[test]
+    fn prerequisites_without_items_dont_block() {
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "Project", None).unwrap();
+        let empty = task::create_task(&conn, project_id, "Empty", None, None, None).unwrap();
+        let (second, _) = task_with_item(&conn, project_id, "Second");
+        add_dependency(&conn, second, empty).unwrap();
+        assert!(!blocked(&conn, second));
+
+        let item_id = task_item::create_task_item(&conn, "Now it has one", None, None).unwrap();
+        task_item::link_task_item(&conn, empty, item_id).unwrap();
+        assert!(blocked(&conn, second));
+        task_item::delete_task_item(&conn, item_id).unwrap();
+        assert!(!blocked(&conn, second));
+    }
+
+    #[test]
+    fn next_actionable_puts_prerequisites_first() {
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "Project", None).unwrap();
+        let other_project = project::create_project(&conn, "Other", None).unwrap();
+        let [one, two, three, four, done] = ["One", "Two", "Three", "Four", "Done"].map(|title| task_with_item(&conn, project_id, title));
+        let (elsewhere, _) = task_with_item(&conn, other_project, "Elsewhere");
+        task_item::update_task_item_content(&conn, done.1, "Done", TaskItemStatus::Completed).unwrap();
+        add_dependency(&conn, one.0, three.0).unwrap();
+        add_dependency(&conn, two.0, one.0).unwrap();
+        add_dependency(&conn, two.0, done.0).unwrap();
+        add_dependency(&conn, four.0, elsewhere).unwrap();
+
+        let tasks = get_next_actionable(&conn, project_id).unwrap();
+        assert_eq!(titles(&tasks), ["Three", "One", "Two", "Four"]);
+        let blocked: Vec<bool> = tasks.iter().map(|task| task.blocked).collect();
+        assert_eq!(blocked, [false, true, true, true]);
+        assert!(matches!(get_next_actionable(&conn, 999), Err(AppError::NotFound { entity: Entity::Project, id: 999 })));
+    }
+}
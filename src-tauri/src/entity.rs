@@ -0,0 +1,58 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Serialize, Deserialize};
+use std::fmt;
+
+// The kinds of rows the frontend can address by id
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Entity {
+    Project,
+    Task,
+    TaskItem,
+    Tag,
+}
+
+impl Entity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Entity::Project => "project",
+            Entity::Task => "task",
+            Entity::TaskItem => "task_item",
+            Entity::Tag => "tag",
+        }
+    }
+
+    pub fn table(self) -> &'static str {
+        match self {
+            Entity::Project => "projects",
+            Entity::Task => "tasks",
+            Entity::TaskItem => "task_items",
+            Entity::Tag => "tags",
+        }
+    }
+}
+
+impl fmt::Display for Entity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Stored as the same snake_case name it has over IPC
+impl FromSql for Entity {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "project" => Ok(Entity::Project),
+            "task" => Ok(Entity::Task),
+            "task_item" => Ok(Entity::TaskItem),
+            "tag" => Ok(Entity::Tag),
+            other => Err(FromSqlError::Other(format!("unknown entity {:?}", other).into())),
+        }
+    }
+}
+
+impl ToSql for Entity {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
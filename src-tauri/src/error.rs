@@ -0,0 +1,98 @@
+use rusqlite::ErrorCode;
+use serde::Serialize;
+use std::fmt;
+
+use crate::entity::Entity;
+use crate::migrations::MigrationError;
+
+pub type Result<T, E = AppError> = std::result::Result<T, E>;
+
+// Returned by every command. Serialised with a `kind` tag so the frontend can
+// react per kind, e.g. `{ "kind": "not_found", "entity": "task", "id": 4 }`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum AppError {
+    NotFound { entity: Entity, id: i64 },
+    Validation { field: &'static str, reason: String },
+    // The request is well formed but clashes with data already stored
+    Conflict { reason: String },
+    // Another connection holds the database lock past the busy timeout
+    Busy,
+    Storage { message: String },
+}
+
+impl AppError {
+    pub fn validation(field: &'static str, reason: impl Into<String>) -> Self {
+        AppError::Validation { field, reason: reason.into() }
+    }
+
+    pub fn conflict(reason: impl Into<String>) -> Self {
+        AppError::Conflict { reason: reason.into() }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound { entity, id } => write!(f, "{} {} not found", entity, id),
+            AppError::Validation { field, reason } => write!(f, "invalid {}: {}", field, reason),
+            AppError::Conflict { reason } => write!(f, "conflict: {}", reason),
+            AppError::Busy => write!(f, "database is busy, try again"),
+            AppError::Storage { message } => write!(f, "storage error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy) | Some(ErrorCode::DatabaseLocked) => AppError::Busy,
+            Some(ErrorCode::ConstraintViolation) => AppError::Conflict { reason: e.to_string() },
+            _ => AppError::Storage { message: e.to_string() },
+        }
+    }
+}
+
+// Only raised writing CSV into memory, reading reports errors per row
+impl From<csv::Error> for AppError {
+    fn from(e: csv::Error) -> Self {
+        AppError::Storage { message: e.to_string() }
+    }
+}
+
+impl From<MigrationError> for AppError {
+    fn from(e: MigrationError) -> Self {
+        AppError::Storage { message: e.to_string() }
+    }
+}
+
+// Turns a missing row into NotFound for the entity that was asked for
+pub trait OrNotFound<T> {
+    fn or_not_found(self, entity: Entity, id: i64) -> Result<T>;
+}
+
+impl<T> OrNotFound<T> for rusqlite::Result<T> {
+    fn or_not_found(self, entity: Entity, id: i64) -> Result<T> {
+        match self {
+            Err(rusqlite::Error::QueryReturnedNoRows) => Err(AppError::NotFound { entity, id }),
+            other => Ok(other?),
+        }
+    }
+}
+
+// For UPDATE/DELETE statements addressed by id, where no changed rows means no such row
+pub fn expect_changed(changed: usize, entity: Entity, id: i64) -> Result<()> {
+    if changed == 0 {
+        return Err(AppError::NotFound { entity, id });
+    }
+    Ok(())
+}
+
+pub fn require_text(field: &'static str, value: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        return Err(AppError::validation(field, "must not be empty"));
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::entity::Entity;
+use crate::error::Result;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Created,
+    Edited,
+    Moved,
+    Trashed,
+    Restored,
+    Linked,
+    Unlinked,
+    Tagged,
+    Untagged,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Created => "created",
+            Action::Edited => "edited",
+            Action::Moved => "moved",
+            Action::Trashed => "trashed",
+            Action::Restored => "restored",
+            Action::Linked => "linked",
+            Action::Unlinked => "unlinked",
+            Action::Tagged => "tagged",
+            Action::Untagged => "untagged",
+        }
+    }
+}
+
+impl FromSql for Action {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "created" => Ok(Action::Created),
+            "edited" => Ok(Action::Edited),
+            "moved" => Ok(Action::Moved),
+            "trashed" => Ok(Action::Trashed),
+            "restored" => Ok(Action::Restored),
+            "linked" => Ok(Action::Linked),
+            "unlinked" => Ok(Action::Unlinked),
+            "tagged" => Ok(Action::Tagged),
+            "untagged" => Ok(Action::Untagged),
+            other => Err(FromSqlError::Other(format!("unknown history action {:?}", other).into())),
+        }
+    }
+}
+
+impl ToSql for Action {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+// One mutation of one row. The values are JSON objects holding only the fields
+// the action touched, e.g. `{ "sequence": 3 }` for a move.
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub entity: Entity,
+    pub entity_id: i64,
+    pub action: Action,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    pub changed_at: String
+}
+
+// Append-only: entries are never updated, and outlive the rows they describe
+pub fn record(conn: &Connection, entity: Entity, id: i64, action: Action, old_value: Option<Value>, new_value: Option<Value>) -> Result<()> {
+    conn.prepare_cached("INSERT INTO history (entity, entity_id, action, old_value, new_value) VALUES (?1, ?2, ?3, ?4, ?5)")?
+        .execute((entity, id, action, old_value, new_value))?;
+    Ok(())
+}
+
+// Oldest first
+pub fn get_history(conn: &Connection, entity: Entity, id: i64) -> Result<Vec<HistoryEntry>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT id, entity, entity_id, action, old_value, new_value, changed_at
+         FROM history
+         WHERE entity = ?1 AND entity_id = ?2
+         ORDER BY id"
+    )?;
+
+    let entries = stmt
+        .query_map((entity, id), |row| {
+            Ok(HistoryEntry {
+                id: row.get(0)?,
+                entity: row.get(1)?,
+                entity_id: row.get(2)?,
+                action: row.get(3)?,
+                old_value: row.get(4)?,
+                new_value: row.get(5)?,
+                changed_at: row.get(6)?
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<HistoryEntry>>>()?;
+
+    Ok(entries)
+}
@@ -0,0 +1,663 @@
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::dependency;
+use crate::entity::Entity;
+use crate::error::{AppError, Result};
+use crate::priority::Priority;
+use crate::project;
+use crate::rank::Placement;
+use crate::tag::{self, Tag, Tagging};
+use crate::task;
+use crate::task_item::{self, TaskItemStatus};
+
+// Oldest undo entries beyond this are dropped
+pub const JOURNAL_LIMIT: i64 = 100;
+
+// Where an open item sits in one of its tasks, so undoing a close or a delete
+// puts it back in place rather than at the end
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkPosition {
+    pub task_id: i64,
+    pub sequence: i64
+}
+
+// One mutation as stored in the journal (as JSON, so variants may be added but
+// existing ones must keep their shape). Applying a change returns its inverse,
+// read from the database just before the change is made.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    ProjectContent { id: i64, title: String, description: Option<String> },
+    ProjectSequence { id: i64, sequence: i64 },
+    TrashProject { id: i64 },
+    RestoreProject { id: i64, sequence: i64 },
+    TaskContent { id: i64, title: String, description: Option<String> },
+    TaskSequence { id: i64, sequence: i64 },
+    TrashTask { id: i64 },
+    RestoreTask { id: i64, sequence: i64 },
+    TaskSchedule { id: i64, start_at: Option<String>, due_at: Option<String> },
+    TaskPriority { id: i64, priority: Option<Priority> },
+    TaskRecurrence { id: i64, recurrence: Option<String> },
+    TaskProject { id: i64, project_id: i64, sequence: i64 },
+    AddDependency { task_id: i64, depends_on_id: i64 },
+    RemoveDependency { task_id: i64, depends_on_id: i64 },
+    TaskItemContent { id: i64, content: String, status: TaskItemStatus, positions: Vec<LinkPosition> },
+    TaskItemSequence { task_id: i64, id: i64, sequence: i64 },
+    TaskItemParent { task_id: i64, id: i64, parent_id: Option<i64>, sequence: Option<i64> },
+    TrashTaskItem { id: i64 },
+    RestoreTaskItem { id: i64, positions: Vec<LinkPosition> },
+    TaskItemSchedule { id: i64, start_at: Option<String>, due_at: Option<String> },
+    TaskItemPriority { id: i64, priority: Option<Priority> },
+    TaskItemRecurrence { id: i64, recurrence: Option<String> },
+    Link { task_id: i64, item_id: i64, sequence: Option<i64> },
+    Unlink { task_id: i64, item_id: i64 },
+    TagContent { id: i64, name: String, color: String },
+    DeleteTag { id: i64 },
+    RestoreTag { tag: Tag, taggings: Vec<Tagging> },
+    MergeTags { source_id: i64, target_id: i64 },
+    // Takes back the taggings a merge `added` to the target and recreates the source
+    UnmergeTags { source: Tag, taggings: Vec<Tagging>, target_id: i64, added: Vec<Tagging> },
+    TagEntity { tag_id: i64, tagging: Tagging },
+    UntagEntity { tag_id: i64, tagging: Tagging },
+    // Several changes made, and undone, as one entry
+    Batch { changes: Vec<Change> },
+}
+
+impl Change {
+    // The sequence change that puts the row back where it is now, for undoing a move
+    // made some other way. Items are ordered per task, so they need a `task_id`.
+    pub fn current_position(conn: &Connection, entity: Entity, id: i64, task_id: Option<i64>) -> Result<Change> {
+        match entity {
+            Entity::Project => Ok(Change::ProjectSequence { id, sequence: project::get_project(conn, id)?.sequence }),
+            Entity::Task => Ok(Change::TaskSequence { id, sequence: task::get_task(conn, id)?.sequence }),
+            Entity::TaskItem => {
+                let task_id = task_id.ok_or(AppError::validation("task_id", "is required to move a task item"))?;
+                let sequence = task_item::link_sequence(conn, task_id, id)?
+                    .flatten()
+                    .ok_or(AppError::NotFound { entity: Entity::TaskItem, id })?;
+                Ok(Change::TaskItemSequence { task_id, id, sequence })
+            }
+            Entity::Tag => Err(AppError::validation("entity", "tags are ordered by name")),
+        }
+    }
+
+    // The change that takes the database from after `self` back to how it is now
+    pub fn inverse(&self, conn: &Connection) -> Result<Change> {
+        let inverse = match *self {
+            Change::ProjectContent { id, .. } => {
+                let project = project::get_project(conn, id)?;
+                Change::ProjectContent { id, title: project.title, description: project.description }
+            }
+            Change::ProjectSequence { id, .. } => Change::current_position(conn, Entity::Project, id, None)?,
+            Change::TrashProject { id } => {
+                Change::RestoreProject { id, sequence: project::get_project(conn, id)?.sequence }
+            }
+            Change::RestoreProject { id, .. } => Change::TrashProject { id },
+            Change::TaskContent { id, .. } => {
+                let task = task::get_task(conn, id)?;
+                Change::TaskContent { id, title: task.title, description: task.description }
+            }
+            Change::TaskSequence { id, .. } => Change::current_position(conn, Entity::Task, id, None)?,
+            Change::TrashTask { id } => {
+                Change::RestoreTask { id, sequence: task::get_task(conn, id)?.sequence }
+            }
+            Change::RestoreTask { id, .. } => Change::TrashTask { id },
+            Change::TaskSchedule { id, .. } => {
+                let task = task::get_task(conn, id)?;
+                Change::TaskSchedule { id, start_at: task.start_at, due_at: task.due_at }
+            }
+            Change::TaskPriority { id, .. } => Change::TaskPriority { id, priority: task::get_task(conn, id)?.priority },
+            Change::TaskRecurrence { id, .. } => Change::TaskRecurrence { id, recurrence: task::get_task(conn, id)?.recurrence },
+            Change::TaskProject { id, .. } => {
+                let task = task::get_task(conn, id)?;
+                Change::TaskProject { id, project_id: task.project_id, sequence: task.sequence }
+            }
+            Change::AddDependency { task_id, depends_on_id } => {
+                if dependency::depends_on(conn, task_id, depends_on_id)? {
+                    Change::AddDependency { task_id, depends_on_id }
+                } else {
+                    Change::RemoveDependency { task_id, depends_on_id }
+                }
+            }
+            Change::RemoveDependency { task_id, depends_on_id } => Change::AddDependency { task_id, depends_on_id },
+            Change::TaskItemContent { id, .. } => {
+                let item = task_item::get_task_item(conn, id)?;
+                Change::TaskItemContent { id, content: item.content, status: item.status, positions: positions(conn, id)? }
+            }
+            Change::TaskItemSequence { task_id, id, .. } => Change::current_position(conn, Entity::TaskItem, id, Some(task_id))?,
+            Change::TaskItemParent { task_id, id, .. } => {
+                let (parent_id, _) = task_item::link_place(conn, task_id, id)?;
+                let sequence = task_item::link_sequence(conn, task_id, id)?.flatten();
+                Change::TaskItemParent { task_id, id, parent_id, sequence }
+            }
+            Change::TrashTaskItem { id } => {
+                task_item::get_task_item(conn, id)?;
+                Change::RestoreTaskItem { id, positions: positions(conn, id)? }
+            }
+            Change::RestoreTaskItem { id, .. } => Change::TrashTaskItem { id },
+            Change::TaskItemSchedule { id, .. } => {
+                let item = task_item::get_task_item(conn, id)?;
+                Change::TaskItemSchedule { id, start_at: item.start_at, due_at: item.due_at }
+            }
+            Change::TaskItemPriority { id, .. } => {
+                Change::TaskItemPriority { id, priority: task_item::get_task_item(conn, id)?.priority }
+            }
+            Change::TaskItemRecurrence { id, .. } => {
+                Change::TaskItemRecurrence { id, recurrence: task_item::get_task_item(conn, id)?.recurrence }
+            }
+            Change::Link { task_id, item_id, .. } => match task_item::link_sequence(conn, task_id, item_id)? {
+                Some(sequence) => Change::Link { task_id, item_id, sequence },
+                None => Change::Unlink { task_id, item_id },
+            },
+            // Linking puts the item back at the top level, then it goes back under its
+            // parent and takes back the subtasks that moved up when it was unlinked
+            Change::Unlink { task_id, item_id } => {
+                let (parent_id, _) = task_item::link_place(conn, task_id, item_id)?;
+                let sequence = task_item::link_sequence(conn, task_id, item_id)?.flatten();
+                let mut changes = vec![Change::Link { task_id, item_id, sequence: sequence.filter(|_| parent_id.is_none()) }];
+                if parent_id.is_some() {
+                    changes.push(Change::TaskItemParent { task_id, id: item_id, parent_id, sequence });
+                }
+                changes.extend(task_item::get_subtasks(conn, task_id, item_id)?.into_iter().map(|(id, sequence)| {
+                    Change::TaskItemParent { task_id, id, parent_id: Some(item_id), sequence }
+                }));
+                if changes.len() == 1 { changes.remove(0) } else { Change::Batch { changes } }
+            }
+            Change::TagContent { id, .. } => {
+                let tag = tag::get_tag(conn, id)?;
+                Change::TagContent { id, name: tag.name, color: tag.color }
+            }
+            Change::DeleteTag { id } => Change::RestoreTag { tag: tag::get_tag(conn, id)?, taggings: tag::get_taggings(conn, id)? },
+            Change::RestoreTag { ref tag, .. } => Change::DeleteTag { id: tag.id },
+            Change::MergeTags { source_id, target_id } => {
+                let existing = tag::get_taggings(conn, target_id)?;
+                let taggings = tag::get_taggings(conn, source_id)?;
+                let added = taggings.iter().filter(|tagging| !existing.contains(tagging)).copied().collect();
+                Change::UnmergeTags { source: tag::get_tag(conn, source_id)?, taggings, target_id, added }
+            }
+            Change::UnmergeTags { ref source, target_id, .. } => Change::MergeTags { source_id: source.id, target_id },
+            Change::TagEntity { tag_id, tagging } => {
+                if tag::get_taggings(conn, tag_id)?.contains(&tagging) {
+                    Change::TagEntity { tag_id, tagging }
+                } else {
+                    Change::UntagEntity { tag_id, tagging }
+                }
+            }
+            Change::UntagEntity { tag_id, tagging } => Change::TagEntity { tag_id, tagging },
+            // Later changes may depend on earlier ones, so the batch is tried and rolled back
+            Change::Batch { .. } => {
+                conn.execute_batch("SAVEPOINT batch_inverse")?;
+                let inverse = self.apply(conn);
+                conn.execute_batch("ROLLBACK TO batch_inverse; RELEASE batch_inverse")?;
+                inverse?
+            }
+        };
+
+        Ok(inverse)
+    }
+
+    pub fn apply(&self, conn: &Connection) -> Result<Change> {
+        // Each change's inverse is read just before it's applied, and they're undone newest first
+        if let Change::Batch { changes } = self {
+            let mut inverses = changes.iter().map(|change| change.apply(conn)).collect::<Result<Vec<Change>>>()?;
+            inverses.reverse();
+            return Ok(Change::Batch { changes: inverses });
+        }
+        let inverse = self.inverse(conn)?;
+
+        match self {
+            Change::ProjectContent { id, title, description } => {
+                project::update_project_content(conn, *id, title, description.as_deref())?
+            }
+            Change::ProjectSequence { id, sequence } => project::move_project(conn, *id, Placement::Position(*sequence))?,
+            Change::TrashProject { id } => {
+                project::delete_project(conn, *id)?;
+            }
+            Change::RestoreProject { id, sequence } => {
+                project::restore_project(conn, *id)?;
+                project::move_project(conn, *id, Placement::Position(*sequence))?;
+            }
+            Change::TaskContent { id, title, description } => {
+                task::update_task_content(conn, *id, title, description.as_deref())?
+            }
+            Change::TaskSequence { id, sequence } => task::move_task(conn, *id, Placement::Position(*sequence))?,
+            Change::TrashTask { id } => task::delete_task(conn, *id)?,
+            Change::RestoreTask { id, sequence } => {
+                task::restore_task(conn, *id)?;
+                task::move_task(conn, *id, Placement::Position(*sequence))?;
+            }
+            Change::TaskSchedule { id, start_at, due_at } => {
+                task::update_task_schedule(conn, *id, start_at.as_deref(), due_at.as_deref())?
+            }
+            Change::TaskPriority { id, priority } => task::update_task_priority(conn, *id, *priority)?,
+            Change::TaskRecurrence { id, recurrence } => task::update_task_recurrence(conn, *id, recurrence.as_deref())?,
+            Change::TaskProject { id, project_id, sequence } => {
+                task::move_to_project(conn, *id, *project_id, Some(*sequence))?
+            }
+            Change::AddDependency { task_id, depends_on_id } => dependency::add_dependency(conn, *task_id, *depends_on_id)?,
+            Change::RemoveDependency { task_id, depends_on_id } => dependency::remove_dependency(conn, *task_id, *depends_on_id)?,
+            Change::TaskItemContent { id, content, status, positions } => {
+                task_item::update_task_item_content(conn, *id, content, *status)?;
+                if status.is_open() {
+                    place(conn, *id, positions)?;
+                }
+            }
+            Change::TaskItemSequence { task_id, id, sequence } => {
+                task_item::move_task_item(conn, *task_id, *id, Placement::Position(*sequence))?
+            }
+            Change::TaskItemParent { task_id, id, parent_id, sequence } => {
+                task_item::nest_task_item(conn, *task_id, *id, *parent_id, *sequence)?
+            }
+            Change::TrashTaskItem { id } => task_item::delete_task_item(conn, *id)?,
+            Change::RestoreTaskItem { id, positions } => {
+                task_item::restore_task_item(conn, *id)?;
+                place(conn, *id, positions)?;
+            }
+            Change::TaskItemSchedule { id, start_at, due_at } => {
+                task_item::update_task_item_schedule(conn, *id, start_at.as_deref(), due_at.as_deref())?
+            }
+            Change::TaskItemPriority { id, priority } => task_item::update_task_item_priority(conn, *id, *priority)?,
+            Change::TaskItemRecurrence { id, recurrence } => {
+                task_item::update_task_item_recurrence(conn, *id, recurrence.as_deref())?
+            }
+            Change::Link { task_id, item_id, sequence } => {
+                task_item::link_task_item(conn, *task_id, *item_id)?;
+                if let Some(sequence) = sequence {
+                    task_item::move_task_item(conn, *task_id, *item_id, Placement::Position(*sequence))?;
+                }
+            }
+            Change::Unlink { task_id, item_id } => task_item::unlink_task_item(conn, *task_id, *item_id)?,
+            Change::TagContent { id, name, color } => tag::update_tag(conn, *id, name, color)?,
+            Change::DeleteTag { id } => tag::delete_tag(conn, *id)?,
+            Change::RestoreTag { tag, taggings } => tag::restore_tag(conn, tag, taggings)?,
+            Change::MergeTags { source_id, target_id } => tag::merge_tags(conn, *source_id, *target_id)?,
+            Change::UnmergeTags { source, taggings, target_id, added } => {
+                for tagging in added {
+                    tag::untag_entity(conn, *target_id, tagging.entity, tagging.entity_id)?;
+                }
+                tag::restore_tag(conn, source, taggings)?;
+            }
+            Change::TagEntity { tag_id, tagging } => tag::tag_entity(conn, *tag_id, tagging.entity, tagging.entity_id)?,
+            Change::UntagEntity { tag_id, tagging } => tag::untag_entity(conn, *tag_id, tagging.entity, tagging.entity_id)?,
+            // Applied change by change above
+            Change::Batch { .. } => {}
+        }
+
+        Ok(inverse)
+    }
+
+    // The projects, tasks and items the change refers to. Tags aren't listed: they have
+    // no trash, and a deleted tag's entry carries the whole tag.
+    fn rows(&self) -> Vec<(Entity, i64)> {
+        match self {
+            Change::ProjectContent { id, .. }
+            | Change::ProjectSequence { id, .. }
+            | Change::TrashProject { id }
+            | Change::RestoreProject { id, .. } => vec![(Entity::Project, *id)],
+            Change::TaskContent { id, .. }
+            | Change::TaskSequence { id, .. }
+            | Change::TrashTask { id }
+            | Change::RestoreTask { id, .. }
+            | Change::TaskSchedule { id, .. }
+            | Change::TaskPriority { id, .. }
+            | Change::TaskRecurrence { id, .. } => vec![(Entity::Task, *id)],
+            Change::TaskProject { id, project_id, .. } => vec![(Entity::Task, *id), (Entity::Project, *project_id)],
+            Change::AddDependency { task_id, depends_on_id } | Change::RemoveDependency { task_id, depends_on_id } => {
+                vec![(Entity::Task, *task_id), (Entity::Task, *depends_on_id)]
+            }
+            Change::TaskItemContent { id, positions, .. } | Change::RestoreTaskItem { id, positions } => {
+                let mut rows = vec![(Entity::TaskItem, *id)];
+                rows.extend(positions.iter().map(|position| (Entity::Task, position.task_id)));
+                rows
+            }
+            Change::TrashTaskItem { id }
+            | Change::TaskItemSchedule { id, .. }
+            | Change::TaskItemPriority { id, .. }
+            | Change::TaskItemRecurrence { id, .. } => vec![(Entity::TaskItem, *id)],
+            Change::TaskItemSequence { task_id, id, .. } => vec![(Entity::Task, *task_id), (Entity::TaskItem, *id)],
+            Change::TaskItemParent { task_id, id, parent_id, .. } => {
+                let mut rows = vec![(Entity::Task, *task_id), (Entity::TaskItem, *id)];
+                rows.extend(parent_id.map(|parent_id| (Entity::TaskItem, parent_id)));
+                rows
+            }
+            Change::Link { task_id, item_id, .. } | Change::Unlink { task_id, item_id } => {
+                vec![(Entity::Task, *task_id), (Entity::TaskItem, *item_id)]
+            }
+            Change::TagContent { .. } | Change::DeleteTag { .. } | Change::MergeTags { .. } => Vec::new(),
+            Change::RestoreTag { taggings, .. } => taggings.iter().map(|tagging| (tagging.entity, tagging.entity_id)).collect(),
+            Change::UnmergeTags { taggings, added, .. } => {
+                taggings.iter().chain(added).map(|tagging| (tagging.entity, tagging.entity_id)).collect()
+            }
+            Change::TagEntity { tagging, .. } | Change::UntagEntity { tagging, .. } => vec![(tagging.entity, tagging.entity_id)],
+            Change::Batch { changes } => changes.iter().flat_map(Change::rows).collect(),
+        }
+    }
+}
+
+fn positions(conn: &Connection, item_id: i64) -> Result<Vec<LinkPosition>> {
+    let positions = task_item::get_item_links(conn, item_id)?
+        .into_iter()
+        .filter_map(|link| link.sequence.map(|sequence| LinkPosition { task_id: link.task_id, sequence }))
+        .collect();
+    Ok(positions)
+}
+
+fn place(conn: &Connection, item_id: i64, positions: &[LinkPosition]) -> Result<()> {
+    for position in positions {
+        task_item::move_task_item(conn, position.task_id, item_id, Placement::Position(position.sequence))?;
+    }
+    Ok(())
+}
+
+// The undo entry of an import: everything it created goes to the trash, items first,
+// then tasks and projects, and the tags it added are deleted
+pub fn imported(task_item_ids: &[i64], task_ids: &[i64], project_ids: &[i64], tag_ids: &[i64]) -> Change {
+    let items = task_item_ids.iter().map(|&id| Change::TrashTaskItem { id });
+    let tasks = task_ids.iter().map(|&id| Change::TrashTask { id });
+    let projects = project_ids.iter().map(|&id| Change::TrashProject { id });
+    let tags = tag_ids.iter().map(|&id| Change::DeleteTag { id });
+    Change::Batch { changes: items.chain(tasks).chain(projects).chain(tags).collect() }
+}
+
+// Applies a change made by the user and records how to undo it
+pub fn perform(conn: &Connection, label: &str, change: Change) -> Result<()> {
+    let inverse = change.apply(conn)?;
+    record(conn, label, &inverse)
+}
+
+// For commands that mutate through their module directly (creates, or deletes that
+// report what they removed); `inverse` must be read before the mutation
+pub fn record(conn: &Connection, label: &str, inverse: &Change) -> Result<()> {
+    conn.prepare_cached("DELETE FROM journal WHERE stack = 'redo'")?
+        .execute([])?;
+    push(conn, "undo", label, inverse)?;
+    conn.prepare_cached(
+        "DELETE FROM journal
+         WHERE stack = 'undo'
+         AND id NOT IN (SELECT id FROM journal WHERE stack = 'undo' ORDER BY id DESC LIMIT ?1)"
+    )?.execute([JOURNAL_LIMIT])?;
+    Ok(())
+}
+
+fn push(conn: &Connection, stack: &str, label: &str, change: &Change) -> Result<()> {
+    let change = serde_json::to_string(change)
+        .map_err(|e| AppError::Storage { message: e.to_string() })?;
+    conn.prepare_cached("INSERT INTO journal (stack, label, change) VALUES (?1, ?2, ?3)")?
+        .execute((stack, label, change))?;
+    Ok(())
+}
+
+// Applies the newest entry of one stack and moves its inverse onto the other.
+// Returns the entry's label, or None when there is nothing to undo/redo.
+fn pop(conn: &Connection, from: &str, to: &str) -> Result<Option<String>> {
+    let entry: Option<(i64, String, String)> = conn.prepare_cached("SELECT id, label, change FROM journal WHERE stack = ?1 ORDER BY id DESC LIMIT 1")?
+        .query_row([from], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .optional()?;
+    let Some((id, label, change)) = entry else {
+        return Ok(None);
+    };
+
+    let change: Change = serde_json::from_str(&change)
+        .map_err(|e| AppError::Storage { message: format!("unreadable journal entry {}: {}", id, e) })?;
+    let inverse = change.apply(conn)?;
+
+    conn.prepare_cached("DELETE FROM journal WHERE id = ?1")?
+        .execute([id])?;
+    push(conn, to, &label, &inverse)?;
+
+    Ok(Some(label))
+}
+
+pub fn undo(conn: &Connection) -> Result<Option<String>> {
+    pop(conn, "undo", "redo")
+}
+
+pub fn redo(conn: &Connection) -> Result<Option<String>> {
+    pop(conn, "redo", "undo")
+}
+
+// Drops the entries, on either stack, that refer to rows which no longer exist, such
+// as ones just purged from the trash. The rest of the history stays undoable.
+pub fn forget_missing(conn: &Connection) -> Result<()> {
+    let entries = conn.prepare_cached("SELECT id, change FROM journal")?
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+        .collect::<rusqlite::Result<Vec<(i64, String)>>>()?;
+
+    for (id, change) in entries {
+        // An unreadable entry is left for undo/redo to report
+        let Ok(change) = serde_json::from_str::<Change>(&change) else {
+            continue;
+        };
+        for (entity, row_id) in change.rows() {
+            let exists: bool = conn.prepare_cached(&format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = ?1)", entity.table()))?
+                .query_row([row_id], |row| row.get(0))?;
+            if !exists {
+                conn.prepare_cached("DELETE FROM journal WHERE id = ?1")?.execute([id])?;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Entries may point at rows that no longer exist once every row is replaced
+pub fn clear(conn: &Connection) -> Result<()> {
+    conn.prepare_cached("DELETE FROM journal")?.execute([])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::priority::{Priority, SortOrder};
+    use crate::trash;
+    use serde_json::{json, Value};
+
+    struct Fixture {
+        projects: [i64; 2],
+        tasks: [i64; 3],
+        // The last item is shared by the first two tasks
+        items: [i64; 4],
+        tags: [i64; 2],
+    }
+
+    fn fixture(conn: &Connection) -> Fixture {
+        let projects = [
+            project::create_project(conn, "First", None).unwrap(),
+            project::create_project(conn, "Second", None).unwrap(),
+        ];
+        let tasks = [
+            task::create_task(conn, projects[0], "One", None, None, None).unwrap(),
+            task::create_task(conn, projects[0], "Two", None, None, None).unwrap(),
+            task::create_task(conn, projects[1], "Three", None, None, None).unwrap(),
+        ];
+        let items = ["a", "b", "c", "shared"].map(|content| task_item::create_task_item(conn, content, None, None).unwrap());
+        for &item_id in &items {
+            task_item::link_task_item(conn, tasks[0], item_id).unwrap();
+        }
+        task_item::link_task_item(conn, tasks[1], items[3]).unwrap();
+        let tags = [tag::create_tag(conn, "red", None).unwrap(), tag::create_tag(conn, "blue", None).unwrap()];
+        tag::tag_entity(conn, tags[0], Entity::Task, tasks[0]).unwrap();
+        tag::tag_entity(conn, tags[0], Entity::TaskItem, items[0]).unwrap();
+        Fixture { projects, tasks, items, tags }
+    }
+
+    // Everything a change can touch, as the frontend sees it. Timestamps move on every
+    // write, so only whether they're set is compared.
+    fn snapshot(conn: &Connection) -> Value {
+        let mut tasks = Vec::new();
+        for project in project::get_all_projects(conn, &[]).unwrap() {
+            for task in task::get_tasks(conn, project.id, SortOrder::default(), &[]).unwrap() {
+                let items = task_item::get_task_items(conn, task.id, &Default::default(), SortOrder::default(), None, None).unwrap();
+                let prerequisites: Vec<i64> = dependency::get_prerequisites(conn, task.id).unwrap().iter().map(|task| task.id).collect();
+                tasks.push(json!({ "task": task, "items": items.items, "prerequisites": prerequisites }));
+            }
+        }
+        let tags: Vec<Value> = tag::get_tags(conn).unwrap().into_iter()
+            .map(|tag| json!({ "taggings": tag::get_taggings(conn, tag.id).unwrap(), "tag": tag }))
+            .collect();
+        let trash: Vec<Value> = trash::list_trash(conn).unwrap().into_iter()
+            .map(|entry| json!([entry.entity, entry.id]))
+            .collect();
+        let mut snapshot = json!({ "projects": project::get_all_projects(conn, &[]).unwrap(), "tasks": tasks, "tags": tags, "trash": trash });
+        mask_timestamps(&mut snapshot);
+        snapshot
+    }
+
+    fn mask_timestamps(value: &mut Value) {
+        match value {
+            Value::Object(fields) => {
+                for (key, field) in fields.iter_mut() {
+                    if ["created_at", "updated_at", "completed_at"].contains(&key.as_str()) {
+                        *field = Value::Bool(!field.is_null());
+                    } else {
+                        mask_timestamps(field);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(mask_timestamps),
+            _ => {}
+        }
+    }
+
+    // Performs the change, then checks undo gets back to the state before it and redo
+    // to the state after it
+    fn round_trip(conn: &Connection, change: Change) {
+        let before = snapshot(conn);
+        perform(conn, "change", change.clone()).unwrap();
+        let after = snapshot(conn);
+        assert_ne!(before, after, "{:?} changed nothing", change);
+
+        assert_eq!(undo(conn).unwrap().as_deref(), Some("change"));
+        assert_eq!(snapshot(conn), before, "undoing {:?}", change);
+        assert_eq!(redo(conn).unwrap().as_deref(), Some("change"));
+        assert_eq!(snapshot(conn), after, "redoing {:?}", change);
+        assert_eq!(undo(conn).unwrap().as_deref(), Some("change"));
+        assert_eq!(snapshot(conn), before, "undoing {:?} again", change);
+    }
+
+    // For the variants that only come about as inverses: makes `change` directly and
+    // round trips the change that takes it back
+    fn round_trip_inverse(conn: &Connection, change: Change) {
+        let inverse = change.apply(conn).unwrap();
+        round_trip(conn, inverse);
+    }
+
+    fn with_fixture(test: impl FnOnce(&Connection, &Fixture)) {
+        let conn = db::test_connection();
+        let fixture = fixture(&conn);
+        test(&conn, &fixture);
+    }
+
+    #[test]
+    fn project_changes_round_trip() {
+        with_fixture(|conn, f| round_trip(conn, Change::ProjectContent { id: f.projects[0], title: "Renamed".into(), description: Some("Notes".into()) }));
+        with_fixture(|conn, f| round_trip(conn, Change::ProjectSequence { id: f.projects[0], sequence: 2 }));
+        with_fixture(|conn, f| round_trip(conn, Change::TrashProject { id: f.projects[1] }));
+        with_fixture(|conn, f| round_trip_inverse(conn, Change::TrashProject { id: f.projects[0] }));
+    }
+
+    #[test]
+    fn task_changes_round_trip() {
+        with_fixture(|conn, f| round_trip(conn, Change::TaskContent { id: f.tasks[0], title: "Renamed".into(), description: None }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskSequence { id: f.tasks[0], sequence: 2 }));
+        with_fixture(|conn, f| round_trip(conn, Change::TrashTask { id: f.tasks[1] }));
+        with_fixture(|conn, f| round_trip_inverse(conn, Change::TrashTask { id: f.tasks[0] }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskSchedule {
+            id: f.tasks[0], start_at: Some("2026-11-01 09:00:00".into()), due_at: Some("2026-11-02 17:00:00".into()),
+        }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskPriority { id: f.tasks[0], priority: Some(Priority::P1) }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskRecurrence { id: f.tasks[0], recurrence: Some("FREQ=WEEKLY".into()) }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskProject { id: f.tasks[0], project_id: f.projects[1], sequence: 1 }));
+        with_fixture(|conn, f| round_trip(conn, Change::AddDependency { task_id: f.tasks[1], depends_on_id: f.tasks[0] }));
+        with_fixture(|conn, f| round_trip_inverse(conn, Change::AddDependency { task_id: f.tasks[1], depends_on_id: f.tasks[0] }));
+    }
+
+    #[test]
+    fn task_item_changes_round_trip() {
+        with_fixture(|conn, f| round_trip(conn, Change::TaskItemContent {
+            id: f.items[0], content: "Done".into(), status: TaskItemStatus::Completed, positions: Vec::new(),
+        }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskItemSequence { task_id: f.tasks[0], id: f.items[0], sequence: 3 }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskItemParent { task_id: f.tasks[0], id: f.items[1], parent_id: Some(f.items[0]), sequence: None }));
+        with_fixture(|conn, f| round_trip(conn, Change::TrashTaskItem { id: f.items[2] }));
+        with_fixture(|conn, f| round_trip_inverse(conn, Change::TrashTaskItem { id: f.items[0] }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskItemSchedule { id: f.items[0], start_at: None, due_at: Some("2026-11-02 17:00:00".into()) }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskItemPriority { id: f.items[0], priority: Some(Priority::P0) }));
+        with_fixture(|conn, f| round_trip(conn, Change::TaskItemRecurrence { id: f.items[0], recurrence: Some("FREQ=DAILY".into()) }));
+        with_fixture(|conn, f| round_trip(conn, Change::Link { task_id: f.tasks[2], item_id: f.items[0], sequence: None }));
+        with_fixture(|conn, f| round_trip(conn, Change::Unlink { task_id: f.tasks[1], item_id: f.items[3] }));
+    }
+
+    #[test]
+    fn unlink_round_trips_with_subtasks() {
+        // b under a, c under b: unlinking b moves c up under a
+        with_fixture(|conn, f| {
+            task_item::nest_task_item(conn, f.tasks[0], f.items[1], Some(f.items[0]), None).unwrap();
+            task_item::nest_task_item(conn, f.tasks[0], f.items[2], Some(f.items[1]), None).unwrap();
+            round_trip(conn, Change::Unlink { task_id: f.tasks[0], item_id: f.items[1] });
+        });
+        // a and b under the shared item, which ends up after c at the top level
+        with_fixture(|conn, f| {
+            task_item::nest_task_item(conn, f.tasks[0], f.items[0], Some(f.items[3]), None).unwrap();
+            task_item::nest_task_item(conn, f.tasks[0], f.items[1], Some(f.items[3]), None).unwrap();
+            round_trip(conn, Change::Unlink { task_id: f.tasks[0], item_id: f.items[3] });
+        });
+    }
+
+    #[test]
+    fn tag_changes_round_trip() {
+        with_fixture(|conn, f| round_trip(conn, Change::TagContent { id: f.tags[0], name: "green".into(), color: "#00aa00".into() }));
+        with_fixture(|conn, f| round_trip(conn, Change::DeleteTag { id: f.tags[0] }));
+        with_fixture(|conn, f| round_trip_inverse(conn, Change::DeleteTag { id: f.tags[0] }));
+        with_fixture(|conn, f| round_trip(conn, Change::MergeTags { source_id: f.tags[0], target_id: f.tags[1] }));
+        with_fixture(|conn, f| round_trip_inverse(conn, Change::MergeTags { source_id: f.tags[0], target_id: f.tags[1] }));
+        with_fixture(|conn, f| round_trip(conn, Change::TagEntity { tag_id: f.tags[1], tagging: Tagging { entity: Entity::Task, entity_id: f.tasks[1] } }));
+        with_fixture(|conn, f| round_trip(conn, Change::UntagEntity { tag_id: f.tags[0], tagging: Tagging { entity: Entity::Task, entity_id: f.tasks[0] } }));
+    }
+
+    #[test]
+    fn batch_round_trips_as_one_entry() {
+        with_fixture(|conn, f| {
+            round_trip(conn, Change::Batch { changes: vec![
+                Change::TrashTaskItem { id: f.items[0] },
+                Change::TaskItemSequence { task_id: f.tasks[0], id: f.items[1], sequence: 3 },
+                Change::TrashTask { id: f.tasks[1] },
+                Change::TrashProject { id: f.projects[1] },
+                Change::DeleteTag { id: f.tags[1] },
+            ] });
+            assert_eq!(undo(conn).unwrap(), None);
+        });
+    }
+
+    #[test]
+    fn keeps_the_newest_entries() {
+        with_fixture(|conn, f| {
+            for index in 1..=JOURNAL_LIMIT + 5 {
+                perform(conn, &format!("rename {}", index), Change::TaskContent { id: f.tasks[0], title: index.to_string(), description: None }).unwrap();
+            }
+            let entries: i64 = conn.query_row("SELECT COUNT(*) FROM journal WHERE stack = 'undo'", [], |row| row.get(0)).unwrap();
+            assert_eq!(entries, JOURNAL_LIMIT);
+
+            for _ in 0..JOURNAL_LIMIT {
+                assert!(undo(conn).unwrap().is_some());
+            }
+            assert_eq!(undo(conn).unwrap(), None);
+            assert_eq!(task::get_task(conn, f.tasks[0]).unwrap().title, "5");
+        });
+    }
+
+    #[test]
+    fn purge_forgets_only_entries_for_purged_rows() {
+        with_fixture(|conn, f| {
+            perform(conn, "rename", Change::TaskContent { id: f.tasks[0], title: "Renamed".into(), description: None }).unwrap();
+            perform(conn, "trash", Change::TrashTask { id: f.tasks[1] }).unwrap();
+            trash::purge_trash(conn, None).unwrap();
+
+            assert_eq!(undo(conn).unwrap().as_deref(), Some("rename"));
+            assert_eq!(undo(conn).unwrap(), None);
+        });
+    }
+}
@@ -1,117 +1,515 @@
+mod backup;
+mod config;
+mod db;
+mod dependency;
+mod entity;
+mod error;
+mod history;
+mod journal;
+mod markdown;
+mod migrations;
+mod priority;
 mod project;
+mod rank;
+mod recurrence;
+mod schedule;
+mod search;
+mod spreadsheet;
+mod tag;
 mod task;
 mod task_item;
+mod todo_txt;
+mod trash;
 
+use std::collections::HashMap;
+use std::fs;
+use tauri::{Manager, State};
+use config::Config;
+use db::Database;
+use entity::Entity;
+use error::AppError;
+use journal::Change;
+use priority::{Priority, SortOrder};
+use rank::Placement;
+use tag::Tagging;
+use task_item::Removal;
+
+
+#[tauri::command]
+fn create_project(db: State<'_, Database>, title: String, description: Option<String>) -> Result<i64, AppError> {
+    db.write(|conn| {
+        let id = project::create_project(conn, &title, description.as_deref())?;
+        journal::record(conn, "Create project", &Change::TrashProject { id })?;
+        Ok(id)
+    })
+}
+
+#[tauri::command]
+fn get_projects(db: State<'_, Database>, tags: Option<Vec<i64>>) -> Result<Vec<project::Project>, AppError> {
+    project::get_all_projects(&db.conn(), &tags.unwrap_or_default())
+}
+
+#[tauri::command]
+fn delete_project(db: State<'_, Database>, id: i64) -> Result<project::DeletedProject, AppError> {
+    db.write(|conn| {
+        let inverse = Change::TrashProject { id }.inverse(conn)?;
+        let deleted = project::delete_project(conn, id)?;
+        journal::record(conn, "Delete project", &inverse)?;
+        Ok(deleted)
+    })
+}
+
+#[tauri::command]
+fn update_project_content(db: State<'_, Database>, id: i64, title: String, description: Option<String>) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Edit project", Change::ProjectContent { id, title, description }))
+}
+
+#[tauri::command]
+fn update_project_sequence(db: State<'_, Database>, id: i64, sequence: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Move project", Change::ProjectSequence { id, sequence }))
+}
+
+#[tauri::command]
+fn get_project(db: State<'_, Database>, id: i64) -> Result<project::Project, AppError> {
+    project::get_project(&db.conn(), id)
+}
+
+
+#[tauri::command]
+fn create_task(db: State<'_, Database>, project_id: i64, title: String, description: Option<String>, start_at: Option<String>, due_at: Option<String>) -> Result<i64, AppError> {
+    db.write(|conn| {
+        let id = task::create_task(conn, project_id, &title, description.as_deref(), start_at.as_deref(), due_at.as_deref())?;
+        journal::record(conn, "Create task", &Change::TrashTask { id })?;
+        Ok(id)
+    })
+}
+
+#[tauri::command]
+fn get_tasks(db: State<'_, Database>, project_id: i64, order: Option<SortOrder>, tags: Option<Vec<i64>>) -> Result<Vec<task::Task>, AppError> {
+    task::get_tasks(&db.conn(), project_id, order.unwrap_or_default(), &tags.unwrap_or_default())
+}
+
+#[tauri::command]
+fn delete_task(db: State<'_, Database>, id: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Delete task", Change::TrashTask { id }))
+}
+
+#[tauri::command]
+fn update_task_content(db: State<'_, Database>, id: i64, title: String, description: Option<String>) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Edit task", Change::TaskContent { id, title, description }))
+}
+
+#[tauri::command]
+fn update_task_schedule(db: State<'_, Database>, id: i64, start_at: Option<String>, due_at: Option<String>) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Reschedule task", Change::TaskSchedule { id, start_at, due_at }))
+}
+
+#[tauri::command]
+fn update_task_priority(db: State<'_, Database>, id: i64, priority: Option<Priority>) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Change task priority", Change::TaskPriority { id, priority }))
+}
+
+// `recurrence` is an RRULE subset (see recurrence.rs); None or blank stops recurring
+#[tauri::command]
+fn update_task_recurrence(db: State<'_, Database>, id: i64, recurrence: Option<String>) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Change task recurrence", Change::TaskRecurrence { id, recurrence }))
+}
+
+#[tauri::command]
+fn update_task_sequence(db: State<'_, Database>, id: i64, sequence: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Move task", Change::TaskSequence { id, sequence }))
+}
+
+// Without a position the task is appended to the target project
+#[tauri::command]
+fn move_task(db: State<'_, Database>, task_id: i64, target_project_id: i64, position: Option<i64>) -> Result<(), AppError> {
+    db.write(|conn| {
+        let inverse = Change::TaskProject { id: task_id, project_id: 0, sequence: 0 }.inverse(conn)?;
+        task::move_to_project(conn, task_id, target_project_id, position)?;
+        journal::record(conn, "Move task to project", &inverse)
+    })
+}
+
+// Rejected with a Conflict when `depends_on_id` already waits on `task_id`
+#[tauri::command]
+fn add_dependency(db: State<'_, Database>, task_id: i64, depends_on_id: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Add dependency", Change::AddDependency { task_id, depends_on_id }))
+}
+
+#[tauri::command]
+fn remove_dependency(db: State<'_, Database>, task_id: i64, depends_on_id: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Remove dependency", Change::RemoveDependency { task_id, depends_on_id }))
+}
+
+#[tauri::command]
+fn get_prerequisites(db: State<'_, Database>, task_id: i64) -> Result<Vec<task::Task>, AppError> {
+    dependency::get_prerequisites(&db.conn(), task_id)
+}
+
+#[tauri::command]
+fn get_dependents(db: State<'_, Database>, task_id: i64) -> Result<Vec<task::Task>, AppError> {
+    dependency::get_dependents(&db.conn(), task_id)
+}
+
+#[tauri::command]
+fn get_next_actionable(db: State<'_, Database>, project_id: i64) -> Result<Vec<task::Task>, AppError> {
+    dependency::get_next_actionable(&db.conn(), project_id)
+}
+
+// Undo trashes the copy; items duplicated by a deep copy stay linked to it, in the trash with it
+#[tauri::command]
+fn copy_task(db: State<'_, Database>, task_id: i64, target_project_id: i64, deep: bool) -> Result<i64, AppError> {
+    db.write(|conn| {
+        let id = task::copy_task(conn, task_id, target_project_id, deep)?;
+        journal::record(conn, "Copy task", &Change::TrashTask { id })?;
+        Ok(id)
+    })
+}
+
+#[tauri::command]
+fn get_task(db: State<'_, Database>, id: i64) -> Result<task::Task, AppError> {
+    task::get_task(&db.conn(), id)
+}
+
+
+#[tauri::command]
+fn create_task_item(db: State<'_, Database>, content: String, start_at: Option<String>, due_at: Option<String>) -> Result<i64, AppError> {
+    db.write(|conn| {
+        let id = task_item::create_task_item(conn, &content, start_at.as_deref(), due_at.as_deref())?;
+        journal::record(conn, "Create task item", &Change::TrashTaskItem { id })?;
+        Ok(id)
+    })
+}
+
+#[tauri::command]
+fn link_task_item(db: State<'_, Database>, task_id: i64, item_id: i64) -> Result<i64, AppError> {
+    db.write(|conn| {
+        let inverse = Change::Link { task_id, item_id, sequence: None }.inverse(conn)?;
+        let id = task_item::link_task_item(conn, task_id, item_id)?;
+        journal::record(conn, "Link task item", &inverse)?;
+        Ok(id)
+    })
+}
+
+#[tauri::command]
+fn get_task_items(
+    db: State<'_, Database>,
+    task_id: i64,
+    filter: Option<task_item::TaskItemFilter>,
+    order: Option<SortOrder>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<task_item::TaskItemPage, AppError> {
+    task_item::get_task_items(&db.conn(), task_id, &filter.unwrap_or_default(), order.unwrap_or_default(), limit, offset)
+}
+
+#[tauri::command]
+fn update_task_item_content(db: State<'_, Database>, task_item_id: i64, content: String, status: task_item::TaskItemStatus) -> Result<(), AppError> {
+    db.write(|conn| {
+        let change = Change::TaskItemContent { id: task_item_id, content, status, positions: Vec::new() };
+        journal::perform(conn, "Edit task item", change)
+    })
+}
+
+#[tauri::command]
+fn update_task_item_schedule(db: State<'_, Database>, task_item_id: i64, start_at: Option<String>, due_at: Option<String>) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Reschedule task item", Change::TaskItemSchedule { id: task_item_id, start_at, due_at }))
+}
+
+#[tauri::command]
+fn update_task_item_priority(db: State<'_, Database>, task_item_id: i64, priority: Option<Priority>) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Change task item priority", Change::TaskItemPriority { id: task_item_id, priority }))
+}
+
+#[tauri::command]
+fn update_task_item_recurrence(db: State<'_, Database>, task_item_id: i64, recurrence: Option<String>) -> Result<(), AppError> {
+    db.write(|conn| {
+        journal::perform(conn, "Change task item recurrence", Change::TaskItemRecurrence { id: task_item_id, recurrence })
+    })
+}
+
+// Upcoming dates of a rule after `from` (now by default), 5 unless a limit is given
+#[tauri::command]
+fn preview_recurrence(db: State<'_, Database>, rule: String, from: Option<String>, limit: Option<u32>) -> Result<Vec<String>, AppError> {
+    recurrence::preview(&db.conn(), &rule, from.as_deref(), limit.unwrap_or(5))
+}
+
+#[tauri::command]
+fn update_task_item_sequence(db: State<'_, Database>, task_id: i64, task_item_id: i64, new_sequence: i64) -> Result<(), AppError> {
+    db.write(|conn| {
+        journal::perform(conn, "Move task item", Change::TaskItemSequence { task_id, id: task_item_id, sequence: new_sequence })
+    })
+}
+
+// Indenting makes the item a subtask of the open item above it, outdenting moves
+// it up a level; either way its own subtasks come along
+#[tauri::command]
+fn indent_task_item(db: State<'_, Database>, task_id: i64, task_item_id: i64) -> Result<(), AppError> {
+    db.write(|conn| {
+        let inverse = Change::TaskItemParent { task_id, id: task_item_id, parent_id: None, sequence: None }.inverse(conn)?;
+        task_item::indent_task_item(conn, task_id, task_item_id)?;
+        journal::record(conn, "Indent task item", &inverse)
+    })
+}
 
 #[tauri::command]
-fn create_project(title: String, description: Option<String>) -> Result<i64, String> {
-    project::create_project(&title, description.as_deref())
-        .map_err(|e| e.to_string())
+fn outdent_task_item(db: State<'_, Database>, task_id: i64, task_item_id: i64) -> Result<(), AppError> {
+    db.write(|conn| {
+        let inverse = Change::TaskItemParent { task_id, id: task_item_id, parent_id: None, sequence: None }.inverse(conn)?;
+        task_item::outdent_task_item(conn, task_id, task_item_id)?;
+        journal::record(conn, "Outdent task item", &inverse)
+    })
+}
+
+// Moves a project, task or task item next to `anchor_id` in its list. Items are
+// ordered per task, so `task_id` says which of the item's tasks to reorder.
+fn move_next_to(conn: &rusqlite::Connection, entity: Entity, id: i64, task_id: Option<i64>, to: Placement) -> Result<(), AppError> {
+    // Also turns away tags and items without a task
+    let inverse = Change::current_position(conn, entity, id, task_id)?;
+    let label = match inverse {
+        Change::TaskItemSequence { task_id, .. } => {
+            task_item::move_task_item(conn, task_id, id, to)?;
+            "Move task item"
+        }
+        Change::TaskSequence { .. } => {
+            task::move_task(conn, id, to)?;
+            "Move task"
+        }
+        _ => {
+            project::move_project(conn, id, to)?;
+            "Move project"
+        }
+    };
+    journal::record(conn, label, &inverse)
+}
+
+#[tauri::command]
+fn move_before(db: State<'_, Database>, entity: Entity, id: i64, anchor_id: i64, task_id: Option<i64>) -> Result<(), AppError> {
+    db.write(|conn| move_next_to(conn, entity, id, task_id, Placement::Before(anchor_id)))
 }
 
 #[tauri::command]
-fn get_projects() -> Result<Vec<project::Project>, String> {
-    project::get_all_projects()
-        .map_err(|e| e.to_string())
+fn move_after(db: State<'_, Database>, entity: Entity, id: i64, anchor_id: i64, task_id: Option<i64>) -> Result<(), AppError> {
+    db.write(|conn| move_next_to(conn, entity, id, task_id, Placement::After(anchor_id)))
 }
 
+// With a `task_id` the item is only removed from that task, unless no other live task
+// shows it. Without one it goes to the trash, out of every task at once.
 #[tauri::command]
-fn delete_project(id: i64) -> Result<(), String> {
-    project::delete_project(id)
-        .map_err(|e| e.to_string())
+fn delete_task_item(db: State<'_, Database>, id: i64, task_id: Option<i64>) -> Result<Removal, AppError> {
+    db.write(|conn| match task_id {
+        Some(task_id) if task_item::removal_from_task(conn, task_id, id)? == Removal::Unlinked => {
+            journal::perform(conn, "Remove task item from task", Change::Unlink { task_id, item_id: id })?;
+            Ok(Removal::Unlinked)
+        }
+        _ => {
+            journal::perform(conn, "Delete task item", Change::TrashTaskItem { id })?;
+            Ok(Removal::Trashed)
+        }
+    })
 }
 
 #[tauri::command]
-fn update_project_content(id: i64, title: String, description: Option<String>) -> Result<(), String> {
-    project::update_project_content(id, &title, description.as_deref())
-        .map_err(|e| e.to_string())
+fn unlink_task_item(db: State<'_, Database>, task_id: i64, item_id: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Unlink task item", Change::Unlink { task_id, item_id }))
 }
 
 #[tauri::command]
-fn update_project_sequence(id: i64, sequence: i64) -> Result<(), String> {
-    project::update_project_sequence(id, sequence)
-        .map_err(|e| e.to_string())
+fn get_item_links(db: State<'_, Database>, item_id: i64) -> Result<Vec<task_item::LinkedTask>, AppError> {
+    task_item::get_linked_tasks(&db.conn(), item_id)
 }
 
 #[tauri::command]
-fn get_project(id: i64) -> Result<project::Project, String> {
-    project::get_project(id)
-        .map_err(|e| e.to_string())
+fn list_orphan_items(db: State<'_, Database>) -> Result<Vec<task_item::TaskItem>, AppError> {
+    task_item::list_orphan_items(&db.conn())
 }
 
 
 #[tauri::command]
-fn create_task(project_id: i64, title: String, description: Option<String>) -> Result<i64, String> {
-    task::create_task(project_id, &title, description.as_deref())
-        .map_err(|e| e.to_string())
+fn create_tag(db: State<'_, Database>, name: String, color: Option<String>) -> Result<i64, AppError> {
+    db.write(|conn| {
+        let id = tag::create_tag(conn, &name, color.as_deref())?;
+        journal::record(conn, "Create tag", &Change::DeleteTag { id })?;
+        Ok(id)
+    })
+}
+
+#[tauri::command]
+fn get_tags(db: State<'_, Database>) -> Result<Vec<tag::Tag>, AppError> {
+    tag::get_tags(&db.conn())
+}
+
+#[tauri::command]
+fn update_tag(db: State<'_, Database>, id: i64, name: String, color: String) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Edit tag", Change::TagContent { id, name, color }))
 }
 
 #[tauri::command]
-fn get_tasks(project_id: i64) -> Result<Vec<task::Task>, String> {
-    task::get_tasks(project_id)
-        .map_err(|e| e.to_string())
+fn delete_tag(db: State<'_, Database>, id: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Delete tag", Change::DeleteTag { id }))
 }
 
 #[tauri::command]
-fn delete_task(id: i64) -> Result<(), String> {
-    task::delete_task(id)
-        .map_err(|e| e.to_string())
+fn merge_tags(db: State<'_, Database>, source_id: i64, target_id: i64) -> Result<(), AppError> {
+    db.write(|conn| journal::perform(conn, "Merge tags", Change::MergeTags { source_id, target_id }))
 }
 
 #[tauri::command]
-fn update_task_content(id: i64, title: String, description: Option<String>) -> Result<(), String> {
-    task::update_task_content(id, &title, description.as_deref())
-        .map_err(|e| e.to_string())
+fn tag_entity(db: State<'_, Database>, tag_id: i64, entity: Entity, entity_id: i64) -> Result<(), AppError> {
+    let tagging = Tagging { entity, entity_id };
+    db.write(|conn| journal::perform(conn, "Add tag", Change::TagEntity { tag_id, tagging }))
 }
 
 #[tauri::command]
-fn update_task_sequence(id: i64, sequence: i64) -> Result<(), String> {
-    task::update_task_sequence(id, sequence)
-        .map_err(|e| e.to_string())
+fn untag_entity(db: State<'_, Database>, tag_id: i64, entity: Entity, entity_id: i64) -> Result<(), AppError> {
+    let tagging = Tagging { entity, entity_id };
+    db.write(|conn| journal::perform(conn, "Remove tag", Change::UntagEntity { tag_id, tagging }))
 }
 
 #[tauri::command]
-fn get_task(id: i64) -> Result<task::Task, String> {
-    task::get_task(id)
-        .map_err(|e| e.to_string())
+fn get_entity_tags(db: State<'_, Database>, entity: Entity, id: i64) -> Result<Vec<tag::Tag>, AppError> {
+    tag::get_entity_tags(&db.conn(), entity, id)
 }
 
 
 #[tauri::command]
-fn create_task_item(content: String) -> Result<i64, String> {
-    task_item::create_task_item(&content)
-        .map_err(|e| e.to_string())
+fn get_overdue(db: State<'_, Database>) -> Result<schedule::Agenda, AppError> {
+    schedule::get_overdue(&db.conn())
 }
 
 #[tauri::command]
-fn link_task_item(task_id: i64, item_id: i64) -> Result<i64, String> {
-    task_item::link_task_item(task_id, item_id)
-        .map_err(|e| e.to_string())
+fn get_due_between(db: State<'_, Database>, from: String, to: String) -> Result<schedule::Agenda, AppError> {
+    schedule::get_due_between(&db.conn(), &from, &to)
 }
 
 #[tauri::command]
-fn get_task_items(task_id: i64) -> Result<Vec<task_item::TaskItemWithSequence>, String> {
-    task_item::get_task_items(task_id)
-        .map_err(|e| e.to_string())
+fn get_today(db: State<'_, Database>) -> Result<schedule::Agenda, AppError> {
+    schedule::get_today(&db.conn())
+}
+
+
+#[tauri::command]
+fn search(db: State<'_, Database>, query: String, limit: Option<u32>) -> Result<Vec<search::SearchHit>, AppError> {
+    search::search(&db.conn(), &query, limit)
 }
 
+
 #[tauri::command]
-fn update_task_item_content(task_item_id: i64, content: String, status: i64) -> Result<(), String> {
-    let status_enum = task_item::TaskItemStatus::from(status);
-    task_item::update_task_item_content(task_item_id, &content, status_enum)
-        .map_err(|e| e.to_string())
+fn list_trash(db: State<'_, Database>) -> Result<Vec<trash::TrashEntry>, AppError> {
+    trash::list_trash(&db.conn())
 }
 
 #[tauri::command]
-fn update_task_item_sequence(task_id: i64, task_item_id: Option<i64>, new_sequence: Option<i64>) -> Result<(), String> {
-    task_item::update_task_item_sequence(task_id, task_item_id, new_sequence)
-        .map_err(|e| e.to_string())
+fn restore_project(db: State<'_, Database>, id: i64) -> Result<(), AppError> {
+    db.write(|conn| {
+        project::restore_project(conn, id)?;
+        journal::record(conn, "Restore project", &Change::TrashProject { id })
+    })
 }
 
 #[tauri::command]
-fn delete_task_item(id: i64) -> Result<(), String> {
-    task_item::delete_task_item(id)
-        .map_err(|e| e.to_string())
+fn restore_task(db: State<'_, Database>, id: i64) -> Result<(), AppError> {
+    db.write(|conn| {
+        task::restore_task(conn, id)?;
+        journal::record(conn, "Restore task", &Change::TrashTask { id })
+    })
+}
+
+#[tauri::command]
+fn restore_task_item(db: State<'_, Database>, id: i64) -> Result<(), AppError> {
+    db.write(|conn| {
+        task_item::restore_task_item(conn, id)?;
+        journal::record(conn, "Restore task item", &Change::TrashTaskItem { id })
+    })
+}
+
+#[tauri::command]
+fn purge_trash(db: State<'_, Database>, older_than_days: Option<u32>) -> Result<trash::PurgedTrash, AppError> {
+    db.write(|conn| trash::purge_trash(conn, older_than_days))
+}
+
+
+// The whole database as a JSON document, see backup.rs for its layout
+#[tauri::command]
+fn export_json(db: State<'_, Database>) -> Result<String, AppError> {
+    backup::export_json(&db.conn())
+}
+
+// With `dry_run` the import is checked and counted but nothing is kept
+#[tauri::command]
+fn import_json(db: State<'_, Database>, document: String, mode: backup::ImportMode, dry_run: Option<bool>) -> Result<backup::ImportReport, AppError> {
+    db.write(|conn| backup::import_json(conn, &document, mode, dry_run.unwrap_or(false)))
+}
+
+#[tauri::command]
+fn export_project_markdown(db: State<'_, Database>, project_id: i64, options: Option<markdown::MarkdownOptions>) -> Result<String, AppError> {
+    markdown::export_project(&db.conn(), project_id, &options.unwrap_or_default())
+}
+
+// With `preview` the parsed tasks come back without anything being written
+#[tauri::command]
+fn import_markdown(db: State<'_, Database>, project_id: i64, text: String, preview: Option<bool>) -> Result<Vec<markdown::ParsedTask>, AppError> {
+    if preview.unwrap_or(false) {
+        return markdown::preview_import(&db.conn(), project_id, &text);
+    }
+    db.write(|conn| {
+        let tasks = markdown::import_project(conn, project_id, &text)?;
+        let task_ids: Vec<i64> = tasks.iter().filter_map(|task| task.id).collect();
+        let task_item_ids = markdown::item_ids(&tasks);
+        journal::record(conn, "Import Markdown", &journal::imported(&task_item_ids, &task_ids, &[], &[]))?;
+        Ok(tasks)
+    })
+}
+
+#[tauri::command]
+fn export_csv(db: State<'_, Database>, project_id: Option<i64>) -> Result<String, AppError> {
+    spreadsheet::export_csv(&db.conn(), project_id)
+}
+
+// `columns` maps the file's headers to field names where they differ; with row errors
+// in the report nothing was imported
+#[tauri::command]
+fn import_csv(db: State<'_, Database>, text: String, project_id: Option<i64>, columns: Option<HashMap<String, String>>) -> Result<spreadsheet::CsvImport, AppError> {
+    db.write(|conn| {
+        let report = spreadsheet::import_csv(conn, &text, project_id, &columns.unwrap_or_default())?;
+        if report.errors.is_empty() {
+            let undo = journal::imported(&report.task_item_ids, &report.task_ids, &report.project_ids, &[]);
+            journal::record(conn, "Import CSV", &undo)?;
+        }
+        Ok(report)
+    })
+}
+
+#[tauri::command]
+fn export_todo_txt(db: State<'_, Database>, project_id: Option<i64>) -> Result<String, AppError> {
+    todo_txt::export_todo_txt(&db.conn(), project_id)
+}
+
+#[tauri::command]
+fn import_todo_txt(db: State<'_, Database>, text: String) -> Result<todo_txt::TodoImport, AppError> {
+    db.write(|conn| {
+        let report = todo_txt::import_todo_txt(conn, &text)?;
+        let undo = journal::imported(&report.task_item_ids, &report.task_ids, &report.project_ids, &report.tag_ids);
+        journal::record(conn, "Import todo.txt", &undo)?;
+        Ok(report)
+    })
+}
+
+#[tauri::command]
+fn get_history(db: State<'_, Database>, entity: Entity, id: i64) -> Result<Vec<history::HistoryEntry>, AppError> {
+    history::get_history(&db.conn(), entity, id)
+}
+
+
+// Both return the label of the entry that was undone/redone, or null when the stack is empty
+#[tauri::command]
+fn undo(db: State<'_, Database>) -> Result<Option<String>, AppError> {
+    db.write(journal::undo)
+}
+
+#[tauri::command]
+fn redo(db: State<'_, Database>) -> Result<Option<String>, AppError> {
+    db.write(journal::redo)
 }
 
 
@@ -120,10 +518,32 @@ fn delete_task_item(id: i64) -> Result<(), String> {
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            let config = Config::resolve(app)?;
+            if let Some(dir) = config.db_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let database = Database::open(&config.db_path)?;
+
+            database.write(|conn| trash::purge_trash(conn, Some(config.trash_retention_days)))?;
+
+            app.manage(database);
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             create_project, get_projects, delete_project, update_project_content, update_project_sequence, get_project,
-            create_task, get_tasks, delete_task, update_task_content, update_task_sequence, get_task,
-            create_task_item, link_task_item, get_task_items, update_task_item_content, update_task_item_sequence, delete_task_item
+            create_task, get_tasks, delete_task, update_task_content, update_task_schedule, update_task_priority, update_task_recurrence, update_task_sequence, get_task,
+            move_task, copy_task,
+            add_dependency, remove_dependency, get_prerequisites, get_dependents, get_next_actionable,
+            create_task_item, link_task_item, get_task_items, update_task_item_content, update_task_item_schedule, update_task_item_priority, update_task_item_recurrence, update_task_item_sequence, indent_task_item, outdent_task_item, delete_task_item,
+            unlink_task_item, get_item_links, list_orphan_items,
+            move_before, move_after,
+            create_tag, get_tags, update_tag, delete_tag, merge_tags, tag_entity, untag_entity, get_entity_tags,
+            get_overdue, get_due_between, get_today, preview_recurrence, search,
+            list_trash, restore_project, restore_task, restore_task_item, purge_trash,
+            export_json, import_json, export_project_markdown, import_markdown, export_csv, import_csv,
+            export_todo_txt, import_todo_txt,
+            undo, redo, get_history
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,599 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, Result};
+use crate::priority::SortOrder;
+use crate::project;
+use crate::task::{self, Task};
+use crate::task_item::{self, TaskItemFilter, TaskItemStatus, TaskItemWithSequence};
+
+// A project as a GitHub-flavored Markdown checklist: the project is the `#` heading,
+// each task a `##` heading in sequence order, and its items a task list nested two
+// spaces per subtask level. GFM only renders `[ ]` and `[x]` as checkboxes, the other
+// markers show as plain text:
+//
+//     - [ ] Pending        - [/] InProgress     - [!] Blocked
+//     - [>] Deferred       - [x] Completed      - [~] Canceled
+//
+// Importing reads the same layout back into a project: every heading starts a task and
+// the checklist lines (`-`, `*` or `+` bullets) under it become its items, nested by
+// indentation. A lone `#` heading above `##` ones names the document, as in the export,
+// and is skipped along with its text. Other text under a heading is the task's
+// description, and lines indented past an item continue its content.
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct MarkdownOptions {
+    // Project and task descriptions as paragraphs under their headings
+    pub include_descriptions: bool,
+    // Leaves out canceled items along with their subtasks
+    pub hide_canceled: bool
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParsedTask {
+    // Set once imported, None in a preview
+    pub id: Option<i64>,
+    pub title: String,
+    pub description: Option<String>,
+    pub items: Vec<ParsedItem>
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParsedItem {
+    pub id: Option<i64>,
+    pub content: String,
+    pub status: TaskItemStatus,
+    pub subtasks: Vec<ParsedItem>
+}
+
+// Ids of the imported items, subtasks included
+pub fn item_ids(tasks: &[ParsedTask]) -> Vec<i64> {
+    fn collect(items: &[ParsedItem], ids: &mut Vec<i64>) {
+        for item in items {
+            ids.extend(item.id);
+            collect(&item.subtasks, ids);
+        }
+    }
+    let mut ids = Vec::new();
+    for task in tasks {
+        collect(&task.items, &mut ids);
+    }
+    ids
+}
+
+pub fn marker(status: TaskItemStatus) -> &'static str {
+    match status {
+        TaskItemStatus::Pending => "[ ]",
+        TaskItemStatus::Completed => "[x]",
+        TaskItemStatus::Canceled => "[~]",
+        TaskItemStatus::InProgress => "[/]",
+        TaskItemStatus::Blocked => "[!]",
+        TaskItemStatus::Deferred => "[>]",
+    }
+}
+
+fn status(marker: char) -> Option<TaskItemStatus> {
+    match marker {
+        ' ' => Some(TaskItemStatus::Pending),
+        'x' | 'X' => Some(TaskItemStatus::Completed),
+        '~' => Some(TaskItemStatus::Canceled),
+        '/' => Some(TaskItemStatus::InProgress),
+        '!' => Some(TaskItemStatus::Blocked),
+        '>' => Some(TaskItemStatus::Deferred),
+        _ => None,
+    }
+}
+
+pub fn export_project(conn: &Connection, project_id: i64, options: &MarkdownOptions) -> Result<String> {
+    let project = project::get_project(conn, project_id)?;
+    let mut out = String::new();
+    heading(&mut out, "#", &project.title);
+    if options.include_descriptions {
+        paragraph(&mut out, project.description.as_deref());
+    }
+
+    for task in task::get_tasks(conn, project_id, SortOrder::Sequence, &[])? {
+        write_task(conn, &mut out, &task, options)?;
+    }
+    Ok(out)
+}
+
+fn write_task(conn: &Connection, out: &mut String, task: &Task, options: &MarkdownOptions) -> Result<()> {
+    out.push('\n');
+    heading(out, "##", &task.title);
+    if options.include_descriptions {
+        paragraph(out, task.description.as_deref());
+    }
+
+    let items = task_item::get_task_items(conn, task.id, &TaskItemFilter::default(), SortOrder::Sequence, None, None)?.items;
+    let items = visible(&items, options);
+    if !items.is_empty() {
+        out.push('\n');
+    }
+    for item in items {
+        let indent = "  ".repeat(item.depth as usize);
+        // Continuation lines line up with the text after the marker
+        let content = item.content.trim().replace('\n', &format!("\n{}      ", indent));
+        out.push_str(&format!("{}- {} {}\n", indent, marker(item.status), content));
+    }
+    Ok(())
+}
+
+// Items come depth first, so a hidden item's subtasks are the deeper ones right after it
+fn visible<'a>(items: &'a [TaskItemWithSequence], options: &MarkdownOptions) -> Vec<&'a TaskItemWithSequence> {
+    let mut hidden_below = None;
+    items.iter()
+        .filter(|item| {
+            if hidden_below.is_some_and(|depth| item.depth > depth) {
+                return false;
+            }
+            hidden_below = None;
+            if options.hide_canceled && item.status == TaskItemStatus::Canceled {
+                hidden_below = Some(item.depth);
+                return false;
+            }
+            true
+        })
+        .collect()
+}
+
+// Headings are a single line, so line breaks in titles become spaces
+fn heading(out: &mut String, level: &str, title: &str) {
+    let title = title.split_whitespace().collect::<Vec<&str>>().join(" ");
+    out.push_str(&format!("{} {}\n", level, title));
+}
+
+fn paragraph(out: &mut String, text: Option<&str>) {
+    if let Some(text) = text.map(str::trim).filter(|text| !text.is_empty()) {
+        out.push_str(&format!("\n{}\n", text));
+    }
+}
+
+// The tasks and items `text` would become, without writing anything
+pub fn preview_import(conn: &Connection, project_id: i64, text: &str) -> Result<Vec<ParsedTask>> {
+    project::get_project(conn, project_id)?;
+    parse(text)
+}
+
+// Appends the parsed tasks to the project, items in their written order
+pub fn import_project(conn: &Connection, project_id: i64, text: &str) -> Result<Vec<ParsedTask>> {
+    let mut tasks = preview_import(conn, project_id, text)?;
+    for task in &mut tasks {
+        let id = task::create_task(conn, project_id, &task.title, task.description.as_deref(), None, None)?;
+        create_items(conn, id, &mut task.items, None)?;
+        close_items(conn, &task.items)?;
+        task.id = Some(id);
+    }
+    Ok(tasks)
+}
+
+// Items start out pending so every one gets its place among its siblings
+fn create_items(conn: &Connection, task_id: i64, items: &mut [ParsedItem], parent_id: Option<i64>) -> Result<()> {
+    for item in items {
+        let id = task_item::create_task_item(conn, &item.content, None, None)?;
+        task_item::link_task_item(conn, task_id, id)?;
+        if parent_id.is_some() {
+            task_item::nest_task_item(conn, task_id, id, parent_id, None)?;
+        }
+        item.id = Some(id);
+        create_items(conn, task_id, &mut item.subtasks, Some(id))?;
+    }
+    Ok(())
+}
+
+// Subtasks first, a finished item can't have unfinished subtasks
+fn close_items(conn: &Connection, items: &[ParsedItem]) -> Result<()> {
+    for item in items {
+        close_items(conn, &item.subtasks)?;
+        if let (Some(id), false) = (item.id, item.status == TaskItemStatus::Pending) {
+            task_item::update_task_item_content(conn, id, &item.content, item.status)?;
+        }
+    }
+    Ok(())
+}
+
+enum Line<'a> {
+    Heading { level: usize, title: &'a str },
+    Item { indent: usize, status: TaskItemStatus, content: &'a str },
+    Text { indent: usize, text: &'a str },
+    // Inside a fenced code block, kept as it is
+    Code(&'a str),
+    Blank,
+}
+
+pub fn parse(text: &str) -> Result<Vec<ParsedTask>> {
+    let mut lines = Vec::new();
+    let mut fenced = false;
+    for (index, line) in text.lines().enumerate() {
+        let fence = line.trim_start().starts_with("```") || line.trim_start().starts_with("~~~");
+        if fence || fenced {
+            fenced ^= fence;
+            lines.push(Line::Code(line.trim_end()));
+        } else {
+            lines.push(classify(index + 1, line)?);
+        }
+    }
+
+    let levels: Vec<usize> = lines.iter()
+        .filter_map(|line| match line {
+            Line::Heading { level, .. } => Some(*level),
+            _ => None,
+        })
+        .collect();
+    let mut skip_title = levels.len() > 1 && levels[0] == 1 && levels[1..].iter().all(|&level| level > 1);
+
+    let mut drafts: Vec<TaskDraft> = Vec::new();
+    // Under the document title, whose text isn't part of any task
+    let mut in_title = false;
+    // The previous line was an item or continued one
+    let mut in_item = false;
+    for (index, line) in lines.into_iter().enumerate() {
+        if let Line::Heading { title, .. } = line {
+            in_title = skip_title;
+            skip_title = false;
+            if !in_title {
+                drafts.push(TaskDraft::new(title));
+            }
+            in_item = false;
+            continue;
+        }
+        let draft = match drafts.last_mut() {
+            Some(draft) if !in_title => draft,
+            _ => {
+                if let Line::Item { .. } = line {
+                    return Err(AppError::validation("text", format!("line {}: checklist items need a heading above them", index + 1)));
+                }
+                continue;
+            }
+        };
+        match line {
+            Line::Item { indent, status, content } => {
+                draft.push_item(index + 1, indent, status, content)?;
+                in_item = true;
+                continue;
+            }
+            Line::Text { indent, text } if in_item => {
+                if let Some(last) = draft.items.last_mut().filter(|last| indent > last.indent) {
+                    last.item.content.push('\n');
+                    last.item.content.push_str(text.trim());
+                    continue;
+                }
+                draft.describe(text);
+            }
+            Line::Text { text, .. } | Line::Code(text) => draft.describe(text),
+            Line::Blank => draft.describe(""),
+            Line::Heading { .. } => {}
+        }
+        in_item = false;
+    }
+
+    if drafts.is_empty() {
+        return Err(AppError::validation("text", "no headings to import as tasks"));
+    }
+    Ok(drafts.into_iter().map(TaskDraft::finish).collect())
+}
+
+fn classify(number: usize, line: &str) -> Result<Line<'_>> {
+    let text = line.trim();
+    if text.is_empty() {
+        return Ok(Line::Blank);
+    }
+    let indent = width(&line[..line.len() - line.trim_start().len()]);
+
+    let level = text.chars().take_while(|&c| c == '#').count();
+    if indent < 4 && (1..=6).contains(&level) && (text.len() == level || text[level..].starts_with([' ', '\t'])) {
+        // An optional closing run of #s only counts after a space: "## C#" keeps its #
+        let mut title = text[level..].trim();
+        let unclosed = title.trim_end_matches('#');
+        if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) {
+            title = unclosed.trim_end();
+        }
+        if title.is_empty() {
+            return Err(AppError::validation("text", format!("line {}: heading without a title", number)));
+        }
+        return Ok(Line::Heading { level, title });
+    }
+
+    if let Some(rest) = text.strip_prefix(['-', '*', '+']).and_then(|rest| rest.strip_prefix([' ', '\t'])) {
+        let mut chars = rest.trim_start().chars();
+        if let (Some('['), Some(marker), Some(']')) = (chars.next(), chars.next(), chars.next()) {
+            let content = chars.as_str();
+            if content.is_empty() || content.starts_with([' ', '\t']) {
+                let status = status(marker).ok_or_else(|| {
+                    AppError::validation("text", format!("line {}: unknown marker [{}], use one of [ ] [x] [~] [/] [!] [>]", number, marker))
+                })?;
+                if content.trim().is_empty() {
+                    return Err(AppError::validation("text", format!("line {}: checklist item without text", number)));
+                }
+                return Ok(Line::Item { indent, status, content: content.trim() });
+            }
+        }
+    }
+
+    Ok(Line::Text { indent, text: line.trim_end() })
+}
+
+// Tabs advance to the next multiple of four columns
+fn width(whitespace: &str) -> usize {
+    whitespace.chars().fold(0, |width, c| if c == '\t' { width / 4 * 4 + 4 } else { width + 1 })
+}
+
+struct TaskDraft<'a> {
+    title: &'a str,
+    description: Vec<&'a str>,
+    items: Vec<ItemDraft>,
+}
+
+struct ItemDraft {
+    indent: usize,
+    depth: usize,
+    parent: Option<usize>,
+    item: ParsedItem,
+}
+
+impl<'a> TaskDraft<'a> {
+    fn new(title: &'a str) -> Self {
+        TaskDraft { title, description: Vec::new(), items: Vec::new() }
+    }
+
+    // Runs of blank lines collapse into one paragraph break
+    fn describe(&mut self, line: &'a str) {
+        if !(line.is_empty() && self.description.last().is_none_or(|last| last.is_empty())) {
+            self.description.push(line);
+        }
+    }
+
+    // The item goes under the nearest earlier one that is indented less
+    fn push_item(&mut self, number: usize, indent: usize, status: TaskItemStatus, content: &str) -> Result<()> {
+        let mut parent = self.items.len().checked_sub(1);
+        while let Some(index) = parent.filter(|&index| self.items[index].indent >= indent) {
+            parent = self.items[index].parent;
+        }
+        if parent.is_some_and(|index| self.items[index].item.status.is_done()) && !status.is_done() {
+            return Err(AppError::validation("text", format!("line {}: an unfinished item can't be a subtask of a finished one", number)));
+        }
+
+        let depth = parent.map_or(0, |index| self.items[index].depth + 1);
+        let item = ParsedItem { id: None, content: content.to_string(), status, subtasks: Vec::new() };
+        self.items.push(ItemDraft { indent, depth, parent, item });
+        Ok(())
+    }
+
+    // Items were pushed depth first, so each one closes the deeper items before it
+    fn finish(self) -> ParsedTask {
+        let mut roots = Vec::new();
+        let mut open: Vec<ParsedItem> = Vec::new();
+        for draft in self.items {
+            while open.len() > draft.depth {
+                close(&mut open, &mut roots);
+            }
+            open.push(draft.item);
+        }
+        while !open.is_empty() {
+            close(&mut open, &mut roots);
+        }
+
+        let description = self.description.join("\n").trim().to_string();
+        ParsedTask {
+            id: None,
+            title: self.title.to_string(),
+            description: (!description.is_empty()).then_some(description),
+            items: roots,
+        }
+    }
+}
+
+fn close(open: &mut Vec<ParsedItem>, roots: &mut Vec<ParsedItem>) {
+    if let Some(item) = open.pop() {
+        match open.last_mut() {
+            Some(parent) => parent.subtasks.push(item),
+            None => roots.push(item),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn fixture(conn: &Connection) -> i64 {
+        let project_id = project::create_project(conn, "Home", Some("Chores")).unwrap();
+        let task_id = task::create_task(conn, project_id, "Kitchen", Some("Clean up\nbefore guests"), None, None).unwrap();
+        task::create_task(conn, project_id, "Empty", None, None, None).unwrap();
+        let item = |content: &str, parent_id: Option<i64>| {
+            let id = task_item::create_task_item(conn, content, None, None).unwrap();
+            task_item::link_task_item(conn, task_id, id).unwrap();
+            task_item::nest_task_item(conn, task_id, id, parent_id, None).unwrap();
+            id
+        };
+        let close = |id: i64, content: &str, status: TaskItemStatus| task_item::update_task_item_content(conn, id, content, status).unwrap();
+
+        item("Buy soap", None);
+        let mop = item("Mop", None);
+        close(mop, "Mop", TaskItemStatus::Blocked);
+        close(item("Find mop", Some(mop)), "Find mop", TaskItemStatus::Deferred);
+        close(item("Get bucket", Some(mop)), "Get bucket", TaskItemStatus::Completed);
+        let old = item("Old plan", None);
+        close(item("Old step", Some(old)), "Old step", TaskItemStatus::Completed);
+        close(old, "Old plan", TaskItemStatus::Canceled);
+        close(item("Dishes\nand glasses", None), "Dishes\nand glasses", TaskItemStatus::InProgress);
+        project_id
+    }
+
+    #[test]
+    fn exports_markers_and_nesting() {
+        let conn = db::test_connection();
+        let project_id = fixture(&conn);
+
+        let markdown = export_project(&conn, project_id, &MarkdownOptions::default()).unwrap();
+        assert_eq!(markdown, "\
+# Home
+
+## Kitchen
+
+- [ ] Buy soap
+- [!] Mop
+  - [x] Get bucket
+  - [>] Find mop
+- [/] Dishes
+      and glasses
+- [~] Old plan
+  - [x] Old step
+
+## Empty
+");
+    }
+
+    #[test]
+    fn exports_descriptions_and_hides_canceled_items_with_their_subtasks() {
+        let conn = db::test_connection();
+        let project_id = fixture(&conn);
+
+        let options = MarkdownOptions { include_descriptions: true, hide_canceled: true };
+        let markdown = export_project(&conn, project_id, &options).unwrap();
+        assert_eq!(markdown, "\
+# Home
+
+Chores
+
+## Kitchen
+
+Clean up
+before guests
+
+- [ ] Buy soap
+- [!] Mop
+  - [x] Get bucket
+  - [>] Find mop
+- [/] Dishes
+      and glasses
+
+## Empty
+");
+    }
+
+    // A task's title and description, and its items as (depth, status, content)
+    type Outline<'a> = (&'a str, Option<&'a str>, Vec<(usize, TaskItemStatus, &'a str)>);
+
+    fn outline(tasks: &[ParsedTask]) -> Vec<Outline<'_>> {
+        fn flatten<'a>(items: &'a [ParsedItem], depth: usize, out: &mut Vec<(usize, TaskItemStatus, &'a str)>) {
+            for item in items {
+                out.push((depth, item.status, item.content.as_str()));
+                flatten(&item.subtasks, depth + 1, out);
+            }
+        }
+        tasks.iter()
+            .map(|task| {
+                let mut items = Vec::new();
+                flatten(&task.items, 0, &mut items);
+                (task.title.as_str(), task.description.as_deref(), items)
+            })
+            .collect()
+    }
+
+    fn rejects(text: &str) -> String {
+        match parse(text) {
+            Err(AppError::Validation { field: "text", reason }) => reason,
+            other => panic!("expected {:?} to be rejected, got {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn skips_the_document_title_and_closing_hashes() {
+        let tasks = parse("# Home\n\nNot a description\n\n## C#\n## Kitchen ##\n### Pantry #\n").unwrap();
+        assert_eq!(outline(&tasks), [("C#", None, vec![]), ("Kitchen", None, vec![]), ("Pantry", None, vec![])]);
+
+        // A lone # heading is a task like any other
+        assert_eq!(outline(&parse("# Home\n- [ ] Sweep\n").unwrap()), [("Home", None, vec![(0, TaskItemStatus::Pending, "Sweep")])]);
+        assert_eq!(outline(&parse("# One\n# Two\n").unwrap()).len(), 2);
+        assert!(rejects("## ##\n").contains("line 1"));
+        assert!(rejects("Just text\n").contains("no headings"));
+    }
+
+    #[test]
+    fn reads_bullets_and_markers_and_leaves_fenced_blocks_alone() {
+        let text = "\
+## Kitchen
+Notes
+```
+- [ ] not an item
+# not a heading
+```
+- [ ] Dash
+* [x] Star
++ [/] Plus
+- [~] Canceled
+- [!] Blocked
+- [>] Deferred
+- plain bullet
+";
+        let tasks = parse(text).unwrap();
+        assert_eq!(outline(&tasks), [(
+            "Kitchen",
+            Some("Notes\n```\n- [ ] not an item\n# not a heading\n```\n- plain bullet"),
+            vec![
+                (0, TaskItemStatus::Pending, "Dash"),
+                (0, TaskItemStatus::Completed, "Star"),
+                (0, TaskItemStatus::InProgress, "Plus"),
+                (0, TaskItemStatus::Canceled, "Canceled"),
+                (0, TaskItemStatus::Blocked, "Blocked"),
+                (0, TaskItemStatus::Deferred, "Deferred"),
+            ],
+        )]);
+
+        assert!(rejects("## Kitchen\n- [?] Unknown\n").starts_with("line 2: unknown marker [?]"));
+        assert!(rejects("- [ ] Orphan\n## Kitchen\n").starts_with("line 1: checklist items need a heading"));
+        assert!(rejects("# Home\n- [ ] Under the title\n## Kitchen\n").starts_with("line 2:"));
+        assert!(rejects("## Kitchen\n- [ ]   \n").contains("without text"));
+    }
+
+    #[test]
+    fn nests_by_indentation_and_continues_content() {
+        let text = "\
+## Kitchen
+- [ ] Clean
+  - [x] Sink
+    scrub the drain
+  - [ ] Floor
+\t  - [ ] Corners
+- [ ] Shop
+   after lunch
+Back in the description
+";
+        assert_eq!(outline(&parse(text).unwrap()), [(
+            "Kitchen",
+            Some("Back in the description"),
+            vec![
+                (0, TaskItemStatus::Pending, "Clean"),
+                (1, TaskItemStatus::Completed, "Sink\nscrub the drain"),
+                (1, TaskItemStatus::Pending, "Floor"),
+                (2, TaskItemStatus::Pending, "Corners"),
+                (0, TaskItemStatus::Pending, "Shop\nafter lunch"),
+            ],
+        )]);
+
+        assert!(rejects("## Kitchen\n- [x] Done\n  - [ ] Open\n").starts_with("line 3: an unfinished item"));
+    }
+
+    #[test]
+    fn preview_writes_nothing_and_import_fills_in_ids() {
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "Home", None).unwrap();
+        let text = "## Kitchen\n- [x] Sink\n  - [x] Drain\n- [ ] Floor\n";
+
+        let preview = preview_import(&conn, project_id, text).unwrap();
+        assert!(preview.iter().all(|task| task.id.is_none()));
+        assert!(item_ids(&preview).is_empty());
+        assert!(task::get_tasks(&conn, project_id, SortOrder::Sequence, &[]).unwrap().is_empty());
+
+        let imported = import_project(&conn, project_id, text).unwrap();
+        let task_id = imported[0].id.unwrap();
+        assert_eq!(item_ids(&imported).len(), 3);
+        let items = task_item::get_task_items(&conn, task_id, &TaskItemFilter::default(), SortOrder::Sequence, None, None).unwrap().items;
+        let shape: Vec<(&str, TaskItemStatus, i64)> = items.iter().map(|item| (item.content.as_str(), item.status, item.depth)).collect();
+        assert_eq!(shape, [("Floor", TaskItemStatus::Pending, 0), ("Sink", TaskItemStatus::Completed, 0), ("Drain", TaskItemStatus::Completed, 1)]);
+        assert!(matches!(preview_import(&conn, 99, text), Err(AppError::NotFound { .. })));
+    }
+}
@@ -0,0 +1,468 @@
+use rusqlite::Connection;
+use std::fmt;
+
+// A single schema step. Released steps are never edited; every schema change
+// is appended as a new step so existing databases can be upgraded in order.
+struct Migration {
+    description: &'static str,
+    sql: &'static str,
+}
+
+// The schema version of a database is the number of steps applied to it,
+// stored in `PRAGMA user_version` (0 for a fresh or pre-migration file).
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        // Kept as `IF NOT EXISTS` so databases created before migrations existed
+        // (user_version 0 but tables present) are adopted as version 1
+        description: "baseline schema",
+        sql: "
+            CREATE TABLE IF NOT EXISTS projects (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                title TEXT NOT NULL,
+                description TEXT,
+                sequence INTEGER NOT NULL UNIQUE
+            );
+
+            CREATE TABLE IF NOT EXISTS tasks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                project_id INTEGER NOT NULL,
+                title TEXT NOT NULL,
+                description TEXT,
+                sequence INTEGER NOT NULL,
+                FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE,
+                UNIQUE(project_id, sequence)
+            );
+
+            CREATE TABLE IF NOT EXISTS task_items (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                content TEXT NOT NULL,
+                status INTEGER NOT NULL DEFAULT 0
+            );
+
+            -- TaskItem can be shared between multiple tasks
+            CREATE TABLE IF NOT EXISTS task_item_links (
+                task_id INTEGER NOT NULL,
+                item_id INTEGER NOT NULL,
+                sequence INTEGER,
+                PRIMARY KEY (task_id, item_id),
+                FOREIGN KEY(task_id) REFERENCES tasks(id),
+                FOREIGN KEY(item_id) REFERENCES task_items(id) ON DELETE CASCADE
+            );
+        ",
+    },
+    Migration {
+        // SQLite only rebuilds a table to change its constraints, so the links table is
+        // recreated with a cascade from tasks. Orphans left behind while foreign keys
+        // weren't enforced are removed first, otherwise the integrity check would fail.
+        description: "enforce foreign keys and cascade task deletes to links",
+        sql: "
+            DELETE FROM tasks WHERE project_id NOT IN (SELECT id FROM projects);
+            DELETE FROM task_item_links
+            WHERE task_id NOT IN (SELECT id FROM tasks)
+               OR item_id NOT IN (SELECT id FROM task_items);
+
+            CREATE TABLE task_item_links_new (
+                task_id INTEGER NOT NULL,
+                item_id INTEGER NOT NULL,
+                sequence INTEGER,
+                PRIMARY KEY (task_id, item_id),
+                FOREIGN KEY(task_id) REFERENCES tasks(id) ON DELETE CASCADE,
+                FOREIGN KEY(item_id) REFERENCES task_items(id) ON DELETE CASCADE
+            );
+            INSERT INTO task_item_links_new (task_id, item_id, sequence)
+                SELECT task_id, item_id, sequence FROM task_item_links;
+            DROP TABLE task_item_links;
+            ALTER TABLE task_item_links_new RENAME TO task_item_links;
+        ",
+    },
+    Migration {
+        // Trashed rows keep their sequence so live rows can't share the plain UNIQUE
+        // constraints any more; they become partial indexes over live rows instead
+        description: "soft delete with deleted_at",
+        sql: "
+            CREATE TABLE projects_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                title TEXT NOT NULL,
+                description TEXT,
+                sequence INTEGER NOT NULL,
+                deleted_at TEXT
+            );
+            INSERT INTO projects_new (id, title, description, sequence)
+                SELECT id, title, description, sequence FROM projects;
+            DROP TABLE projects;
+            ALTER TABLE projects_new RENAME TO projects;
+            CREATE UNIQUE INDEX projects_live_sequence ON projects(sequence) WHERE deleted_at IS NULL;
+
+            CREATE TABLE tasks_new (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                project_id INTEGER NOT NULL,
+                title TEXT NOT NULL,
+                description TEXT,
+                sequence INTEGER NOT NULL,
+                deleted_at TEXT,
+                FOREIGN KEY(project_id) REFERENCES projects(id) ON DELETE CASCADE
+            );
+            INSERT INTO tasks_new (id, project_id, title, description, sequence)
+                SELECT id, project_id, title, description, sequence FROM tasks;
+            DROP TABLE tasks;
+            ALTER TABLE tasks_new RENAME TO tasks;
+            CREATE UNIQUE INDEX tasks_live_sequence ON tasks(project_id, sequence) WHERE deleted_at IS NULL;
+
+            ALTER TABLE task_items ADD COLUMN deleted_at TEXT;
+        ",
+    },
+    Migration {
+        description: "undo/redo journal",
+        sql: "
+            CREATE TABLE journal (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                stack TEXT NOT NULL CHECK (stack IN ('undo', 'redo')),
+                label TEXT NOT NULL,
+                change TEXT NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            CREATE INDEX journal_stack ON journal(stack, id);
+        ",
+    },
+    Migration {
+        // ADD COLUMN can't default to datetime('now'), so the timestamps are set by the
+        // inserts; rows from before this step have no creation time and stay NULL
+        description: "timestamps and change history",
+        sql: "
+            ALTER TABLE projects ADD COLUMN created_at TEXT;
+            ALTER TABLE projects ADD COLUMN updated_at TEXT;
+            ALTER TABLE projects ADD COLUMN completed_at TEXT;
+            ALTER TABLE tasks ADD COLUMN created_at TEXT;
+            ALTER TABLE tasks ADD COLUMN updated_at TEXT;
+            ALTER TABLE tasks ADD COLUMN completed_at TEXT;
+            ALTER TABLE task_items ADD COLUMN created_at TEXT;
+            ALTER TABLE task_items ADD COLUMN updated_at TEXT;
+            ALTER TABLE task_items ADD COLUMN completed_at TEXT;
+
+            CREATE TABLE history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                entity TEXT NOT NULL,
+                entity_id INTEGER NOT NULL,
+                action TEXT NOT NULL,
+                old_value TEXT,
+                new_value TEXT,
+                changed_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+            CREATE INDEX history_entity ON history(entity, entity_id, id);
+        ",
+    },
+    Migration {
+        description: "start and due dates",
+        sql: "
+            ALTER TABLE tasks ADD COLUMN start_at TEXT;
+            ALTER TABLE tasks ADD COLUMN due_at TEXT;
+            ALTER TABLE task_items ADD COLUMN start_at TEXT;
+            ALTER TABLE task_items ADD COLUMN due_at TEXT;
+            CREATE INDEX tasks_due_at ON tasks(due_at) WHERE due_at IS NOT NULL;
+            CREATE INDEX task_items_due_at ON task_items(due_at) WHERE due_at IS NOT NULL;
+        ",
+    },
+    Migration {
+        description: "priorities",
+        sql: "
+            ALTER TABLE tasks ADD COLUMN priority INTEGER;
+            ALTER TABLE task_items ADD COLUMN priority INTEGER;
+        ",
+    },
+    Migration {
+        // One join table for every kind of row, told apart by `entity` ('project',
+        // 'task' or 'task_item'); it can't have a foreign key to the tagged row
+        description: "tags",
+        sql: "
+            CREATE TABLE tags (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                color TEXT NOT NULL
+            );
+            CREATE TABLE taggings (
+                tag_id INTEGER NOT NULL,
+                entity TEXT NOT NULL,
+                entity_id INTEGER NOT NULL,
+                PRIMARY KEY (tag_id, entity, entity_id),
+                FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
+            );
+            CREATE INDEX taggings_entity ON taggings(entity, entity_id);
+        ",
+    },
+    Migration {
+        // External-content FTS5 tables over the searchable columns, kept in sync by
+        // triggers and filled from the existing rows with 'rebuild'. Any later step that
+        // rebuilds one of these tables has to recreate its triggers.
+        description: "full-text search",
+        sql: "
+            CREATE VIRTUAL TABLE projects_fts USING fts5(
+                title, description, content='projects', content_rowid='id', tokenize='unicode61 remove_diacritics 2'
+            );
+            CREATE TRIGGER projects_fts_insert AFTER INSERT ON projects BEGIN
+                INSERT INTO projects_fts(rowid, title, description) VALUES (new.id, new.title, new.description);
+            END;
+            CREATE TRIGGER projects_fts_delete AFTER DELETE ON projects BEGIN
+                INSERT INTO projects_fts(projects_fts, rowid, title, description) VALUES ('delete', old.id, old.title, old.description);
+            END;
+            CREATE TRIGGER projects_fts_update AFTER UPDATE OF title, description ON projects BEGIN
+                INSERT INTO projects_fts(projects_fts, rowid, title, description) VALUES ('delete', old.id, old.title, old.description);
+                INSERT INTO projects_fts(rowid, title, description) VALUES (new.id, new.title, new.description);
+            END;
+            INSERT INTO projects_fts(projects_fts) VALUES ('rebuild');
+
+            CREATE VIRTUAL TABLE tasks_fts USING fts5(
+                title, description, content='tasks', content_rowid='id', tokenize='unicode61 remove_diacritics 2'
+            );
+            CREATE TRIGGER tasks_fts_insert AFTER INSERT ON tasks BEGIN
+                INSERT INTO tasks_fts(rowid, title, description) VALUES (new.id, new.title, new.description);
+            END;
+            CREATE TRIGGER tasks_fts_delete AFTER DELETE ON tasks BEGIN
+                INSERT INTO tasks_fts(tasks_fts, rowid, title, description) VALUES ('delete', old.id, old.title, old.description);
+            END;
+            CREATE TRIGGER tasks_fts_update AFTER UPDATE OF title, description ON tasks BEGIN
+                INSERT INTO tasks_fts(tasks_fts, rowid, title, description) VALUES ('delete', old.id, old.title, old.description);
+                INSERT INTO tasks_fts(rowid, title, description) VALUES (new.id, new.title, new.description);
+            END;
+            INSERT INTO tasks_fts(tasks_fts) VALUES ('rebuild');
+
+            CREATE VIRTUAL TABLE task_items_fts USING fts5(
+                content, content='task_items', content_rowid='id', tokenize='unicode61 remove_diacritics 2'
+            );
+            CREATE TRIGGER task_items_fts_insert AFTER INSERT ON task_items BEGIN
+                INSERT INTO task_items_fts(rowid, content) VALUES (new.id, new.content);
+            END;
+            CREATE TRIGGER task_items_fts_delete AFTER DELETE ON task_items BEGIN
+                INSERT INTO task_items_fts(task_items_fts, rowid, content) VALUES ('delete', old.id, old.content);
+            END;
+            CREATE TRIGGER task_items_fts_update AFTER UPDATE OF content ON task_items BEGIN
+                INSERT INTO task_items_fts(task_items_fts, rowid, content) VALUES ('delete', old.id, old.content);
+                INSERT INTO task_items_fts(rowid, content) VALUES (new.id, new.content);
+            END;
+            INSERT INTO task_items_fts(task_items_fts) VALUES ('rebuild');
+        ",
+    },
+    Migration {
+        // Integer sequences had to be renumbered on every move; fractional ranks are
+        // written once per move (see rank.rs). Existing sequences become the first ranks.
+        description: "fractional ranks",
+        sql: "
+            ALTER TABLE projects ADD COLUMN rank REAL NOT NULL DEFAULT 0;
+            UPDATE projects SET rank = sequence;
+            DROP INDEX projects_live_sequence;
+            ALTER TABLE projects DROP COLUMN sequence;
+            CREATE INDEX projects_live_rank ON projects(rank) WHERE deleted_at IS NULL;
+
+            ALTER TABLE tasks ADD COLUMN rank REAL NOT NULL DEFAULT 0;
+            UPDATE tasks SET rank = sequence;
+            DROP INDEX tasks_live_sequence;
+            ALTER TABLE tasks DROP COLUMN sequence;
+            CREATE INDEX tasks_live_rank ON tasks(project_id, rank) WHERE deleted_at IS NULL;
+
+            ALTER TABLE task_item_links ADD COLUMN rank REAL;
+            UPDATE task_item_links SET rank = sequence;
+            ALTER TABLE task_item_links DROP COLUMN sequence;
+            CREATE INDEX task_item_links_rank ON task_item_links(task_id, rank) WHERE rank IS NOT NULL;
+        ",
+    },
+    Migration {
+        // Nesting belongs to the link, so a shared item can sit at different depths in
+        // different tasks. Ranks now order siblings under the same parent.
+        description: "nested task items",
+        sql: "
+            ALTER TABLE task_item_links ADD COLUMN parent_id INTEGER;
+            DROP INDEX task_item_links_rank;
+            CREATE INDEX task_item_links_rank ON task_item_links(task_id, parent_id, rank) WHERE rank IS NOT NULL;
+            CREATE INDEX task_item_links_parent ON task_item_links(parent_id) WHERE parent_id IS NOT NULL;
+        ",
+    },
+    Migration {
+        // Edges outlive a trashed task (it may be restored) and go with it when it's purged
+        description: "task dependencies",
+        sql: "
+            CREATE TABLE task_dependencies (
+                task_id INTEGER NOT NULL,
+                depends_on_id INTEGER NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                PRIMARY KEY (task_id, depends_on_id),
+                FOREIGN KEY(task_id) REFERENCES tasks(id) ON DELETE CASCADE,
+                FOREIGN KEY(depends_on_id) REFERENCES tasks(id) ON DELETE CASCADE,
+                CHECK (task_id != depends_on_id)
+            );
+            CREATE INDEX task_dependencies_depends_on ON task_dependencies(depends_on_id);
+        ",
+    },
+    Migration {
+        // `recurred_id` is the occurrence spawned when the row was completed, so completing
+        // it again (after a reopen or an undo) doesn't spawn a second one
+        description: "recurrence",
+        sql: "
+            ALTER TABLE tasks ADD COLUMN recurrence TEXT;
+            ALTER TABLE tasks ADD COLUMN recurred_id INTEGER REFERENCES tasks(id) ON DELETE SET NULL;
+            ALTER TABLE task_items ADD COLUMN recurrence TEXT;
+            ALTER TABLE task_items ADD COLUMN recurred_id INTEGER REFERENCES task_items(id) ON DELETE SET NULL;
+        ",
+    },
+];
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    // The file was written by a newer build; opening it could corrupt data we don't know about
+    TooNew { found: i64, supported: i64 },
+    Failed { version: i64, description: &'static str, source: rusqlite::Error },
+    // The upgraded database still has rows pointing at missing parents
+    ForeignKeyViolation { version: i64, table: String },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sqlite(e) => write!(f, "{}", e),
+            MigrationError::TooNew { found, supported } => write!(
+                f,
+                "database schema version {} is newer than the supported version {}",
+                found, supported
+            ),
+            MigrationError::Failed { version, description, source } => write!(
+                f,
+                "migration {} ({}) failed: {}",
+                version, description, source
+            ),
+            MigrationError::ForeignKeyViolation { version, table } => write!(
+                f,
+                "after migration {} rows in {} still violate a foreign key",
+                version, table
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Sqlite(e)
+    }
+}
+
+pub fn schema_version(conn: &Connection) -> Result<i64, MigrationError> {
+    let version = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    Ok(version)
+}
+
+// Bring the database up to the last step in MIGRATIONS, one transaction per step, so a
+// failing step leaves the file at the last version that fully applied.
+// Foreign keys are switched off while steps run, since rebuilding a table means
+// dropping it while other tables still reference it; the result is checked instead.
+pub fn migrate(conn: &mut Connection) -> Result<i64, MigrationError> {
+    run(conn, MIGRATIONS)
+}
+
+fn run(conn: &mut Connection, migrations: &[Migration]) -> Result<i64, MigrationError> {
+    let latest = migrations.len() as i64;
+    let current = schema_version(conn)?;
+    if current > latest {
+        return Err(MigrationError::TooNew { found: current, supported: latest });
+    }
+
+    conn.pragma_update(None, "foreign_keys", false)?;
+
+    for (index, migration) in migrations.iter().enumerate().skip(current as usize) {
+        let version = index as i64 + 1;
+        let failed = |source| MigrationError::Failed { version, description: migration.description, source };
+
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration.sql).map_err(failed)?;
+
+        // Intermediate versions may still hold rows a later step cleans up, so
+        // integrity is only required of the schema this build runs against
+        if version == latest {
+            if let Some(table) = first_foreign_key_violation(&tx).map_err(failed)? {
+                return Err(MigrationError::ForeignKeyViolation { version, table });
+            }
+        }
+        tx.pragma_update(None, "user_version", version).map_err(failed)?;
+        tx.commit()?;
+    }
+
+    Ok(latest)
+}
+
+fn first_foreign_key_violation(conn: &Connection) -> rusqlite::Result<Option<String>> {
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check")?;
+    let mut rows = stmt.query([])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATEST: i64 = MIGRATIONS.len() as i64;
+
+    fn count(conn: &Connection, sql: &str) -> i64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn upgrades_baseline_schema_and_drops_orphan_links() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // A pre-migration file: version 0, foreign keys never enforced
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        conn.execute_batch(MIGRATIONS[0].sql).unwrap();
+        conn.execute_batch(
+            "INSERT INTO projects (title, sequence) VALUES ('a', 1), ('b', 2);
+             INSERT INTO tasks (project_id, title, sequence) VALUES (1, 't1', 1), (9, 'orphan', 1), (2, 't2', 1);
+             INSERT INTO task_items (content) VALUES ('i1'), ('i2');
+             INSERT INTO task_item_links (task_id, item_id, sequence) VALUES (1, 1, 1), (2, 2, 1), (3, 2, 1), (77, 1, 2);",
+        )
+        .unwrap();
+
+        assert_eq!(migrate(&mut conn).unwrap(), LATEST);
+        assert_eq!(schema_version(&conn).unwrap(), LATEST);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM tasks"), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM task_item_links"), 2);
+        assert!(first_foreign_key_violation(&conn).unwrap().is_none());
+
+        // Running again is a no-op
+        assert_eq!(migrate(&mut conn).unwrap(), LATEST);
+    }
+
+    #[test]
+    fn refuses_newer_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", LATEST + 1).unwrap();
+
+        match migrate(&mut conn) {
+            Err(MigrationError::TooNew { found, supported }) => {
+                assert_eq!(found, LATEST + 1);
+                assert_eq!(supported, LATEST);
+            }
+            other => panic!("expected TooNew, got {:?}", other),
+        }
+        assert_eq!(schema_version(&conn).unwrap(), LATEST + 1);
+    }
+
+    #[test]
+    fn failing_step_rolls_back() {
+        let steps = [
+            Migration { description: "create", sql: "CREATE TABLE a (id INTEGER PRIMARY KEY);" },
+            Migration {
+                description: "half applied",
+                sql: "CREATE TABLE b (id INTEGER PRIMARY KEY); INSERT INTO missing VALUES (1);",
+            },
+        ];
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        match run(&mut conn, &steps) {
+            Err(MigrationError::Failed { version, .. }) => assert_eq!(version, 2),
+            other => panic!("expected Failed, got {:?}", other),
+        }
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sqlite_master WHERE name = 'a'"), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM sqlite_master WHERE name = 'b'"), 0);
+    }
+}
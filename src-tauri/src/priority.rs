@@ -0,0 +1,77 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, Result};
+
+// Crosses IPC as "P0".."P3" and is stored as 0..3; P0 is the most urgent.
+// Tasks and items without a priority store NULL and sort after P3.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    P0 = 0,
+    P1 = 1,
+    P2 = 2,
+    P3 = 3,
+}
+
+impl TryFrom<i64> for Priority {
+    type Error = AppError;
+
+    fn try_from(value: i64) -> Result<Self> {
+        match value {
+            0 => Ok(Priority::P0),
+            1 => Ok(Priority::P1),
+            2 => Ok(Priority::P2),
+            3 => Ok(Priority::P3),
+            _ => Err(AppError::validation("priority", format!("unknown priority value {}", value))),
+        }
+    }
+}
+
+impl From<Priority> for i64 {
+    fn from(priority: Priority) -> Self {
+        priority as i64
+    }
+}
+
+impl FromSql for Priority {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let raw = value.as_i64()?;
+        Priority::try_from(raw).map_err(|_| FromSqlError::OutOfRange(raw))
+    }
+}
+
+impl ToSql for Priority {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(i64::from(*self)))
+    }
+}
+
+// How get_tasks / get_task_items order their rows. Sorting by priority or due date only
+// changes the order they're returned in, the stored ranks stay as the user arranged them.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Sequence,
+    Priority,
+    // Soonest due first, undated last
+    Due,
+}
+
+impl SortOrder {
+    // ORDER BY terms for a table (or alias) with `priority` and `due_at` columns, whose
+    // manual order is in `rank_table`.rank; ties fall back to that order
+    pub fn order_by(self, row_table: &str, rank_table: &str) -> String {
+        match self {
+            SortOrder::Sequence => format!("{}.rank", rank_table),
+            SortOrder::Priority => format!(
+                "{0}.priority IS NULL, {0}.priority, {1}.rank",
+                row_table, rank_table
+            ),
+            SortOrder::Due => format!(
+                "{0}.due_at IS NULL, {0}.due_at, {1}.rank",
+                row_table, rank_table
+            ),
+        }
+    }
+}
@@ -1,119 +1,153 @@
-use rusqlite::{Connection, Result, Error};
+use rusqlite::Connection;
 use serde::Serialize;
+use serde_json::json;
 
-pub const DB_PATH: &str = "yuno-tasks.db";
+use crate::entity::Entity;
+use crate::error::{expect_changed, require_text, OrNotFound, Result};
+use crate::history::{self, Action};
+use crate::rank::{self, Placement, Scope};
+use crate::tag;
 
 #[derive(Debug, Serialize)]
 pub struct Project {
     pub id: i64,
     pub title: String,
     pub description: Option<String>,
-    pub sequence: i64
+    // Position among the live projects, from 1
+    pub sequence: i64,
+    // NULL for projects created before timestamps were tracked
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    // Set once the project has tasks and all of them are complete
+    pub completed_at: Option<String>
 }
+impl Project {
+    const COLUMNS: &'static str = "id, title, description,
+        (SELECT COUNT(*) FROM projects AS earlier WHERE earlier.deleted_at IS NULL AND (earlier.rank, earlier.id) <= (projects.rank, projects.id)),
+        created_at, updated_at, completed_at";
 
-pub fn create_project(title: &str, description: Option<&str>) -> Result<i64> {
-    let conn = Connection::open(DB_PATH)?;
-
-    // Get the highest current sequence number
-    let max_sequence: i64 = conn.query_row(
-        "SELECT COALESCE(MAX(sequence), 0) FROM projects",
-        [],
-        |row| row.get(0)
-    )?;
-
-    conn.execute(
-        "INSERT INTO projects (title, description, sequence) VALUES (?1, ?2, ?3)",
-        (title, description, max_sequence + 1)
-    )?;
-    Ok(conn.last_insert_rowid())
-}
-
-pub fn get_all_projects() -> Result<Vec<Project>> {
-    let conn = Connection::open(DB_PATH)?;
-    let mut stmt = conn.prepare("SELECT id, title, description, sequence FROM projects ORDER BY sequence")?;
-
-    let projects: Vec<Project> = stmt.query_map([], |row| {
+    pub fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
         Ok(Project {
             id: row.get(0)?,
             title: row.get(1)?,
             description: row.get(2)?,
             sequence: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            completed_at: row.get(6)?,
         })
-    })?.collect::<Result<Vec<Project>, _>>()?;
+    }
+}
 
-    Ok(projects)
+// What a project delete took into the trash with it; they come back on restore
+// and are only removed for good (through the foreign key cascades) on purge
+#[derive(Debug, Serialize)]
+pub struct DeletedProject {
+    pub tasks: usize,
+    // Distinct items linked to the project's tasks; items shared with
+    // other projects stay visible there
+    pub task_items: usize
 }
 
-pub fn update_project_content(project_id: i64, title: &str, description: Option<&str>) -> Result<()> {
-    let conn = Connection::open(DB_PATH)?;
+pub fn create_project(conn: &Connection, title: &str, description: Option<&str>) -> Result<i64> {
+    require_text("title", title)?;
 
-    conn.execute(
-        "UPDATE projects SET title = ?1, description = ?2 WHERE id = ?3",
-        (title, description, project_id)
-    )?;
+    conn.prepare_cached(
+        "INSERT INTO projects (title, description, rank, created_at, updated_at)
+         VALUES (?1, ?2, ?3, datetime('now'), datetime('now'))"
+    )?.execute((title, description, rank::next(conn, Scope::Projects)?))?;
+    let id = conn.last_insert_rowid();
 
-    Ok(())
+    history::record(conn, Entity::Project, id, Action::Created, None, Some(json!({ "title": title, "description": description })))?;
+    Ok(id)
 }
 
-// When updating the sequence of a project, ensure that the sequences of all other projects also happen
-pub fn update_project_sequence(project_id: i64, new_sequence: i64) -> Result<(), Error> {
-    let mut conn = Connection::open(DB_PATH)?;
-    let tx = conn.transaction()?;
-
-    // Make copy of projects in array
-    let mut projects = tx.prepare("SELECT id, sequence FROM projects ORDER BY sequence")?
-        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
-        .collect::<Result<Vec<(i64, i64)>>>()?;
-
-    // Find the project and move to new position
-    let index = projects.iter().position(|(id, _)| *id == project_id).ok_or_else(|| Error::InvalidQuery)?;
-    let (_, old_sequence) = projects.remove(index);
-    projects.insert(new_sequence as usize - 1, (project_id, old_sequence));
-
-    // Step 1: Temporarily set sequences to negative values
-    for (new_index, (id, _)) in projects.iter().enumerate() {
-        let temp_seq = -(new_index as i64 + 1);
-        tx.execute(
-            "UPDATE projects SET sequence = ?1 WHERE id = ?2",
-            (temp_seq, *id)
-        )?;
-    }
+// Only projects carrying all of `tags`, when any are given
+pub fn get_all_projects(conn: &Connection, tags: &[i64]) -> Result<Vec<Project>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {} FROM projects WHERE deleted_at IS NULL AND {} ORDER BY rank, id",
+        Project::COLUMNS, tag::filter_condition(Entity::Project, "projects.id", "?1")
+    ))?;
 
-    // Step 2: Set sequences to their final positive values
-    for (new_index, (id, _)) in projects.iter().enumerate() {
-        let final_seq = (new_index + 1) as i64;
-        tx.execute(
-            "UPDATE projects SET sequence = ?1 WHERE id = ?2",
-            (final_seq, *id)
-        )?;
-    }
+    let projects: Vec<Project> = stmt.query_map([tag::filter_param(tags)], Project::from_row)?
+        .collect::<rusqlite::Result<Vec<Project>>>()?;
 
-    tx.commit()?;
-    Ok(())
+    Ok(projects)
 }
 
-pub fn get_project(id: i64) -> Result<Project> {
-    let conn = Connection::open(DB_PATH)?;
-
-    let project = conn.query_row(
-        "SELECT id, title, description, sequence FROM projects WHERE id = ?1",
-        [id],
-        |row| {
-            Ok(Project {
-                id: row.get(0)?,
-                title: row.get(1)?,
-                description: row.get(2)?,
-                sequence: row.get(3)?,
-            })
-        }
-    )?;
+pub fn update_project_content(conn: &Connection, project_id: i64, title: &str, description: Option<&str>) -> Result<()> {
+    require_text("title", title)?;
+    let old = get_project(conn, project_id)?;
+
+    let changed = conn.prepare_cached("UPDATE projects SET title = ?1, description = ?2, updated_at = datetime('now') WHERE id = ?3 AND deleted_at IS NULL")?
+        .execute((title, description, project_id))?;
+    expect_changed(changed, Entity::Project, project_id)?;
+
+    history::record(
+        conn, Entity::Project, project_id, Action::Edited,
+        Some(json!({ "title": old.title, "description": old.description })),
+        Some(json!({ "title": title, "description": description })),
+    )
+}
+
+// Moves the project within the list of live projects
+pub fn move_project(conn: &Connection, project_id: i64, to: Placement) -> Result<()> {
+    let old = get_project(conn, project_id)?;
+    rank::move_row(conn, Scope::Projects, project_id, to)?;
+
+    conn.prepare_cached("UPDATE projects SET updated_at = datetime('now') WHERE id = ?1")?
+        .execute([project_id])?;
+    let new = get_project(conn, project_id)?;
+    history::record(conn, Entity::Project, project_id, Action::Moved, Some(json!({ "sequence": old.sequence })), Some(json!({ "sequence": new.sequence })))
+}
+
+pub fn get_project(conn: &Connection, id: i64) -> Result<Project> {
+    let project = conn.prepare_cached(&format!("SELECT {} FROM projects WHERE id = ?1 AND deleted_at IS NULL", Project::COLUMNS))?
+        .query_row([id], Project::from_row)
+        .or_not_found(Entity::Project, id)?;
 
     Ok(project)
 }
 
-pub fn delete_project(id: i64) -> Result<()> {
-    let conn = Connection::open(DB_PATH)?;
+// Moves the project to the trash; its tasks are hidden along with it
+pub fn delete_project(conn: &Connection, id: i64) -> Result<DeletedProject> {
+    let tasks: usize = conn.prepare_cached("SELECT COUNT(*) FROM tasks WHERE project_id = ?1 AND deleted_at IS NULL")?
+        .query_row([id], |row| row.get(0))?;
+    let task_items: usize = conn.prepare_cached(
+        "SELECT COUNT(DISTINCT task_item_links.item_id)
+         FROM task_item_links
+         JOIN tasks ON tasks.id = task_item_links.task_id
+         JOIN task_items ON task_items.id = task_item_links.item_id
+         WHERE tasks.project_id = ?1 AND tasks.deleted_at IS NULL AND task_items.deleted_at IS NULL"
+    )?.query_row([id], |row| row.get(0))?;
+
+    let changed = conn.prepare_cached("UPDATE projects SET deleted_at = datetime('now') WHERE id = ?1 AND deleted_at IS NULL")?
+        .execute([id])?;
+    expect_changed(changed, Entity::Project, id)?;
+    history::record(conn, Entity::Project, id, Action::Trashed, None, None)?;
+
+    Ok(DeletedProject { tasks, task_items })
+}
+
+// Takes the project out of the trash and appends it after the live projects
+pub fn restore_project(conn: &Connection, id: i64) -> Result<()> {
+    let changed = conn.prepare_cached("UPDATE projects SET deleted_at = NULL, rank = ?1 WHERE id = ?2 AND deleted_at IS NOT NULL")?
+        .execute((rank::next(conn, Scope::Projects)?, id))?;
+    expect_changed(changed, Entity::Project, id)?;
+
+    history::record(conn, Entity::Project, id, Action::Restored, None, None)
+}
 
-    conn.execute("DELETE FROM projects WHERE id = ?1", [id])?;
+// A project is complete once it has live tasks and every one of them is complete.
+// Keeps an existing completed_at rather than moving it on every refresh.
+pub fn refresh_completion(conn: &Connection, id: i64) -> Result<()> {
+    conn.prepare_cached(
+        "UPDATE projects SET completed_at = CASE
+             WHEN EXISTS (SELECT 1 FROM tasks WHERE project_id = ?1 AND deleted_at IS NULL)
+              AND NOT EXISTS (SELECT 1 FROM tasks WHERE project_id = ?1 AND deleted_at IS NULL AND completed_at IS NULL)
+             THEN COALESCE(completed_at, datetime('now'))
+             END
+         WHERE id = ?1"
+    )?.execute([id])?;
     Ok(())
 }
@@ -0,0 +1,284 @@
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::entity::Entity;
+use crate::error::{AppError, Result};
+
+// Projects, tasks and open item links are ordered by a REAL `rank` column. A move
+// writes a single rank halfway between the new neighbours, so the rest of the list
+// is left alone. Only when two neighbours get too close for a float to fall between
+// them is the list renumbered 1..n. The 1-based `sequence` the frontend sees is the
+// row's position in that order; it has no gaps and is computed on read.
+
+// One ordered list
+#[derive(Debug, Clone, Copy)]
+pub enum Scope {
+    // All live projects
+    Projects,
+    // The live tasks of a project
+    Tasks(i64),
+    // The open items of a task under one parent item (None for the top level);
+    // closed ones are linked with a NULL rank
+    Links { task_id: i64, parent_id: Option<i64> },
+}
+
+impl Scope {
+    fn table(self) -> &'static str {
+        match self {
+            Scope::Projects => "projects",
+            Scope::Tasks(_) => "tasks",
+            Scope::Links { .. } => "task_item_links",
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Scope::Links { .. } => "item_id",
+            _ => "id",
+        }
+    }
+
+    // Keeps the rows of the list, with the ids from `group` bound as ?1 and ?2
+    fn condition(self) -> &'static str {
+        match self {
+            Scope::Projects => "?1 IS NULL AND ?2 IS NULL AND deleted_at IS NULL",
+            Scope::Tasks(_) => "project_id = ?1 AND ?2 IS NULL AND deleted_at IS NULL",
+            Scope::Links { .. } => "task_id = ?1 AND parent_id IS ?2 AND rank IS NOT NULL",
+        }
+    }
+
+    fn group(self) -> (Option<i64>, Option<i64>) {
+        match self {
+            Scope::Projects => (None, None),
+            Scope::Tasks(project_id) => (Some(project_id), None),
+            Scope::Links { task_id, parent_id } => (Some(task_id), parent_id),
+        }
+    }
+
+    fn entity(self) -> Entity {
+        match self {
+            Scope::Projects => Entity::Project,
+            Scope::Tasks(_) => Entity::Task,
+            Scope::Links { .. } => Entity::TaskItem,
+        }
+    }
+}
+
+// Where a moved row ends up within its list
+#[derive(Debug, Clone, Copy)]
+pub enum Placement {
+    // 1-based, counted as if the row were already there
+    Position(i64),
+    Before(i64),
+    After(i64),
+}
+
+// A rank strictly between the two, None when floats can't split them any further
+fn between(before: Option<f64>, after: Option<f64>) -> Option<f64> {
+    match (before, after) {
+        (None, None) => Some(1.0),
+        (Some(before), None) => Some(before + 1.0),
+        (None, Some(after)) => Some(after - 1.0),
+        (Some(before), Some(after)) => {
+            let middle = before + (after - before) / 2.0;
+            (before < middle && middle < after).then_some(middle)
+        }
+    }
+}
+
+// Rank for a row appended after the rest of the list
+pub fn next(conn: &Connection, scope: Scope) -> Result<f64> {
+    let max_rank: f64 = conn.prepare_cached(&format!(
+        "SELECT COALESCE(MAX(rank), 0) FROM {} WHERE {}",
+        scope.table(), scope.condition()
+    ))?.query_row(scope.group(), |row| row.get(0))?;
+    Ok(max_rank + 1.0)
+}
+
+// 1-based position of the row, None if it isn't part of the list
+pub fn position(conn: &Connection, scope: Scope, id: i64) -> Result<Option<i64>> {
+    let (group, subgroup) = scope.group();
+    let position: i64 = conn.prepare_cached(&format!(
+        "SELECT COUNT(*) FROM {table} WHERE {condition}
+         AND (rank, {key}) <= (SELECT rank, {key} FROM {table} WHERE {condition} AND {key} = ?3)",
+        table = scope.table(), key = scope.key(), condition = scope.condition()
+    ))?.query_row((group, subgroup, id), |row| row.get(0))?;
+    Ok((position > 0).then_some(position))
+}
+
+fn count(conn: &Connection, scope: Scope) -> Result<i64> {
+    let count = conn.prepare_cached(&format!("SELECT COUNT(*) FROM {} WHERE {}", scope.table(), scope.condition()))?
+        .query_row(scope.group(), |row| row.get(0))?;
+    Ok(count)
+}
+
+// Checks a position for a row about to join the list (`joining`) or already in it
+pub fn require_position(conn: &Connection, scope: Scope, field: &'static str, position: i64, joining: bool) -> Result<()> {
+    let places = count(conn, scope)? + i64::from(joining);
+    if position < 1 || position > places {
+        return Err(AppError::validation(field, format!("must be between 1 and {}", places)));
+    }
+    Ok(())
+}
+
+// Ranks of the rows `id` would sit between, leaving `id` itself out
+fn neighbours(conn: &Connection, scope: Scope, id: i64, to: Placement) -> Result<(Option<f64>, Option<f64>)> {
+    let (table, key, condition) = (scope.table(), scope.key(), scope.condition());
+    let (group, subgroup) = scope.group();
+
+    match to {
+        Placement::Position(position) => {
+            let ranks = conn.prepare_cached(&format!(
+                "SELECT rank FROM {table} WHERE {condition} AND {key} != ?3 ORDER BY rank, {key} LIMIT 2 OFFSET ?4",
+                table = table, key = key, condition = condition
+            ))?
+                .query_map((group, subgroup, id, (position - 2).max(0)), |row| row.get(0))?
+                .collect::<rusqlite::Result<Vec<f64>>>()?;
+
+            if position == 1 {
+                Ok((None, ranks.first().copied()))
+            } else {
+                Ok((ranks.first().copied(), ranks.get(1).copied()))
+            }
+        }
+        Placement::Before(anchor) | Placement::After(anchor) => {
+            let (comparison, direction) = match to {
+                Placement::Before(_) => ("<", "DESC"),
+                _ => (">", "ASC"),
+            };
+            let anchor_rank: f64 = conn.prepare_cached(&format!(
+                "SELECT rank FROM {} WHERE {} AND {} = ?3",
+                table, condition, key
+            ))?
+                .query_row((group, subgroup, anchor), |row| row.get(0))
+                .optional()?
+                .ok_or(AppError::NotFound { entity: scope.entity(), id: anchor })?;
+            let beyond: Option<f64> = conn.prepare_cached(&format!(
+                "SELECT rank FROM {table} WHERE {condition} AND {key} != ?3
+                 AND (rank, {key}) {comparison} (?4, ?5)
+                 ORDER BY rank {direction}, {key} {direction} LIMIT 1",
+                table = table, key = key, condition = condition, comparison = comparison, direction = direction
+            ))?
+                .query_row((group, subgroup, id, anchor_rank, anchor), |row| row.get(0))
+                .optional()?;
+
+            match to {
+                Placement::Before(_) => Ok((beyond, Some(anchor_rank))),
+                _ => Ok((Some(anchor_rank), beyond)),
+            }
+        }
+    }
+}
+
+// Moves a row of the list; a single rank is written unless the list has to be renumbered
+pub fn move_row(conn: &Connection, scope: Scope, id: i64, to: Placement) -> Result<()> {
+    if position(conn, scope, id)?.is_none() {
+        return Err(AppError::NotFound { entity: scope.entity(), id });
+    }
+    match to {
+        Placement::Position(position) => require_position(conn, scope, "sequence", position, false)?,
+        Placement::Before(anchor) | Placement::After(anchor) if anchor == id => {
+            return Err(AppError::validation("anchor_id", "can't move a row next to itself"));
+        }
+        _ => {}
+    }
+
+    let (before, after) = neighbours(conn, scope, id, to)?;
+    let rank = match between(before, after) {
+        Some(rank) => rank,
+        None => {
+            renumber(conn, scope)?;
+            let (before, after) = neighbours(conn, scope, id, to)?;
+            between(before, after).ok_or(AppError::Storage { message: "no rank left between neighbours".to_string() })?
+        }
+    };
+
+    let (group, subgroup) = scope.group();
+    conn.prepare_cached(&format!("UPDATE {} SET rank = ?3 WHERE {} AND {} = ?4", scope.table(), scope.condition(), scope.key()))?
+        .execute((group, subgroup, rank, id))?;
+    Ok(())
+}
+
+// Spreads the list back out to ranks 1..n, keeping its order
+fn renumber(conn: &Connection, scope: Scope) -> Result<()> {
+    conn.prepare_cached(&format!(
+        "UPDATE {table} SET rank = ordered.position
+         FROM (
+             SELECT {key} AS key, ROW_NUMBER() OVER (ORDER BY rank, {key}) AS position
+             FROM {table} WHERE {condition}
+         ) AS ordered
+         WHERE {table}.{key} = ordered.key AND {condition}",
+        table = scope.table(), key = scope.key(), condition = scope.condition()
+    ))?.execute(scope.group())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, project, task, task_item};
+
+    fn titles(conn: &Connection) -> Vec<String> {
+        conn.prepare("SELECT title FROM projects WHERE deleted_at IS NULL ORDER BY rank, id").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn positions_outside_the_list_are_rejected() {
+        let conn = db::test_connection();
+        let [first, _, third] = ["A", "B", "C"].map(|title| project::create_project(&conn, title, None).unwrap());
+
+        for position in [0, 4] {
+            let moved = move_row(&conn, Scope::Projects, first, Placement::Position(position));
+            assert!(matches!(moved, Err(AppError::Validation { field: "sequence", .. })), "{}", position);
+        }
+        // One more place when the row is joining the list
+        assert!(require_position(&conn, Scope::Projects, "position", 4, true).is_ok());
+        assert!(require_position(&conn, Scope::Projects, "position", 5, true).is_err());
+        assert!(matches!(move_row(&conn, Scope::Projects, first, Placement::After(first)), Err(AppError::Validation { .. })));
+
+        move_row(&conn, Scope::Projects, first, Placement::Position(3)).unwrap();
+        assert_eq!(titles(&conn), ["B", "C", "A"]);
+        move_row(&conn, Scope::Projects, first, Placement::Before(third)).unwrap();
+        assert_eq!(titles(&conn), ["B", "A", "C"]);
+        assert_eq!(position(&conn, Scope::Projects, first).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn anchors_must_share_the_scope() {
+        let conn = db::test_connection();
+        let [home, work] = ["Home", "Work"].map(|title| project::create_project(&conn, title, None).unwrap());
+        let chores = task::create_task(&conn, home, "Chores", None, None, None).unwrap();
+        let report = task::create_task(&conn, work, "Report", None, None, None).unwrap();
+        let moved = move_row(&conn, Scope::Tasks(home), chores, Placement::Before(report));
+        assert!(matches!(moved, Err(AppError::NotFound { entity: Entity::Task, id }) if id == report));
+
+        let [parent, child, sibling] = ["Parent", "Child", "Sibling"].map(|content| {
+            let id = task_item::create_task_item(&conn, content, None, None).unwrap();
+            task_item::link_task_item(&conn, chores, id).unwrap();
+            id
+        });
+        task_item::indent_task_item(&conn, chores, child).unwrap();
+        let nested = Scope::Links { task_id: chores, parent_id: Some(parent) };
+        let moved = move_row(&conn, nested, child, Placement::After(sibling));
+        assert!(matches!(moved, Err(AppError::NotFound { entity: Entity::TaskItem, id }) if id == sibling));
+        // Nor can a row be moved through a list it isn't part of
+        let top = Scope::Links { task_id: chores, parent_id: None };
+        assert!(matches!(move_row(&conn, top, child, Placement::Position(1)), Err(AppError::NotFound { .. })));
+    }
+
+    #[test]
+    fn collapsed_ranks_are_renumbered() {
+        let conn = db::test_connection();
+        let [_, second, third] = ["A", "B", "C"].map(|title| project::create_project(&conn, title, None).unwrap());
+        // No float left between the first two
+        conn.execute("UPDATE projects SET rank = ?1 WHERE id = ?2", (1.0 + f64::EPSILON, second)).unwrap();
+
+        move_row(&conn, Scope::Projects, third, Placement::Position(2)).unwrap();
+        assert_eq!(titles(&conn), ["A", "C", "B"]);
+        let ranks: Vec<f64> = conn.prepare("SELECT rank FROM projects ORDER BY rank").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(ranks, [1.0, 1.5, 2.0]);
+    }
+}
@@ -0,0 +1,462 @@
+use rusqlite::Connection;
+use std::fmt;
+
+use crate::error::{AppError, Result};
+use crate::schedule;
+
+// A subset of iCalendar RRULE, stored in canonical form such as
+// "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,TH;COUNT=5;UNTIL=20261231". "daily", "weekly",
+// "monthly" and "yearly" are accepted as shorthands for FREQ alone.
+//
+// Occurrences keep the time of day of the one they follow. Monthly and yearly rules
+// clamp to the last day of shorter months (and February 29 to the 28th) rather than
+// skipping them. COUNT is how
+// many occurrences are left including the current one, and UNTIL is the last date
+// one may fall on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    // 0 (Monday) ..= 6 (Sunday), weekly rules only
+    pub by_day: Vec<u8>,
+    // 1..=12, yearly rules only
+    pub by_month: Option<u32>,
+    // 1..=31, or -1 for the last day of the month; monthly and yearly rules only
+    pub by_month_day: Option<i32>,
+    pub count: Option<u32>,
+    // "YYYY-MM-DD"
+    pub until: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+// Rules longer than this are almost certainly a mistake, and it keeps the
+// day-by-day search in next_after bounded
+const MAX_INTERVAL: u32 = 1000;
+
+// Periods next_after looks through for an occurrence. A wanted day is clamped into
+// every month, so the second period always has one; the rest is slack.
+const MAX_PERIODS: i64 = 4;
+
+fn invalid(reason: impl Into<String>) -> AppError {
+    AppError::validation("recurrence", reason)
+}
+
+impl Rule {
+    pub fn parse(text: &str) -> Result<Rule> {
+        let text = text.trim().to_ascii_uppercase();
+        let text = text.strip_prefix("RRULE:").unwrap_or(&text);
+        let frequency = |name: &str| match name {
+            "DAILY" => Some(Frequency::Daily),
+            "WEEKLY" => Some(Frequency::Weekly),
+            "MONTHLY" => Some(Frequency::Monthly),
+            "YEARLY" => Some(Frequency::Yearly),
+            _ => None,
+        };
+        if let Some(frequency) = frequency(text) {
+            return Ok(Rule { frequency, interval: 1, by_day: Vec::new(), by_month: None, by_month_day: None, count: None, until: None });
+        }
+
+        let mut rule = Rule { frequency: Frequency::Daily, interval: 1, by_day: Vec::new(), by_month: None, by_month_day: None, count: None, until: None };
+        let mut has_frequency = false;
+        for part in text.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(|| invalid(format!("{:?} is not KEY=VALUE", part)))?;
+            match key {
+                "FREQ" => {
+                    rule.frequency = frequency(value).ok_or_else(|| invalid(format!("unsupported FREQ {:?}", value)))?;
+                    has_frequency = true;
+                }
+                "INTERVAL" => {
+                    rule.interval = value.parse().ok()
+                        .filter(|interval| (1..=MAX_INTERVAL).contains(interval))
+                        .ok_or_else(|| invalid(format!("INTERVAL must be between 1 and {}", MAX_INTERVAL)))?;
+                }
+                "BYDAY" => {
+                    rule.by_day = value.split(',')
+                        .map(|day| {
+                            WEEKDAYS.iter().position(|name| *name == day)
+                                .map(|index| index as u8)
+                                .ok_or_else(|| invalid(format!("unknown BYDAY weekday {:?}", day)))
+                        })
+                        .collect::<Result<Vec<u8>>>()?;
+                    rule.by_day.sort_unstable();
+                    rule.by_day.dedup();
+                }
+                "BYMONTH" => {
+                    rule.by_month = Some(value.parse().ok()
+                        .filter(|month| (1..=12).contains(month))
+                        .ok_or_else(|| invalid("BYMONTH must be between 1 and 12"))?);
+                }
+                "BYMONTHDAY" => {
+                    rule.by_month_day = Some(value.parse().ok()
+                        .filter(|day| (1..=31).contains(day) || *day == -1)
+                        .ok_or_else(|| invalid("BYMONTHDAY must be between 1 and 31, or -1"))?);
+                }
+                "COUNT" => {
+                    rule.count = Some(value.parse().ok()
+                        .filter(|count| *count >= 1)
+                        .ok_or_else(|| invalid("COUNT must be at least 1"))?);
+                }
+                "UNTIL" => rule.until = Some(parse_until(value)?),
+                _ => return Err(invalid(format!("unsupported rule part {:?}", key))),
+            }
+        }
+
+        if !has_frequency {
+            return Err(invalid("FREQ is required (DAILY, WEEKLY, MONTHLY or YEARLY)"));
+        }
+        if !rule.by_day.is_empty() && rule.frequency != Frequency::Weekly {
+            return Err(invalid("BYDAY is only supported with FREQ=WEEKLY"));
+        }
+        if rule.by_month.is_some() && rule.frequency != Frequency::Yearly {
+            return Err(invalid("BYMONTH is only supported with FREQ=YEARLY"));
+        }
+        match (rule.frequency, rule.by_month, rule.by_month_day) {
+            (_, _, None) | (Frequency::Monthly, _, _) | (Frequency::Yearly, Some(_), _) => {}
+            (Frequency::Yearly, None, Some(_)) => return Err(invalid("BYMONTHDAY needs BYMONTH with FREQ=YEARLY")),
+            _ => return Err(invalid("BYMONTHDAY is only supported with FREQ=MONTHLY or YEARLY")),
+        }
+        Ok(rule)
+    }
+
+    // The first occurrence after `anchor` ("YYYY-MM-DD HH:MM:SS"), None once UNTIL is passed.
+    // COUNT isn't looked at here, see `next`.
+    pub fn next_after(&self, anchor: &str) -> Result<Option<String>> {
+        let (days, time) = parse_datetime(anchor)?;
+        let (year, month, day) = civil_from_days(days);
+        let interval = i64::from(self.interval);
+
+        let next = match self.frequency {
+            Frequency::Daily => days + interval,
+            Frequency::Weekly if self.by_day.is_empty() => days + 7 * interval,
+            Frequency::Weekly => {
+                let monday = days - weekday(days);
+                first_after(days, (1..=7 * MAX_PERIODS * interval).map(|offset| days + offset).filter(|&candidate| {
+                    let week = (candidate - weekday(candidate) - monday) / 7;
+                    week % interval == 0 && self.by_day.contains(&(weekday(candidate) as u8))
+                }))?
+            }
+            Frequency::Monthly => {
+                let wanted = self.by_month_day.unwrap_or(day as i32);
+                // With BYMONTHDAY the anchor's own month may still have the day ahead
+                let first = if self.by_month_day.is_some() { 0 } else { interval };
+                first_after(days, (0..MAX_PERIODS).map(|step| {
+                    let (year, month) = add_months(year, month, first + step * interval);
+                    clamped(year, month, wanted)
+                }))?
+            }
+            Frequency::Yearly => {
+                // Pinned to a month, and a day of it, that the anchor may have been clamped away from
+                let wanted = self.by_month_day.unwrap_or(day as i32);
+                let first = if self.by_month.is_some() { 0 } else { interval };
+                let month = self.by_month.unwrap_or(month);
+                first_after(days, (0..MAX_PERIODS).map(|step| clamped(year + first + step * interval, month, wanted)))?
+            }
+        };
+
+        let next = format_datetime(next, &time);
+        match &self.until {
+            Some(until) if next[..10] > **until => Ok(None),
+            _ => Ok(Some(next)),
+        }
+    }
+
+    // The occurrence after `anchor` together with the rule it carries on, None when the
+    // series ends with the anchor. A monthly rule without BYMONTHDAY is pinned to the
+    // anchor's day, and a yearly one without BYMONTH to its month and day, so clamping
+    // to a short month doesn't move later occurrences.
+    pub fn next(&self, anchor: &str) -> Result<Option<(String, Rule)>> {
+        if self.count == Some(1) {
+            return Ok(None);
+        }
+        let Some(next) = self.next_after(anchor)? else {
+            return Ok(None);
+        };
+
+        let mut rule = self.clone();
+        rule.count = self.count.map(|count| count - 1);
+        let (_, month, day) = civil_from_days(parse_datetime(anchor)?.0);
+        match rule.frequency {
+            Frequency::Monthly if rule.by_month_day.is_none() => rule.by_month_day = Some(day as i32),
+            Frequency::Yearly if rule.by_month.is_none() => {
+                rule.by_month = Some(month);
+                rule.by_month_day = Some(day as i32);
+            }
+            _ => {}
+        }
+        Ok(Some((next, rule)))
+    }
+
+    // Up to `limit` occurrences following `anchor`
+    pub fn upcoming(&self, anchor: &str, limit: usize) -> Result<Vec<String>> {
+        let mut occurrences = Vec::new();
+        let (mut anchor, mut rule) = (anchor.to_string(), self.clone());
+        while occurrences.len() < limit {
+            let Some((next, next_rule)) = rule.next(&anchor)? else {
+                break;
+            };
+            occurrences.push(next.clone());
+            (anchor, rule) = (next, next_rule);
+        }
+        Ok(occurrences)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={}", frequency)?;
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<&str> = self.by_day.iter().map(|&day| WEEKDAYS[usize::from(day)]).collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if let Some(month) = self.by_month {
+            write!(f, ";BYMONTH={}", month)?;
+        }
+        if let Some(day) = self.by_month_day {
+            write!(f, ";BYMONTHDAY={}", day)?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={}", count)?;
+        }
+        if let Some(until) = &self.until {
+            write!(f, ";UNTIL={}", until.replace('-', ""))?;
+        }
+        Ok(())
+    }
+}
+
+// Validates a rule before it's stored; blank means no recurrence
+pub fn normalize(rule: Option<&str>) -> Result<Option<String>> {
+    match rule.map(str::trim).filter(|rule| !rule.is_empty()) {
+        Some(rule) => Ok(Some(Rule::parse(rule)?.to_string())),
+        None => Ok(None),
+    }
+}
+
+// The dates and rule of the next occurrence of a task or item
+#[derive(Debug)]
+pub struct Occurrence {
+    pub start_at: Option<String>,
+    pub due_at: Option<String>,
+    pub rule: Rule,
+}
+
+// Where the next occurrence of a task or item goes. Occurrences follow the due date,
+// or the start date when there's no due date; undated ones follow `today` ("YYYY-MM-DD")
+// and get a due date. A start date keeps its distance before the due date.
+pub fn next_occurrence(rule: &Rule, start_at: Option<&str>, due_at: Option<&str>, today: &str) -> Result<Option<Occurrence>> {
+    let end_of_today = format!("{} 23:59:59", today);
+    let anchor = due_at.or(start_at).unwrap_or(&end_of_today);
+    let Some((next, rule)) = rule.next(anchor)? else {
+        return Ok(None);
+    };
+
+    let occurrence = match (start_at, due_at) {
+        (Some(start_at), Some(due_at)) => {
+            let lead = seconds(due_at)? - seconds(start_at)?;
+            Occurrence { start_at: Some(from_seconds(seconds(&next)? - lead)), due_at: Some(next), rule }
+        }
+        (Some(_), None) => Occurrence { start_at: Some(next), due_at: None, rule },
+        (None, _) => Occurrence { start_at: None, due_at: Some(next), rule },
+    };
+    Ok(Some(occurrence))
+}
+
+pub const MAX_PREVIEW: u32 = 100;
+
+// The next `limit` occurrences of a rule after `from` (a date, or now when None), for
+// showing a rule before it's saved
+pub fn preview(conn: &Connection, rule: &str, from: Option<&str>, limit: u32) -> Result<Vec<String>> {
+    if limit == 0 || limit > MAX_PREVIEW {
+        return Err(AppError::validation("limit", format!("must be between 1 and {}", MAX_PREVIEW)));
+    }
+    let rule = Rule::parse(rule)?;
+    let from = match schedule::normalize(conn, "from", from, false)? {
+        Some(from) => from,
+        None => conn.prepare_cached("SELECT datetime('now', 'localtime')")?.query_row([], |row| row.get(0))?,
+    };
+    rule.upcoming(&from, limit as usize)
+}
+
+// Seconds from one stored date to another, for moving dates along with an occurrence
+pub fn offset(from: &str, to: &str) -> Result<i64> {
+    Ok(seconds(to)? - seconds(from)?)
+}
+
+fn parse_until(value: &str) -> Result<String> {
+    // Accepts 20261231, 2026-12-31 and 20261231T235959Z; only the date is kept
+    let date: String = value.chars().take_while(|c| *c != 'T').filter(|c| *c != '-').collect();
+    let parsed = (date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))
+        .filter(|date| parse_date(date).is_some());
+    parsed.ok_or_else(|| invalid(format!("UNTIL {:?} is not a date (YYYYMMDD)", value)))
+}
+
+// Days since 1970-01-01 for a valid "YYYY-MM-DD"
+fn parse_date(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    ((1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day))
+        .then(|| days_from_civil(year, month, day))
+}
+
+// Splits a stored "YYYY-MM-DD HH:MM:SS" into days since the epoch and the time of day
+fn parse_datetime(value: &str) -> Result<(i64, String)> {
+    let (date, time) = value.split_once(' ').unwrap_or((value, "00:00:00"));
+    let days = parse_date(date).ok_or_else(|| invalid(format!("{:?} is not a stored date", value)))?;
+    Ok((days, time.to_string()))
+}
+
+fn format_datetime(days: i64, time: &str) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02} {}", year, month, day, time)
+}
+
+fn seconds(value: &str) -> Result<i64> {
+    let (days, time) = parse_datetime(value)?;
+    let clock: Vec<i64> = time.split(':').filter_map(|part| part.parse().ok()).collect();
+    match clock[..] {
+        [hours, minutes, seconds] => Ok(days * 86400 + hours * 3600 + minutes * 60 + seconds),
+        _ => Err(invalid(format!("{:?} is not a stored date", value))),
+    }
+}
+
+fn from_seconds(seconds: i64) -> String {
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    format_datetime(days, &format!("{:02}:{:02}:{:02}", time / 3600, time % 3600 / 60, time % 60))
+}
+
+// The first candidate after `days`, in days since the epoch
+fn first_after(days: i64, mut candidates: impl Iterator<Item = i64>) -> Result<i64> {
+    candidates.find(|&candidate| candidate > days)
+        .ok_or_else(|| invalid("the rule has no occurrence after its anchor"))
+}
+
+// `day` of the month, or its last day when the month is shorter or `day` is -1
+fn clamped(year: i64, month: u32, day: i32) -> i64 {
+    let last = days_in_month(year, month);
+    days_from_civil(year, month, if day < 0 { last } else { (day as u32).min(last) })
+}
+
+// 0 for Monday; 1970-01-01 was a Thursday
+fn weekday(days: i64) -> i64 {
+    (days + 3).rem_euclid(7)
+}
+
+fn add_months(year: i64, month: u32, months: i64) -> (i64, u32) {
+    let total = year * 12 + i64::from(month) - 1 + months;
+    (total.div_euclid(12), (total.rem_euclid(12) + 1) as u32)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Proleptic Gregorian calendar conversions (Howard Hinnant's days_from_civil)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upcoming(rule: &str, anchor: &str, limit: usize) -> Vec<String> {
+        let dates = Rule::parse(rule).unwrap().upcoming(&format!("{} 09:30:00", anchor), limit).unwrap();
+        dates.iter().map(|date| date.strip_suffix(" 09:30:00").unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn monthly_clamps_to_month_end_and_returns() {
+        assert_eq!(upcoming("monthly", "2026-01-31", 4), ["2026-02-28", "2026-03-31", "2026-04-30", "2026-05-31"]);
+        assert_eq!(upcoming("FREQ=MONTHLY;BYMONTHDAY=-1", "2026-02-10", 3), ["2026-02-28", "2026-03-31", "2026-04-30"]);
+    }
+
+    #[test]
+    fn yearly_keeps_february_29() {
+        assert_eq!(upcoming("yearly", "2024-02-29", 5), ["2025-02-28", "2026-02-28", "2027-02-28", "2028-02-29", "2029-02-28"]);
+        assert_eq!(upcoming("FREQ=YEARLY;INTERVAL=4", "2024-02-29", 2), ["2028-02-29", "2032-02-29"]);
+    }
+
+    #[test]
+    fn yearly_carries_its_month_and_day() {
+        let (next, rule) = Rule::parse("yearly").unwrap().next("2024-02-29 09:30:00").unwrap().unwrap();
+        assert_eq!(next, "2025-02-28 09:30:00");
+        assert_eq!(rule.to_string(), "FREQ=YEARLY;BYMONTH=2;BYMONTHDAY=29");
+        assert_eq!(upcoming("FREQ=YEARLY;BYMONTH=6;BYMONTHDAY=15", "2026-01-10", 2), ["2026-06-15", "2027-06-15"]);
+    }
+
+    #[test]
+    fn interval_skips_periods() {
+        assert_eq!(upcoming("FREQ=DAILY;INTERVAL=3", "2026-12-30", 2), ["2027-01-02", "2027-01-05"]);
+        assert_eq!(upcoming("FREQ=WEEKLY;INTERVAL=2", "2026-10-01", 2), ["2026-10-15", "2026-10-29"]);
+        assert_eq!(upcoming("FREQ=MONTHLY;INTERVAL=5", "2026-10-31", 2), ["2027-03-31", "2027-08-31"]);
+    }
+
+    #[test]
+    fn until_and_count_end_the_series() {
+        assert_eq!(upcoming("FREQ=DAILY;UNTIL=20261003", "2026-10-01", 5), ["2026-10-02", "2026-10-03"]);
+        // COUNT includes the anchor itself
+        assert_eq!(upcoming("FREQ=WEEKLY;COUNT=3", "2026-10-01", 5), ["2026-10-08", "2026-10-15"]);
+        assert!(upcoming("FREQ=DAILY;COUNT=1", "2026-10-01", 5).is_empty());
+    }
+
+    #[test]
+    fn by_day_picks_weekdays_in_active_weeks() {
+        // 2026-10-01 is a Thursday
+        assert_eq!(upcoming("FREQ=WEEKLY;BYDAY=MO,TH", "2026-10-01", 3), ["2026-10-05", "2026-10-08", "2026-10-12"]);
+        assert_eq!(upcoming("FREQ=WEEKLY;INTERVAL=2;BYDAY=TU", "2026-10-01", 2), ["2026-10-13", "2026-10-27"]);
+    }
+
+    #[test]
+    fn parse_rejects_parts_outside_their_frequency() {
+        assert_eq!(normalize(Some("rrule:freq=weekly;byday=th,mo")).unwrap().as_deref(), Some("FREQ=WEEKLY;BYDAY=MO,TH"));
+        assert!(Rule::parse("FREQ=DAILY;BYDAY=MO").is_err());
+        assert!(Rule::parse("FREQ=MONTHLY;BYMONTH=2").is_err());
+        assert!(Rule::parse("FREQ=YEARLY;BYMONTHDAY=3").is_err());
+        assert!(Rule::parse("FREQ=YEARLY;BYMONTH=13").is_err());
+    }
+}
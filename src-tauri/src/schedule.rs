@@ -0,0 +1,206 @@
+use rusqlite::{Connection, ToSql};
+use serde::Serialize;
+
+use crate::error::{AppError, Result};
+use crate::task::Task;
+use crate::task_item::{TaskItem, TaskItemStatus};
+
+// Start and due dates are local wall-clock times, stored as "YYYY-MM-DD HH:MM:SS" so
+// they compare as text against datetime('now', 'localtime'). A bare date covers the
+// whole day: as a start date it means its first second, as a due date its last.
+pub fn normalize(conn: &Connection, field: &'static str, value: Option<&str>, end_of_day: bool) -> Result<Option<String>> {
+    let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+
+    // datetime() also takes julian day numbers and the like, only accept dates
+    let looks_like_date = value.len() >= 10 && value.as_bytes()[4] == b'-' && value.as_bytes()[7] == b'-';
+    let modifier = if end_of_day && value.len() == 10 { "+86399 seconds" } else { "+0 seconds" };
+    let normalized: Option<String> = conn.prepare_cached("SELECT datetime(?1, ?2)")?
+        .query_row((value, modifier), |row| row.get(0))?;
+
+    match normalized {
+        Some(normalized) if looks_like_date => Ok(Some(normalized)),
+        _ => Err(AppError::validation(field, format!("{:?} is not a date (YYYY-MM-DD or YYYY-MM-DD HH:MM)", value))),
+    }
+}
+
+// Validates and normalises a start/due pair before it's stored
+pub fn normalize_dates(conn: &Connection, start_at: Option<&str>, due_at: Option<&str>) -> Result<(Option<String>, Option<String>)> {
+    let start_at = normalize(conn, "start_at", start_at, false)?;
+    let due_at = normalize(conn, "due_at", due_at, true)?;
+
+    if let (Some(start), Some(due)) = (&start_at, &due_at) {
+        if start > due {
+            return Err(AppError::validation("start_at", "must not be after the due date"));
+        }
+    }
+    Ok((start_at, due_at))
+}
+
+// Tasks and items across all live projects, each ordered by due date
+#[derive(Debug, Serialize)]
+pub struct Agenda {
+    pub tasks: Vec<Task>,
+    pub task_items: Vec<TaskItem>
+}
+
+// `condition` is an SQL expression over `due_at`, `start_at` and `done`, bound to `params`.
+// Trashed rows and tasks of trashed projects are left out, as are items that aren't in
+// any live task of a live project.
+fn agenda(conn: &Connection, condition: &str, params: &[&dyn ToSql]) -> Result<Agenda> {
+    let tasks = conn.prepare_cached(&format!(
+        "SELECT {} FROM (
+             SELECT tasks.*, tasks.completed_at IS NOT NULL AS done
+             FROM tasks
+             JOIN projects ON projects.id = tasks.project_id
+             WHERE tasks.deleted_at IS NULL AND projects.deleted_at IS NULL
+         ) AS tasks
+         WHERE {}
+         ORDER BY tasks.due_at IS NULL, tasks.due_at, tasks.id",
+        Task::COLUMNS, condition
+    ))?
+        .query_map(params, Task::from_row)?
+        .collect::<rusqlite::Result<Vec<Task>>>()?;
+
+    let task_items = conn.prepare_cached(&format!(
+        "SELECT {} FROM (
+             SELECT task_items.*, task_items.status IN ({}, {}) AS done
+             FROM task_items
+             WHERE task_items.deleted_at IS NULL AND EXISTS (
+                 SELECT 1 FROM task_item_links
+                 JOIN tasks ON tasks.id = task_item_links.task_id
+                 JOIN projects ON projects.id = tasks.project_id
+                 WHERE task_item_links.item_id = task_items.id
+                 AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL
+             )
+         ) AS task_items
+         WHERE {}
+         ORDER BY task_items.due_at IS NULL, task_items.due_at, task_items.id",
+        TaskItem::COLUMNS, i64::from(TaskItemStatus::Completed), i64::from(TaskItemStatus::Canceled), condition
+    ))?
+        .query_map(params, TaskItem::from_row)?
+        .collect::<rusqlite::Result<Vec<TaskItem>>>()?;
+
+    Ok(Agenda { tasks, task_items })
+}
+
+// Not yet done and past their due date
+pub fn get_overdue(conn: &Connection) -> Result<Agenda> {
+    let now: String = conn.prepare_cached("SELECT datetime('now', 'localtime')")?
+        .query_row([], |row| row.get(0))?;
+    agenda(conn, "NOT done AND due_at < ?1", &[&now])
+}
+
+// Everything due within the range, done or not; bare dates include the whole day
+pub fn get_due_between(conn: &Connection, from: &str, to: &str) -> Result<Agenda> {
+    let from = normalize(conn, "from", Some(from), false)?
+        .ok_or(AppError::validation("from", "must not be empty"))?;
+    let to = normalize(conn, "to", Some(to), true)?
+        .ok_or(AppError::validation("to", "must not be empty"))?;
+    agenda(conn, "due_at BETWEEN ?1 AND ?2", &[&from, &to])
+}
+
+// Not yet done, and either due today or already started and not yet due.
+// Overdue work is left to get_overdue.
+pub fn get_today(conn: &Connection) -> Result<Agenda> {
+    let (start, end): (String, String) = conn.prepare_cached(
+        "SELECT datetime('now', 'localtime', 'start of day'), datetime('now', 'localtime', 'start of day', '+86399 seconds')"
+    )?.query_row([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    agenda(
+        conn,
+        "NOT done AND (due_at BETWEEN ?1 AND ?2 OR (start_at <= ?2 AND (due_at IS NULL OR due_at > ?2)))",
+        &[&start, &end],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, project, task, task_item};
+
+    struct Fixture {
+        projects: [i64; 2],
+        tasks: [i64; 3],
+        items: [i64; 3],
+    }
+
+    // Two tasks in the first project, one in the second, each with one item dated
+    // like the task
+    fn fixture(conn: &Connection, start_at: Option<&str>, due_at: Option<&str>) -> Fixture {
+        let projects = ["First", "Second"].map(|title| project::create_project(conn, title, None).unwrap());
+        let tasks = [(projects[0], "One"), (projects[0], "Two"), (projects[1], "Three")]
+            .map(|(project_id, title)| task::create_task(conn, project_id, title, None, start_at, due_at).unwrap());
+        let items = [0, 1, 2].map(|index| {
+            let item_id = task_item::create_task_item(conn, &format!("Item {}", index), start_at, due_at).unwrap();
+            task_item::link_task_item(conn, tasks[index], item_id).unwrap();
+            item_id
+        });
+        Fixture { projects, tasks, items }
+    }
+
+    fn titles(agenda: &Agenda) -> (Vec<&str>, Vec<&str>) {
+        (
+            agenda.tasks.iter().map(|task| task.title.as_str()).collect(),
+            agenda.task_items.iter().map(|item| item.content.as_str()).collect(),
+        )
+    }
+
+    fn today(conn: &Connection) -> String {
+        conn.query_row("SELECT date('now', 'localtime')", [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn overdue_leaves_out_trashed_tasks_and_projects() {
+        let conn = db::test_connection();
+        let f = fixture(&conn, None, Some("2020-01-01"));
+        assert_eq!(titles(&get_overdue(&conn).unwrap()), (vec!["One", "Two", "Three"], vec!["Item 0", "Item 1", "Item 2"]));
+
+        task::delete_task(&conn, f.tasks[1]).unwrap();
+        project::delete_project(&conn, f.projects[1]).unwrap();
+        assert_eq!(titles(&get_overdue(&conn).unwrap()), (vec!["One"], vec!["Item 0"]));
+    }
+
+    #[test]
+    fn overdue_leaves_out_done_work() {
+        let conn = db::test_connection();
+        let f = fixture(&conn, None, Some("2020-01-01"));
+        task_item::update_task_item_content(&conn, f.items[0], "Item 0", TaskItemStatus::Completed).unwrap();
+
+        let agenda = get_overdue(&conn).unwrap();
+        assert!(!agenda.tasks.iter().any(|task| task.id == f.tasks[0]));
+        assert_eq!(titles(&agenda).1, ["Item 1", "Item 2"]);
+    }
+
+    #[test]
+    fn range_includes_whole_days_and_done_work() {
+        let conn = db::test_connection();
+        let f = fixture(&conn, None, Some("2030-01-31 18:00"));
+        task_item::update_task_item_content(&conn, f.items[0], "Item 0", TaskItemStatus::Completed).unwrap();
+
+        assert_eq!(titles(&get_due_between(&conn, "2030-01-01", "2030-01-31").unwrap()).1, ["Item 0", "Item 1", "Item 2"]);
+        assert!(titles(&get_due_between(&conn, "2030-01-01", "2030-01-30").unwrap()).0.is_empty());
+        assert!(matches!(get_due_between(&conn, "soon", "2030-01-31"), Err(AppError::Validation { field: "from", .. })));
+
+        task::delete_task(&conn, f.tasks[0]).unwrap();
+        project::delete_project(&conn, f.projects[1]).unwrap();
+        assert_eq!(titles(&get_due_between(&conn, "2030-01-01", "2030-01-31").unwrap()), (vec!["Two"], vec!["Item 1"]));
+    }
+
+    #[test]
+    fn today_has_work_due_today_or_started_and_not_yet_due() {
+        let conn = db::test_connection();
+        let today = today(&conn);
+        let f = fixture(&conn, None, Some(&today));
+        let started = task::create_task(&conn, f.projects[0], "Started", None, Some("2020-01-01"), Some("2999-01-01")).unwrap();
+        task::create_task(&conn, f.projects[0], "Later", None, Some("2999-01-01"), None).unwrap();
+        task::create_task(&conn, f.projects[0], "Overdue", None, None, Some("2020-01-01")).unwrap();
+        let agenda = get_today(&conn).unwrap();
+        assert_eq!(titles(&agenda), (vec!["One", "Two", "Three", "Started"], vec!["Item 0", "Item 1", "Item 2"]));
+
+        task::delete_task(&conn, started).unwrap();
+        task::delete_task(&conn, f.tasks[0]).unwrap();
+        project::delete_project(&conn, f.projects[1]).unwrap();
+        assert_eq!(titles(&get_today(&conn).unwrap()), (vec!["Two"], vec!["Item 1"]));
+    }
+}
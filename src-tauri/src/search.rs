@@ -0,0 +1,160 @@
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::entity::Entity;
+use crate::error::{AppError, Result};
+
+pub const DEFAULT_LIMIT: u32 = 20;
+pub const MAX_LIMIT: u32 = 100;
+
+// Matched terms in a snippet are wrapped in these
+pub const HIGHLIGHT_START: &str = "**";
+pub const HIGHLIGHT_END: &str = "**";
+
+#[derive(Debug, Serialize)]
+pub struct PathPart {
+    pub id: i64,
+    pub title: String
+}
+
+// `title` is the task item content for items. `project`/`task` locate the hit:
+// both are null for projects, and an item shared between tasks reports the
+// live task with the lowest id (null if it isn't linked to any).
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub entity: Entity,
+    pub id: i64,
+    pub title: String,
+    pub snippet: String,
+    // bm25 score, lower is a better match. Only comparable between hits of the
+    // same entity, each FTS table scores against its own statistics.
+    pub rank: f64,
+    pub project: Option<PathPart>,
+    pub task: Option<PathPart>
+}
+
+// Every word of the user's input has to match, as a prefix so results show up while
+// typing. Words are quoted so FTS5 operators and stray quotes are taken literally.
+fn match_expression(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+        .collect();
+    if terms.is_empty() {
+        return None;
+    }
+    Some(terms.join(" "))
+}
+
+fn path_part(id: Option<i64>, title: Option<String>) -> Option<PathPart> {
+    Some(PathPart { id: id?, title: title? })
+}
+
+// Live projects, tasks and items matching `query`. Each entity's hits are ranked on
+// their own and then taken in turns: the best project, task and item, then the
+// second best of each, and so on.
+pub fn search(conn: &Connection, query: &str, limit: Option<u32>) -> Result<Vec<SearchHit>> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT);
+    if limit == 0 || limit > MAX_LIMIT {
+        return Err(AppError::validation("limit", format!("must be between 1 and {}", MAX_LIMIT)));
+    }
+    let Some(expression) = match_expression(query) else {
+        return Ok(Vec::new());
+    };
+
+    let mut stmt = conn.prepare_cached(
+        "WITH hits (kind, entity, id, title, snippet, score, project_id, project_title, task_id, task_title) AS (
+             SELECT 0, 'project', projects.id, projects.title,
+                    snippet(projects_fts, -1, ?2, ?3, '…', 12), bm25(projects_fts),
+                    NULL, NULL, NULL, NULL
+             FROM projects_fts
+             JOIN projects ON projects.id = projects_fts.rowid
+             WHERE projects_fts MATCH ?1 AND projects.deleted_at IS NULL
+             UNION ALL
+             SELECT 1, 'task', tasks.id, tasks.title,
+                    snippet(tasks_fts, -1, ?2, ?3, '…', 12), bm25(tasks_fts),
+                    projects.id, projects.title, NULL, NULL
+             FROM tasks_fts
+             JOIN tasks ON tasks.id = tasks_fts.rowid
+             JOIN projects ON projects.id = tasks.project_id
+             WHERE tasks_fts MATCH ?1 AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL
+             UNION ALL
+             SELECT 2, 'task_item', task_items.id, task_items.content,
+                    snippet(task_items_fts, -1, ?2, ?3, '…', 12), bm25(task_items_fts),
+                    projects.id, projects.title, tasks.id, tasks.title
+             FROM task_items_fts
+             JOIN task_items ON task_items.id = task_items_fts.rowid
+             LEFT JOIN tasks ON tasks.id = (
+                 SELECT MIN(task_item_links.task_id)
+                 FROM task_item_links
+                 JOIN tasks AS linked ON linked.id = task_item_links.task_id
+                 JOIN projects AS owner ON owner.id = linked.project_id
+                 WHERE task_item_links.item_id = task_items.id
+                 AND linked.deleted_at IS NULL AND owner.deleted_at IS NULL
+             )
+             LEFT JOIN projects ON projects.id = tasks.project_id
+             WHERE task_items_fts MATCH ?1 AND task_items.deleted_at IS NULL
+         )
+         SELECT entity, id, title, snippet, score, project_id, project_title, task_id, task_title
+         FROM hits
+         ORDER BY ROW_NUMBER() OVER (PARTITION BY kind ORDER BY score, id), kind
+         LIMIT ?4"
+    )?;
+
+    let hits = stmt
+        .query_map((&expression, HIGHLIGHT_START, HIGHLIGHT_END, limit), |row| {
+            Ok(SearchHit {
+                entity: row.get(0)?,
+                id: row.get(1)?,
+                title: row.get(2)?,
+                snippet: row.get(3)?,
+                rank: row.get(4)?,
+                project: path_part(row.get(5)?, row.get(6)?),
+                task: path_part(row.get(7)?, row.get(8)?)
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<SearchHit>>>()?;
+
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, project, task, task_item};
+
+    #[test]
+    fn entities_take_turns() {
+        let conn = db::test_connection();
+        let project_ids = [
+            project::create_project(&conn, "Garden", None).unwrap(),
+            project::create_project(&conn, "Garden shed", None).unwrap(),
+        ];
+        for title in ["Garden", "Water the garden", "Garden path"] {
+            task::create_task(&conn, project_ids[0], title, None, None, None).unwrap();
+        }
+        for content in ["Garden hose", "Garden gloves garden boots garden hat"] {
+            task_item::create_task_item(&conn, content, None, None).unwrap();
+        }
+
+        let entities: Vec<Entity> = search(&conn, "garden", None).unwrap().into_iter().map(|hit| hit.entity).collect();
+        assert_eq!(entities, [
+            Entity::Project, Entity::Task, Entity::TaskItem,
+            Entity::Project, Entity::Task, Entity::TaskItem,
+            Entity::Task,
+        ]);
+    }
+
+    #[test]
+    fn limit_applies_after_interleaving() {
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "Garden", None).unwrap();
+        for index in 0..5 {
+            task::create_task(&conn, project_id, &format!("Garden {}", index), None, None, None).unwrap();
+        }
+        task_item::create_task_item(&conn, "Garden hose", None, None).unwrap();
+
+        let entities: Vec<Entity> = search(&conn, "garden", Some(3)).unwrap().into_iter().map(|hit| hit.entity).collect();
+        assert_eq!(entities, [Entity::Project, Entity::Task, Entity::TaskItem]);
+    }
+}
@@ -0,0 +1,520 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::error::{AppError, Result};
+use crate::priority::SortOrder;
+use crate::project::{self, Project};
+use crate::schedule;
+use crate::task::{self, Task};
+use crate::task_item::{self, TaskItemFilter, TaskItemStatus, TaskItemWithSequence};
+
+// Projects, tasks and items flattened into one CSV table for spreadsheets: a row per
+// item, each task's items in sequence order with subtasks right after their item, a
+// row with blank item columns for a task without items and one with blank task
+// columns for a project without tasks. Statuses are "Pending", "InProgress", ... and
+// dates "YYYY-MM-DD HH:MM:SS". An item shared between tasks has a row under each, with
+// the same item_id. Cells that a spreadsheet would take for a formula (starting with
+// =, +, - or @) are written with a leading ', as is text that starts with one.
+//
+// Importing adds every row as new data, so an exported file comes back as a copy.
+// Headers are matched to IMPORTED_COLUMNS by name unless mapped to one explicitly, and
+// other columns (ids of existing rows, sequences, completion dates) are ignored: rows
+// keep their order in the file and completion follows the status. Rows go into the
+// live project with their `project_title`, or a new one, unless a project is given; a
+// row without a task only names a project. Rows with the same `task_id`, or the same
+// `task_title` when there is no id, fill one task, dated by the first of them to set
+// task dates. `item_parent_id` names the `item_id` of an earlier row of the same task.
+// Rows of different tasks with the same `item_id` are one item linked to each task,
+// with the content, status and dates of its first row. A leading ' before a formula
+// character or another ' is dropped.
+
+pub const COLUMNS: [&str; 17] = [
+    "project_id", "project_title", "project_sequence",
+    "task_id", "task_title", "task_sequence", "task_start_at", "task_due_at", "task_completed_at",
+    "item_id", "item_parent_id", "item_content", "item_status", "item_sequence",
+    "item_start_at", "item_due_at", "item_completed_at",
+];
+
+pub const IMPORTED_COLUMNS: [&str; 11] = [
+    "project_title", "task_id", "task_title", "task_start_at", "task_due_at",
+    "item_id", "item_parent_id", "item_content", "item_status", "item_start_at", "item_due_at",
+];
+
+// One project, or every live project in order
+pub fn export_csv(conn: &Connection, project_id: Option<i64>) -> Result<String> {
+    let projects = match project_id {
+        Some(project_id) => vec![project::get_project(conn, project_id)?],
+        None => project::get_all_projects(conn, &[])?,
+    };
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(COLUMNS)?;
+    for project in &projects {
+        let tasks = task::get_tasks(conn, project.id, SortOrder::Sequence, &[])?;
+        if tasks.is_empty() {
+            writer.write_record(row(project, None, None))?;
+        }
+        for task in &tasks {
+            let items = task_item::get_task_items(conn, task.id, &TaskItemFilter::default(), SortOrder::Sequence, None, None)?.items;
+            if items.is_empty() {
+                writer.write_record(row(project, Some(task), None))?;
+            }
+            for item in &items {
+                writer.write_record(row(project, Some(task), Some(item)))?;
+            }
+        }
+    }
+
+    let bytes = writer.into_inner().map_err(|e| AppError::Storage { message: e.to_string() })?;
+    String::from_utf8(bytes).map_err(|e| AppError::Storage { message: e.to_string() })
+}
+
+fn row(project: &Project, task: Option<&Task>, item: Option<&TaskItemWithSequence>) -> [String; 17] {
+    let cells = [
+        project.id.to_string(),
+        project.title.clone(),
+        project.sequence.to_string(),
+        text(task.map(|task| task.id)),
+        text(task.map(|task| &task.title)),
+        text(task.map(|task| task.sequence)),
+        text(task.and_then(|task| task.start_at.as_ref())),
+        text(task.and_then(|task| task.due_at.as_ref())),
+        text(task.and_then(|task| task.completed_at.as_ref())),
+        text(item.map(|item| item.id)),
+        // Subtasks of an item that isn't shown are exported at the top level, like they're listed
+        text(item.filter(|item| item.depth > 0).and_then(|item| item.parent_id)),
+        text(item.map(|item| &item.content)),
+        text(item.map(|item| format!("{:?}", item.status))),
+        text(item.and_then(|item| item.sequence)),
+        text(item.and_then(|item| item.start_at.as_ref())),
+        text(item.and_then(|item| item.due_at.as_ref())),
+        text(item.and_then(|item| item.completed_at.as_ref())),
+    ];
+    cells.map(escape)
+}
+
+fn text(value: Option<impl ToString>) -> String {
+    value.map_or_else(String::new, |value| value.to_string())
+}
+
+// Characters that start a formula, and the quote that escapes them
+const ESCAPED: [char; 5] = ['=', '+', '-', '@', '\''];
+
+// Keeps a spreadsheet from evaluating the cell; the quote shows as text, not in it
+fn escape(cell: String) -> String {
+    if cell.starts_with(ESCAPED) {
+        format!("'{}", cell)
+    } else {
+        cell
+    }
+}
+
+fn unescape(cell: &str) -> &str {
+    match cell.strip_prefix('\'') {
+        Some(rest) if rest.starts_with(ESCAPED) => rest,
+        _ => cell,
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowError {
+    // Line in the file the row starts on, the header being line 1
+    pub line: u64,
+    pub field: String,
+    pub reason: String
+}
+
+// With any errors nothing was imported
+#[derive(Debug, Default, Serialize)]
+pub struct CsvImport {
+    // Projects created for titles that didn't match one
+    pub project_ids: Vec<i64>,
+    pub task_ids: Vec<i64>,
+    pub task_item_ids: Vec<i64>,
+    pub errors: Vec<RowError>
+}
+
+struct TaskRow {
+    project_title: String,
+    title: String,
+    start_at: Option<String>,
+    due_at: Option<String>,
+    items: Vec<ItemRow>,
+}
+
+struct ItemRow {
+    key: Option<String>,
+    // Index of the parent among the task's items
+    parent: Option<usize>,
+    // Task and item index of the first row with the same key, in another task
+    shared: Option<(usize, usize)>,
+    content: String,
+    status: TaskItemStatus,
+    start_at: Option<String>,
+    due_at: Option<String>,
+}
+
+// `columns` maps headers of the file to IMPORTED_COLUMNS. Every row is checked before
+// anything is written.
+pub fn import_csv(conn: &Connection, text: &str, project_id: Option<i64>, columns: &HashMap<String, String>) -> Result<CsvImport> {
+    if let Some(project_id) = project_id {
+        project::get_project(conn, project_id)?;
+    }
+    let mut reader = csv::ReaderBuilder::new().flexible(true).trim(csv::Trim::All).from_reader(text.as_bytes());
+    let headers = reader.headers().map_err(|e| AppError::validation("text", e.to_string()))?.clone();
+    let fields = map_columns(&headers, columns, project_id.is_none())?;
+
+    let mut report = CsvImport::default();
+    let mut project_titles: Vec<String> = Vec::new();
+    let mut tasks: Vec<TaskRow> = Vec::new();
+    let mut task_index: HashMap<(String, String), usize> = HashMap::new();
+    // Where each item key was first seen, and the status it was given there
+    let mut item_keys: HashMap<String, (usize, usize, TaskItemStatus)> = HashMap::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map_or(0, |position| position.line());
+                report.errors.push(RowError { line, field: "row".to_string(), reason: e.to_string() });
+                continue;
+            }
+        };
+        if record.iter().all(str::is_empty) {
+            continue;
+        }
+        let line = record.position().map_or(0, |position| position.line());
+        let get = |field: &str| fields.get(field).and_then(|&index| record.get(index)).map(unescape).filter(|value| !value.is_empty());
+        let mut fail = |field: &str, reason: String| report.errors.push(RowError { line, field: field.to_string(), reason });
+
+        // Everything goes into the given project, whatever the row says
+        let project_title = if project_id.is_none() { get("project_title").unwrap_or_default().to_string() } else { String::new() };
+        if project_id.is_none() {
+            if project_title.is_empty() {
+                fail("project_title", "must not be empty".to_string());
+                continue;
+            }
+            if !project_titles.contains(&project_title) {
+                project_titles.push(project_title.clone());
+            }
+        }
+        let Some(title) = get("task_title") else {
+            if let Some(field) = IMPORTED_COLUMNS[1..].iter().find(|field| get(field).is_some()) {
+                fail("task_title", format!("must not be empty when {} is set", field));
+            }
+            continue;
+        };
+        let key = (project_title.clone(), get("task_id").unwrap_or(title).to_string());
+        let index = *task_index.entry(key).or_insert_with(|| {
+            tasks.push(TaskRow { project_title, title: title.to_string(), start_at: None, due_at: None, items: Vec::new() });
+            tasks.len() - 1
+        });
+        let task = &mut tasks[index];
+
+        if task.start_at.is_none() && task.due_at.is_none() {
+            match schedule::normalize_dates(conn, get("task_start_at"), get("task_due_at")) {
+                Ok((start_at, due_at)) => (task.start_at, task.due_at) = (start_at, due_at),
+                Err(e) => fail_with(&mut fail, "task", e),
+            }
+        }
+
+        let Some(content) = get("item_content") else {
+            if let Some(field) = ["item_id", "item_parent_id", "item_status", "item_start_at", "item_due_at"].into_iter().find(|field| get(field).is_some()) {
+                fail("item_content", format!("must not be empty when {} is set", field));
+            }
+            continue;
+        };
+        let Some(status) = parse_status(get("item_status").unwrap_or_default()) else {
+            fail("item_status", format!("{:?} is not one of Pending, InProgress, Blocked, Deferred, Completed, Canceled", get("item_status").unwrap_or_default()));
+            continue;
+        };
+        let (start_at, due_at) = match schedule::normalize_dates(conn, get("item_start_at"), get("item_due_at")) {
+            Ok(dates) => dates,
+            Err(e) => {
+                fail_with(&mut fail, "item", e);
+                continue;
+            }
+        };
+        let item_key = get("item_id").map(str::to_string);
+        if item_key.is_some() && task.items.iter().any(|item| item.key == item_key) {
+            fail("item_id", format!("{} is used by an earlier row of the task", item_key.unwrap_or_default()));
+            continue;
+        }
+        let shared = item_key.as_ref().and_then(|key| item_keys.get(key)).copied();
+        // A shared item keeps the status of its first row
+        let status = shared.map_or(status, |(_, _, status)| status);
+        let parent = match get("item_parent_id") {
+            None => None,
+            Some(parent_key) => match task.items.iter().position(|item| item.key.as_deref() == Some(parent_key)) {
+                None => {
+                    fail("item_parent_id", format!("no earlier row of the task has item_id {}", parent_key));
+                    continue;
+                }
+                Some(parent) if task.items[parent].status.is_done() && !status.is_done() => {
+                    fail("item_status", "an unfinished item can't be a subtask of a finished one".to_string());
+                    continue;
+                }
+                Some(parent) => Some(parent),
+            },
+        };
+        if let (Some(key), None) = (&item_key, shared) {
+            item_keys.insert(key.clone(), (index, task.items.len(), status));
+        }
+        let shared = shared.map(|(task, item, _)| (task, item));
+        task.items.push(ItemRow { key: item_key, parent, shared, content: content.to_string(), status, start_at, due_at });
+    }
+
+    if report.errors.is_empty() {
+        write(conn, project_id, &project_titles, &tasks, &mut report)?;
+    }
+    Ok(report)
+}
+
+// Which column each imported field is read from
+fn map_columns(headers: &csv::StringRecord, columns: &HashMap<String, String>, needs_project: bool) -> Result<HashMap<&'static str, usize>> {
+    let mut fields = HashMap::new();
+    for (index, header) in headers.iter().enumerate() {
+        let name = columns.get(header).map_or(header, String::as_str).trim().to_lowercase();
+        let Some(&field) = IMPORTED_COLUMNS.iter().find(|&&field| field == name) else {
+            if columns.contains_key(header) {
+                return Err(AppError::validation("columns", format!("{:?} is not one of {}", name, IMPORTED_COLUMNS.join(", "))));
+            }
+            continue;
+        };
+        if fields.insert(field, index).is_some() {
+            return Err(AppError::validation("columns", format!("more than one column maps to {}", field)));
+        }
+    }
+
+    for field in ["project_title", "task_title"] {
+        if !fields.contains_key(field) && (field == "task_title" || needs_project) {
+            return Err(AppError::validation("columns", format!("no column maps to {}", field)));
+        }
+    }
+    Ok(fields)
+}
+
+// Row errors name the column, e.g. "item_due_at" rather than "due_at"
+fn fail_with(fail: &mut impl FnMut(&str, String), prefix: &str, e: AppError) {
+    match e {
+        AppError::Validation { field, reason } => fail(&format!("{}_{}", prefix, field), reason),
+        e => fail(prefix, e.to_string()),
+    }
+}
+
+// Spreadsheet-friendly: any case, spaces or underscores, "done" for Completed; blank is Pending
+fn parse_status(value: &str) -> Option<TaskItemStatus> {
+    let key: String = value.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+    match key.as_str() {
+        "" | "pending" => Some(TaskItemStatus::Pending),
+        "inprogress" => Some(TaskItemStatus::InProgress),
+        "blocked" => Some(TaskItemStatus::Blocked),
+        "deferred" => Some(TaskItemStatus::Deferred),
+        "completed" | "done" => Some(TaskItemStatus::Completed),
+        "canceled" | "cancelled" => Some(TaskItemStatus::Canceled),
+        _ => None,
+    }
+}
+
+fn write(conn: &Connection, project_id: Option<i64>, project_titles: &[String], tasks: &[TaskRow], report: &mut CsvImport) -> Result<()> {
+    let live = project::get_all_projects(conn, &[])?;
+    let mut projects: HashMap<&str, i64> = HashMap::new();
+    for title in project_titles {
+        let id = match live.iter().find(|project| &project.title == title) {
+            Some(project) => project.id,
+            None => {
+                let id = project::create_project(conn, title, None)?;
+                report.project_ids.push(id);
+                id
+            }
+        };
+        projects.insert(title, id);
+    }
+
+    // Pending first so every item gets its place, then closed subtasks before their parents.
+    // A shared item is made by whichever of its rows comes first in task order.
+    let mut created: HashMap<(usize, usize), i64> = HashMap::new();
+    let mut ids: Vec<Vec<i64>> = Vec::with_capacity(tasks.len());
+    for (task_index, task) in tasks.iter().enumerate() {
+        let project_id = project_id.or_else(|| projects.get(task.project_title.as_str()).copied())
+            .ok_or_else(|| AppError::validation("project_title", "must not be empty"))?;
+
+        let task_id = task::create_task(conn, project_id, &task.title, None, task.start_at.as_deref(), task.due_at.as_deref())?;
+        report.task_ids.push(task_id);
+
+        let mut task_ids = Vec::with_capacity(task.items.len());
+        for (item_index, item) in task.items.iter().enumerate() {
+            let first = item.shared.unwrap_or((task_index, item_index));
+            let id = match created.get(&first) {
+                Some(&id) => id,
+                None => {
+                    let first_row = &tasks[first.0].items[first.1];
+                    let id = task_item::create_task_item(conn, &first_row.content, first_row.start_at.as_deref(), first_row.due_at.as_deref())?;
+                    created.insert(first, id);
+                    report.task_item_ids.push(id);
+                    id
+                }
+            };
+            task_item::link_task_item(conn, task_id, id)?;
+            if let Some(parent) = item.parent {
+                task_item::nest_task_item(conn, task_id, id, Some(task_ids[parent]), None)?;
+            }
+            task_ids.push(id);
+        }
+        ids.push(task_ids);
+    }
+
+    // A shared item can have subtasks in one task and a parent in another, so the
+    // closing order follows the nesting of every task at once
+    let mut first_rows: HashMap<i64, &ItemRow> = HashMap::new();
+    let mut subtasks: HashMap<i64, Vec<i64>> = HashMap::new();
+    for (task, task_ids) in tasks.iter().zip(&ids) {
+        for (item, &id) in task.items.iter().zip(task_ids) {
+            first_rows.entry(id).or_insert(item);
+            if let Some(parent) = item.parent {
+                subtasks.entry(task_ids[parent]).or_default().push(id);
+            }
+        }
+    }
+    let mut order = Vec::with_capacity(first_rows.len());
+    let mut seen = HashSet::new();
+    for &id in ids.iter().flatten() {
+        subtasks_first(id, &subtasks, &mut seen, &mut order);
+    }
+    for id in order {
+        let item = first_rows[&id];
+        if item.status != TaskItemStatus::Pending {
+            task_item::update_task_item_content(conn, id, &item.content, item.status)?;
+        }
+    }
+    Ok(())
+}
+
+// Appends the item's subtasks, theirs before them, and then the item, each only once
+fn subtasks_first(id: i64, subtasks: &HashMap<i64, Vec<i64>>, seen: &mut HashSet<i64>, order: &mut Vec<i64>) {
+    if !seen.insert(id) {
+        return;
+    }
+    for &subtask_id in subtasks.get(&id).into_iter().flatten() {
+        subtasks_first(subtask_id, subtasks, seen, order);
+    }
+    order.push(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    fn shape(conn: &Connection, task_id: i64) -> Vec<(String, TaskItemStatus, i64)> {
+        task_item::get_task_items(conn, task_id, &TaskItemFilter::default(), SortOrder::Sequence, None, None).unwrap().items
+            .into_iter()
+            .map(|item| (item.content, item.status, item.depth))
+            .collect()
+    }
+
+    fn columns(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|&(header, field)| (header.to_string(), field.to_string())).collect()
+    }
+
+    #[test]
+    fn shared_items_close_after_subtasks_in_every_task() {
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "Project", None).unwrap();
+        let tasks = ["A", "B"].map(|title| task::create_task(&conn, project_id, title, None, None, None).unwrap());
+        let [parent, shared] = ["parent", "shared"].map(|content| task_item::create_task_item(&conn, content, None, None).unwrap());
+        task_item::link_task_item(&conn, tasks[0], shared).unwrap();
+        task_item::link_task_item(&conn, tasks[1], parent).unwrap();
+        task_item::link_task_item(&conn, tasks[1], shared).unwrap();
+        task_item::nest_task_item(&conn, tasks[1], shared, Some(parent), None).unwrap();
+        task_item::update_task_item_content(&conn, shared, "shared", TaskItemStatus::Completed).unwrap();
+        task_item::update_task_item_content(&conn, parent, "parent", TaskItemStatus::Completed).unwrap();
+
+        let csv = export_csv(&conn, Some(project_id)).unwrap();
+        let report = import_csv(&conn, &csv, Some(project_id), &HashMap::new()).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.task_item_ids.len(), 2);
+        assert_eq!(shape(&conn, report.task_ids[0]), [("shared".to_string(), TaskItemStatus::Completed, 0)]);
+        assert_eq!(shape(&conn, report.task_ids[1]), [
+            ("parent".to_string(), TaskItemStatus::Completed, 0),
+            ("shared".to_string(), TaskItemStatus::Completed, 1),
+        ]);
+    }
+
+    #[test]
+    fn row_errors_name_line_and_field_and_write_nothing() {
+        let conn = db::test_connection();
+        let csv = "project_title,task_title,item_content,item_status,item_due_at\n\
+                   New,x,one,done,\n\
+                   New,x,two,in progress,2026-13-40\n\
+                   ,y,three,,\n\
+                   New,,four,,\n\
+                   New,z,five,weird,\n";
+
+        let report = import_csv(&conn, csv, None, &HashMap::new()).unwrap();
+        let errors: Vec<(u64, &str)> = report.errors.iter().map(|error| (error.line, error.field.as_str())).collect();
+        assert_eq!(errors, [(3, "item_due_at"), (4, "project_title"), (5, "task_title"), (6, "item_status")]);
+        assert!(report.project_ids.is_empty() && report.task_ids.is_empty() && report.task_item_ids.is_empty());
+        assert!(project::get_all_projects(&conn, &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn headers_map_through_columns() {
+        let conn = db::test_connection();
+        let mapping = columns(&[("Project", "project_title"), ("Title", "task_title"), ("Todo", "item_content"), ("State", "Item_Status")]);
+        let report = import_csv(&conn, "Project,Title,Todo,State,Notes\nHome,Chores,Dishes,done,ignored\n", None, &mapping).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(project::get_project(&conn, report.project_ids[0]).unwrap().title, "Home");
+        assert_eq!(shape(&conn, report.task_ids[0]), [("Dishes".to_string(), TaskItemStatus::Completed, 0)]);
+
+        let unknown = columns(&[("Title", "task_title"), ("Todo", "notes")]);
+        assert!(matches!(import_csv(&conn, "Title,Todo\nx,y\n", None, &unknown), Err(AppError::Validation { field: "columns", .. })));
+        let twice = columns(&[("Title", "task_title")]);
+        assert!(matches!(import_csv(&conn, "Title,task_title\nx,y\n", None, &twice), Err(AppError::Validation { field: "columns", .. })));
+        assert!(matches!(import_csv(&conn, "task_title\nx\n", None, &HashMap::new()), Err(AppError::Validation { field: "columns", .. })));
+    }
+
+    #[test]
+    fn rows_with_one_item_id_make_one_item() {
+        let conn = db::test_connection();
+        let csv = "project_title,task_id,task_title,item_id,item_content,item_status\n\
+                   Home,1,Kitchen,7,Buy soap,InProgress\n\
+                   Home,2,Bathroom,7,Ignored content,Completed\n";
+
+        let report = import_csv(&conn, csv, None, &HashMap::new()).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.task_item_ids.len(), 1);
+        let links = task_item::get_item_links(&conn, report.task_item_ids[0]).unwrap();
+        assert_eq!(links.iter().map(|link| link.task_id).collect::<Vec<_>>(), report.task_ids);
+        for task_id in report.task_ids {
+            assert_eq!(shape(&conn, task_id), [("Buy soap".to_string(), TaskItemStatus::InProgress, 0)]);
+        }
+    }
+
+    #[test]
+    fn formula_cells_round_trip() {
+        let cells = ["=SUM(A1)", "+1", "-2", "@user", "'quoted", "''twice", "plain", "a=b"];
+        for cell in cells {
+            let escaped = escape(cell.to_string());
+            assert_eq!(escaped.starts_with('\''), cell.starts_with(ESCAPED), "{}", cell);
+            assert_eq!(unescape(&escaped), cell);
+        }
+
+        let conn = db::test_connection();
+        let project_id = project::create_project(&conn, "=HYPERLINK(\"x\")", None).unwrap();
+        let task_id = task::create_task(&conn, project_id, "@mention", None, None, None).unwrap();
+        for content in ["-1 day", "'quoted"] {
+            let item_id = task_item::create_task_item(&conn, content, None, None).unwrap();
+            task_item::link_task_item(&conn, task_id, item_id).unwrap();
+        }
+
+        let csv = export_csv(&conn, Some(project_id)).unwrap();
+        assert!(csv.contains("'@mention") && csv.contains("'-1 day") && csv.contains("''quoted"));
+        let report = import_csv(&conn, &csv, None, &HashMap::new()).unwrap();
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.project_ids.is_empty());
+        assert_eq!(task::get_task(&conn, report.task_ids[0]).unwrap().title, "@mention");
+        let contents: Vec<String> = shape(&conn, report.task_ids[0]).into_iter().map(|(content, ..)| content).collect();
+        assert_eq!(contents, ["-1 day", "'quoted"]);
+    }
+}
@@ -0,0 +1,290 @@
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::entity::Entity;
+use crate::error::{expect_changed, require_text, AppError, OrNotFound, Result};
+use crate::history::{self, Action};
+
+pub const DEFAULT_COLOR: &str = "#808080";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    // "#rrggbb"
+    pub color: String
+}
+impl Tag {
+    pub fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Tag {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            color: row.get(2)?,
+        })
+    }
+}
+
+// One row a tag is attached to
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Tagging {
+    pub entity: Entity,
+    pub entity_id: i64
+}
+
+pub fn require_color(color: &str) -> Result<()> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return Err(AppError::validation("color", "must look like #rrggbb"));
+    }
+    Ok(())
+}
+
+// Names are unique regardless of case; a clash comes back as a Conflict
+pub fn create_tag(conn: &Connection, name: &str, color: Option<&str>) -> Result<i64> {
+    require_text("name", name)?;
+    let color = color.unwrap_or(DEFAULT_COLOR);
+    require_color(color)?;
+
+    conn.prepare_cached("INSERT INTO tags (name, color) VALUES (?1, ?2)")?
+        .execute((name.trim(), color))?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn get_tags(conn: &Connection) -> Result<Vec<Tag>> {
+    let tags = conn.prepare_cached("SELECT id, name, color FROM tags ORDER BY name")?
+        .query_map([], Tag::from_row)?
+        .collect::<rusqlite::Result<Vec<Tag>>>()?;
+    Ok(tags)
+}
+
+pub fn get_tag(conn: &Connection, id: i64) -> Result<Tag> {
+    let tag = conn.prepare_cached("SELECT id, name, color FROM tags WHERE id = ?1")?
+        .query_row([id], Tag::from_row)
+        .or_not_found(Entity::Tag, id)?;
+    Ok(tag)
+}
+
+// Renames and/or recolours the tag
+pub fn update_tag(conn: &Connection, id: i64, name: &str, color: &str) -> Result<()> {
+    require_text("name", name)?;
+    require_color(color)?;
+
+    let changed = conn.prepare_cached("UPDATE tags SET name = ?1, color = ?2 WHERE id = ?3")?
+        .execute((name.trim(), color, id))?;
+    expect_changed(changed, Entity::Tag, id)
+}
+
+// Tags aren't trashed, deleting one detaches it from everything right away
+pub fn delete_tag(conn: &Connection, id: i64) -> Result<()> {
+    let changed = conn.prepare_cached("DELETE FROM tags WHERE id = ?1")?
+        .execute([id])?;
+    expect_changed(changed, Entity::Tag, id)
+}
+
+// Puts a deleted tag back under its old id, with the taggings it had
+pub fn restore_tag(conn: &Connection, tag: &Tag, taggings: &[Tagging]) -> Result<()> {
+    conn.prepare_cached("INSERT INTO tags (id, name, color) VALUES (?1, ?2, ?3)")?
+        .execute((tag.id, &tag.name, &tag.color))?;
+
+    let mut insert = conn.prepare_cached("INSERT OR IGNORE INTO taggings (tag_id, entity, entity_id) VALUES (?1, ?2, ?3)")?;
+    for tagging in taggings {
+        insert.execute((tag.id, tagging.entity, tagging.entity_id))?;
+    }
+    Ok(())
+}
+
+pub fn get_taggings(conn: &Connection, tag_id: i64) -> Result<Vec<Tagging>> {
+    let taggings = conn.prepare_cached("SELECT entity, entity_id FROM taggings WHERE tag_id = ?1 ORDER BY entity, entity_id")?
+        .query_map([tag_id], |row| Ok(Tagging { entity: row.get(0)?, entity_id: row.get(1)? }))?
+        .collect::<rusqlite::Result<Vec<Tagging>>>()?;
+    Ok(taggings)
+}
+
+// Moves every tagging of `source_id` onto `target_id` and deletes the source tag
+pub fn merge_tags(conn: &Connection, source_id: i64, target_id: i64) -> Result<()> {
+    if source_id == target_id {
+        return Err(AppError::validation("target_id", "can't merge a tag into itself"));
+    }
+    get_tag(conn, source_id)?;
+    get_tag(conn, target_id)?;
+
+    // Rows carrying both tags keep their target tagging, the source one goes with the tag
+    conn.prepare_cached("UPDATE OR IGNORE taggings SET tag_id = ?2 WHERE tag_id = ?1")?
+        .execute((source_id, target_id))?;
+    delete_tag(conn, source_id)
+}
+
+fn require_live(conn: &Connection, entity: Entity, id: i64) -> Result<()> {
+    if entity == Entity::Tag {
+        return Err(AppError::validation("entity", "tags can't be tagged"));
+    }
+    conn.prepare_cached(&format!("SELECT id FROM {} WHERE id = ?1 AND deleted_at IS NULL", entity.table()))?
+        .query_row([id], |row| row.get::<_, i64>(0))
+        .or_not_found(entity, id)?;
+    Ok(())
+}
+
+pub fn tag_entity(conn: &Connection, tag_id: i64, entity: Entity, entity_id: i64) -> Result<()> {
+    let tag = get_tag(conn, tag_id)?;
+    require_live(conn, entity, entity_id)?;
+
+    let changed = conn.prepare_cached("INSERT OR IGNORE INTO taggings (tag_id, entity, entity_id) VALUES (?1, ?2, ?3)")?
+        .execute((tag_id, entity, entity_id))?;
+    if changed > 0 {
+        history::record(conn, entity, entity_id, Action::Tagged, None, Some(json!({ "tag_id": tag_id, "name": tag.name })))?;
+    }
+    Ok(())
+}
+
+pub fn untag_entity(conn: &Connection, tag_id: i64, entity: Entity, entity_id: i64) -> Result<()> {
+    let tag = get_tag(conn, tag_id)?;
+
+    let changed = conn.prepare_cached("DELETE FROM taggings WHERE tag_id = ?1 AND entity = ?2 AND entity_id = ?3")?
+        .execute((tag_id, entity, entity_id))?;
+    expect_changed(changed, entity, entity_id)?;
+
+    history::record(conn, entity, entity_id, Action::Untagged, Some(json!({ "tag_id": tag_id, "name": tag.name })), None)
+}
+
+pub fn get_entity_tags(conn: &Connection, entity: Entity, entity_id: i64) -> Result<Vec<Tag>> {
+    let tags = conn.prepare_cached(
+        "SELECT tags.id, tags.name, tags.color
+         FROM tags
+         JOIN taggings ON taggings.tag_id = tags.id
+         WHERE taggings.entity = ?1 AND taggings.entity_id = ?2
+         ORDER BY tags.name"
+    )?
+        .query_map((entity, entity_id), Tag::from_row)?
+        .collect::<rusqlite::Result<Vec<Tag>>>()?;
+    Ok(tags)
+}
+
+// Gives a copied row the tags of the row it was copied from
+pub fn copy_taggings(conn: &Connection, entity: Entity, from_id: i64, to_id: i64) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT OR IGNORE INTO taggings (tag_id, entity, entity_id)
+         SELECT tag_id, entity, ?3 FROM taggings WHERE entity = ?1 AND entity_id = ?2"
+    )?.execute((entity, from_id, to_id))?;
+    Ok(())
+}
+
+// Tag filters are bound as a JSON array of tag ids (NULL for no filter), so one cached
+// statement serves any number of tags
+pub fn filter_param(tags: &[i64]) -> Option<String> {
+    if tags.is_empty() {
+        return None;
+    }
+    let mut tags = tags.to_vec();
+    tags.sort_unstable();
+    tags.dedup();
+    Some(json!(tags).to_string())
+}
+
+// SQL condition keeping rows that carry every tag in the `param` array
+pub fn filter_condition(entity: Entity, id_column: &str, param: &str) -> String {
+    format!(
+        "({param} IS NULL OR (
+             SELECT COUNT(DISTINCT taggings.tag_id) FROM taggings
+             WHERE taggings.entity = '{entity}' AND taggings.entity_id = {id_column}
+             AND taggings.tag_id IN (SELECT value FROM json_each({param}))
+         ) = json_array_length({param}))",
+        param = param, entity = entity.as_str(), id_column = id_column
+    )
+}
+
+// Taggings can't reference their rows through a foreign key, so they're cleared
+// here once the rows are gone for good
+pub fn remove_orphaned_taggings(conn: &Connection) -> Result<()> {
+    conn.prepare_cached(
+        "DELETE FROM taggings WHERE
+             (entity = 'project' AND entity_id NOT IN (SELECT id FROM projects))
+          OR (entity = 'task' AND entity_id NOT IN (SELECT id FROM tasks))
+          OR (entity = 'task_item' AND entity_id NOT IN (SELECT id FROM task_items))"
+    )?.execute([])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::priority::SortOrder;
+    use crate::task_item::TaskItemFilter;
+    use crate::{db, project, task, task_item};
+
+    #[test]
+    fn filters_keep_rows_with_every_tag() {
+        let conn = db::test_connection();
+        let [home, urgent, unused] = ["home", "urgent", "unused"].map(|name| create_tag(&conn, name, None).unwrap());
+        let projects = ["Both", "Home only", "Neither"].map(|title| project::create_project(&conn, title, None).unwrap());
+        let tasks = ["Both", "Urgent only"].map(|title| task::create_task(&conn, projects[0], title, None, None, None).unwrap());
+        let items = ["Both", "Home only"].map(|content| {
+            let id = task_item::create_task_item(&conn, content, None, None).unwrap();
+            task_item::link_task_item(&conn, tasks[0], id).unwrap();
+            id
+        });
+        for (tag_id, entity, id) in [
+            (home, Entity::Project, projects[0]), (urgent, Entity::Project, projects[0]), (home, Entity::Project, projects[1]),
+            (home, Entity::Task, tasks[0]), (urgent, Entity::Task, tasks[0]), (urgent, Entity::Task, tasks[1]),
+            (home, Entity::TaskItem, items[0]), (urgent, Entity::TaskItem, items[0]), (home, Entity::TaskItem, items[1]),
+        ] {
+            tag_entity(&conn, tag_id, entity, id).unwrap();
+        }
+
+        let project_titles = |tags: &[i64]| -> Vec<String> {
+            project::get_all_projects(&conn, tags).unwrap().into_iter().map(|project| project.title).collect()
+        };
+        let task_titles = |tags: &[i64]| -> Vec<String> {
+            task::get_tasks(&conn, projects[0], SortOrder::Sequence, tags).unwrap().into_iter().map(|task| task.title).collect()
+        };
+        let item_contents = |tags: &[i64]| -> Vec<String> {
+            let filter = TaskItemFilter { tags: tags.to_vec(), ..TaskItemFilter::default() };
+            task_item::get_task_items(&conn, tasks[0], &filter, SortOrder::Sequence, None, None).unwrap().items
+                .into_iter().map(|item| item.content).collect()
+        };
+
+        assert_eq!(project_titles(&[]), ["Both", "Home only", "Neither"]);
+        assert_eq!(project_titles(&[home]), ["Both", "Home only"]);
+        assert_eq!(project_titles(&[home, urgent, home]), ["Both"]);
+        assert!(project_titles(&[home, unused]).is_empty());
+        assert_eq!(task_titles(&[urgent]), ["Both", "Urgent only"]);
+        assert_eq!(task_titles(&[urgent, home]), ["Both"]);
+        assert_eq!(item_contents(&[home]), ["Both", "Home only"]);
+        assert_eq!(item_contents(&[home, urgent]), ["Both"]);
+    }
+
+    #[test]
+    fn merge_moves_taggings_without_duplicates() {
+        let conn = db::test_connection();
+        let [source, target] = ["errands", "shopping"].map(|name| create_tag(&conn, name, None).unwrap());
+        let [both, source_only] = ["Both", "Source only"].map(|title| project::create_project(&conn, title, None).unwrap());
+        tag_entity(&conn, source, Entity::Project, both).unwrap();
+        tag_entity(&conn, target, Entity::Project, both).unwrap();
+        tag_entity(&conn, source, Entity::Project, source_only).unwrap();
+
+        assert!(matches!(merge_tags(&conn, target, target), Err(AppError::Validation { field: "target_id", .. })));
+        merge_tags(&conn, source, target).unwrap();
+        assert!(matches!(get_tag(&conn, source), Err(AppError::NotFound { .. })));
+        let taggings: Vec<i64> = get_taggings(&conn, target).unwrap().into_iter().map(|tagging| tagging.entity_id).collect();
+        assert_eq!(taggings, [both, source_only]);
+        assert_eq!(get_entity_tags(&conn, Entity::Project, both).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn names_clash_regardless_of_case() {
+        let conn = db::test_connection();
+        let home = create_tag(&conn, "Home", None).unwrap();
+        let work = create_tag(&conn, " Work ", Some("#112233")).unwrap();
+        assert_eq!(get_tag(&conn, work).unwrap().name, "Work");
+
+        assert!(matches!(create_tag(&conn, "home", None), Err(AppError::Conflict { .. })));
+        assert!(matches!(update_tag(&conn, work, "HOME", "#112233"), Err(AppError::Conflict { .. })));
+        // Changing only the case of its own name is fine
+        update_tag(&conn, home, "HOME", "#445566").unwrap();
+        assert_eq!(get_tag(&conn, home).unwrap().name, "HOME");
+        assert!(matches!(create_tag(&conn, "Red", Some("red")), Err(AppError::Validation { field: "color", .. })));
+        assert!(matches!(update_tag(&conn, 99, "Gone", DEFAULT_COLOR), Err(AppError::NotFound { .. })));
+    }
+}
@@ -1,7 +1,18 @@
-use rusqlite::{Connection, Result, Error};
+use rusqlite::{Connection, OptionalExtension};
 use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
 
-pub const DB_PATH: &str = "yuno-tasks.db";
+use crate::entity::Entity;
+use crate::error::{expect_changed, require_text, AppError, OrNotFound, Result};
+use crate::history::{self, Action};
+use crate::priority::{Priority, SortOrder};
+use crate::project;
+use crate::rank::{self, Placement, Scope};
+use crate::recurrence::{self, Occurrence, Rule};
+use crate::schedule;
+use crate::tag;
+use crate::task_item::{self, TaskItemFilter, TaskItemStatus};
 
 #[derive(Debug, Serialize)]
 pub struct Task {
@@ -9,132 +20,372 @@ pub struct Task {
     pub project_id: i64,
     pub title: String,
     pub description: Option<String>,
-    pub sequence: i64
+    // Position among the project's live tasks, from 1
+    pub sequence: i64,
+    // NULL for tasks created before timestamps were tracked
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    // Set once the task has items and all of them are completed or canceled
+    pub completed_at: Option<String>,
+    // Local wall-clock times, see schedule.rs
+    pub start_at: Option<String>,
+    pub due_at: Option<String>,
+    pub priority: Option<Priority>,
+    // Canonical rule, see recurrence.rs
+    pub recurrence: Option<String>,
+    // Waits on a live prerequisite that has items and isn't completed yet, see dependency.rs
+    pub blocked: bool
 }
+impl Task {
+    pub const COLUMNS: &'static str = "tasks.id, tasks.project_id, tasks.title, tasks.description,
+        (SELECT COUNT(*) FROM tasks AS earlier
+         WHERE earlier.project_id = tasks.project_id AND earlier.deleted_at IS NULL AND (earlier.rank, earlier.id) <= (tasks.rank, tasks.id)),
+        tasks.created_at, tasks.updated_at, tasks.completed_at, tasks.start_at, tasks.due_at, tasks.priority, tasks.recurrence,
+        EXISTS (SELECT 1 FROM task_dependencies
+                JOIN tasks AS prerequisite ON prerequisite.id = task_dependencies.depends_on_id
+                JOIN projects AS prerequisite_project ON prerequisite_project.id = prerequisite.project_id
+                WHERE task_dependencies.task_id = tasks.id AND prerequisite.completed_at IS NULL
+                AND prerequisite.deleted_at IS NULL AND prerequisite_project.deleted_at IS NULL
+                AND EXISTS (SELECT 1 FROM task_item_links
+                            JOIN task_items ON task_items.id = task_item_links.item_id
+                            WHERE task_item_links.task_id = prerequisite.id AND task_items.deleted_at IS NULL))";
 
-pub fn create_task(project_id: i64, title: &str, description: Option<&str>) -> Result<i64> {
-    let conn = Connection::open(DB_PATH)?;
+    pub fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(Task {
+            id: row.get(0)?,
+            project_id: row.get(1)?,
+            title: row.get(2)?,
+            description: row.get(3)?,
+            sequence: row.get(4)?,
+            created_at: row.get(5)?,
+            updated_at: row.get(6)?,
+            completed_at: row.get(7)?,
+            start_at: row.get(8)?,
+            due_at: row.get(9)?,
+            priority: row.get(10)?,
+            recurrence: row.get(11)?,
+            blocked: row.get(12)?,
+        })
+    }
+}
 
-    let max_sequence: i64 = conn.query_row(
-        "SELECT COALESCE(MAX(sequence), 0) FROM tasks WHERE project_id = ?1",
-        [project_id],
-        |row| row.get(0)
-    )?;
+pub fn create_task(conn: &Connection, project_id: i64, title: &str, description: Option<&str>, start_at: Option<&str>, due_at: Option<&str>) -> Result<i64> {
+    require_text("title", title)?;
+    let (start_at, due_at) = schedule::normalize_dates(conn, start_at, due_at)?;
+    require_live_project(conn, project_id)?;
 
-    conn.execute(
-        "INSERT INTO tasks (project_id, title, description, sequence) VALUES (?1, ?2, ?3, ?4)",
-        (project_id, title, description, max_sequence + 1),
-    )?;
+    conn.prepare_cached(
+        "INSERT INTO tasks (project_id, title, description, rank, start_at, due_at, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, datetime('now'), datetime('now'))"
+    )?.execute((project_id, title, description, rank::next(conn, Scope::Tasks(project_id))?, &start_at, &due_at))?;
+    let id = conn.last_insert_rowid();
 
-    Ok(conn.last_insert_rowid())
+    history::record(
+        conn, Entity::Task, id, Action::Created, None,
+        Some(json!({ "project_id": project_id, "title": title, "description": description, "start_at": start_at, "due_at": due_at })),
+    )?;
+    project::refresh_completion(conn, project_id)?;
+    Ok(id)
 }
 
-pub fn get_tasks(project_id: i64) -> Result<Vec<Task>> {
-    let conn = Connection::open(DB_PATH)?;
+fn require_live_project(conn: &Connection, project_id: i64) -> Result<()> {
+    conn.prepare_cached("SELECT id FROM projects WHERE id = ?1 AND deleted_at IS NULL")?
+        .query_row([project_id], |row| row.get::<_, i64>(0))
+        .or_not_found(Entity::Project, project_id)?;
+    Ok(())
+}
 
-    let mut stmt = conn.prepare("SELECT * FROM tasks WHERE project_id = ?1 ORDER BY sequence")?;
+// Only tasks carrying all of `tags`, when any are given
+pub fn get_tasks(conn: &Connection, project_id: i64, order: SortOrder, tags: &[i64]) -> Result<Vec<Task>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {}
+         FROM tasks
+         JOIN projects ON projects.id = tasks.project_id
+         WHERE tasks.project_id = ?1 AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL AND {}
+         ORDER BY {}, tasks.id",
+        Task::COLUMNS, tag::filter_condition(Entity::Task, "tasks.id", "?2"), order.order_by("tasks", "tasks")
+    ))?;
 
     let tasks = stmt
-        .query_map([project_id], |row| {
-            Ok(Task {
-                id: row.get(0)?,
-                project_id: row.get(1)?,
-                title: row.get(2)?,
-                description: row.get(3)?,
-                sequence: row.get(4)?
-            })
-        })?
-        .collect::<Result<Vec<Task>, _>>()?;
+        .query_map((project_id, tag::filter_param(tags)), Task::from_row)?
+        .collect::<rusqlite::Result<Vec<Task>>>()?;
 
     Ok(tasks)
 }
 
-pub fn update_task_content(task_id: i64, title: &str, description: Option<&str>) -> Result<()> {
-    let conn = Connection::open(DB_PATH)?;
+pub fn update_task_content(conn: &Connection, task_id: i64, title: &str, description: Option<&str>) -> Result<()> {
+    require_text("title", title)?;
+    let old = get_task(conn, task_id)?;
 
-    conn.execute(
-        "UPDATE tasks SET title = ?1, description = ?2 WHERE id = ?3",
-        (title, description, task_id)
-    )?;
+    let changed = conn.prepare_cached("UPDATE tasks SET title = ?1, description = ?2, updated_at = datetime('now') WHERE id = ?3 AND deleted_at IS NULL")?
+        .execute((title, description, task_id))?;
+    expect_changed(changed, Entity::Task, task_id)?;
 
-    Ok(())
+    history::record(
+        conn, Entity::Task, task_id, Action::Edited,
+        Some(json!({ "title": old.title, "description": old.description })),
+        Some(json!({ "title": title, "description": description })),
+    )
 }
 
-pub fn update_task_sequence(task_id: i64, new_sequence: i64) -> Result<(), Error> {
-    println!("This is the task: {} with this new sequence: {}", task_id, new_sequence);
-    let mut conn = Connection::open(DB_PATH)?;
-    let tx = conn.transaction()?;
+pub fn update_task_schedule(conn: &Connection, task_id: i64, start_at: Option<&str>, due_at: Option<&str>) -> Result<()> {
+    let (start_at, due_at) = schedule::normalize_dates(conn, start_at, due_at)?;
+    let old = get_task(conn, task_id)?;
 
-    // Get the project_id for the task
-    let project_id: i64 = tx.query_row(
-        "SELECT project_id FROM tasks WHERE id = ?1",
-        [task_id],
-        |row| row.get(0),
-    )?;
+    conn.prepare_cached("UPDATE tasks SET start_at = ?1, due_at = ?2, updated_at = datetime('now') WHERE id = ?3")?
+        .execute((&start_at, &due_at, task_id))?;
 
-    // Make copy of tasks in array
-    let mut tasks = tx.prepare("SELECT id, sequence FROM tasks WHERE project_id = ?1 ORDER BY sequence")?
-        .query_map([project_id], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?
-        .collect::<Result<Vec<(i64, i64)>>>()?;
+    history::record(
+        conn, Entity::Task, task_id, Action::Edited,
+        Some(json!({ "start_at": old.start_at, "due_at": old.due_at })),
+        Some(json!({ "start_at": start_at, "due_at": due_at })),
+    )
+}
 
-    // Find the project and move to new position
-    let index = tasks.iter().position(|(id, _)| *id == task_id).ok_or_else(|| Error::InvalidQuery)?;
-    let (_, old_sequence) = tasks.remove(index);
+pub fn update_task_priority(conn: &Connection, task_id: i64, priority: Option<Priority>) -> Result<()> {
+    let old = get_task(conn, task_id)?;
 
-    // Ensure new_sequence is within bounds
-    let new_index = new_sequence as usize - 1;
-    if new_index > tasks.len() {
-        return Err(Error::InvalidQuery);
-    }
+    conn.prepare_cached("UPDATE tasks SET priority = ?1, updated_at = datetime('now') WHERE id = ?2")?
+        .execute((priority, task_id))?;
+
+    history::record(conn, Entity::Task, task_id, Action::Edited, Some(json!({ "priority": old.priority })), Some(json!({ "priority": priority })))
+}
 
-    tasks.insert(new_index, (task_id, old_sequence));
+// Moves the task within its project
+pub fn move_task(conn: &Connection, task_id: i64, to: Placement) -> Result<()> {
+    let old = get_task(conn, task_id)?;
+    rank::move_row(conn, Scope::Tasks(old.project_id), task_id, to)?;
 
-    // Step 1: Temporarily set sequences to negative values
-    for (new_index, (id, _)) in tasks.iter().enumerate() {
-        let temp_seq = -(new_index as i64 + 1);
-        tx.execute(
-            "UPDATE tasks SET sequence = ?1 WHERE id = ?2",
-            (temp_seq, *id)
-        )?;
+    conn.prepare_cached("UPDATE tasks SET updated_at = datetime('now') WHERE id = ?1")?
+        .execute([task_id])?;
+    let new = get_task(conn, task_id)?;
+    history::record(conn, Entity::Task, task_id, Action::Moved, Some(json!({ "sequence": old.sequence })), Some(json!({ "sequence": new.sequence })))
+}
+
+// Moves the task to another project, appended unless a `position` is given. Its item
+// links go with it; both projects' ordering and completion are kept up to date.
+pub fn move_to_project(conn: &Connection, task_id: i64, project_id: i64, position: Option<i64>) -> Result<()> {
+    let old = get_task(conn, task_id)?;
+    require_live_project(conn, project_id)?;
+    if old.project_id == project_id && position.is_none() {
+        return Ok(());
+    }
+    if let Some(position) = position {
+        rank::require_position(conn, Scope::Tasks(project_id), "position", position, old.project_id != project_id)?;
     }
 
-    // Step 2: Set sequences to their final positive values
-    for (new_index, (id, _)) in tasks.iter().enumerate() {
-        let final_seq = (new_index + 1) as i64;
-        tx.execute(
-            "UPDATE tasks SET sequence = ?1 WHERE id = ?2",
-            (final_seq, *id)
-        )?;
+    if old.project_id != project_id {
+        conn.prepare_cached("UPDATE tasks SET project_id = ?1, rank = ?2, updated_at = datetime('now') WHERE id = ?3")?
+            .execute((project_id, rank::next(conn, Scope::Tasks(project_id))?, task_id))?;
+    }
+    if let Some(position) = position {
+        rank::move_row(conn, Scope::Tasks(project_id), task_id, Placement::Position(position))?;
     }
 
-    tx.commit()?;
-    Ok(())
+    let new = get_task(conn, task_id)?;
+    history::record(
+        conn, Entity::Task, task_id, Action::Moved,
+        Some(json!({ "project_id": old.project_id, "sequence": old.sequence })),
+        Some(json!({ "project_id": new.project_id, "sequence": new.sequence })),
+    )?;
+    project::refresh_completion(conn, old.project_id)?;
+    project::refresh_completion(conn, project_id)
 }
 
-pub fn get_task(id: i64) -> Result<Task> {
-    let conn = Connection::open(DB_PATH)?;
-
-    let tasks = conn.query_row(
-        "SELECT * FROM tasks WHERE id = ?1",
-        [id],
-        |row| {
-            Ok(Task {
-                id: row.get(0)?,
-                project_id: row.get(1)?,
-                title: row.get(2)?,
-                description: row.get(3)?,
-                sequence: row.get(4)?,
-            })
-        }
+// Copies the task and its tags to the end of a project. A shallow copy links the same
+// items, so they're shared with the original; a deep copy links duplicates instead.
+pub fn copy_task(conn: &Connection, task_id: i64, project_id: i64, deep: bool) -> Result<i64> {
+    let task = get_task(conn, task_id)?;
+    require_live_project(conn, project_id)?;
+
+    conn.prepare_cached(
+        "INSERT INTO tasks (project_id, title, description, rank, start_at, due_at, priority, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, datetime('now'), datetime('now'))"
+    )?.execute((
+        project_id, &task.title, &task.description, rank::next(conn, Scope::Tasks(project_id))?,
+        &task.start_at, &task.due_at, task.priority,
+    ))?;
+    let id = conn.last_insert_rowid();
+
+    history::record(
+        conn, Entity::Task, id, Action::Created, None,
+        Some(json!({ "project_id": project_id, "title": task.title, "description": task.description, "copied_from": task_id })),
     )?;
+    tag::copy_taggings(conn, Entity::Task, task_id, id)?;
+
+    // Items come in tree order, so each parent is linked before its subtasks and
+    // appending keeps every level in the original's sequence
+    let items = task_item::get_task_items(conn, task_id, &TaskItemFilter::default(), SortOrder::Sequence, None, None)?.items;
+    let mut copies = HashMap::new();
+    for item in items {
+        let item_id = if deep { task_item::copy_task_item(conn, item.id)? } else { item.id };
+        task_item::link_task_item(conn, id, item_id)?;
+        if let Some(parent_id) = item.parent_id.and_then(|parent_id| copies.get(&parent_id)) {
+            task_item::nest_task_item(conn, id, item_id, Some(*parent_id), None)?;
+        }
+        copies.insert(item.id, item_id);
+    }
+
+    refresh_completion(conn, id)?;
+    Ok(id)
+}
+
+pub fn get_task(conn: &Connection, id: i64) -> Result<Task> {
+    let tasks = conn.prepare_cached(&format!(
+        "SELECT {}
+         FROM tasks
+         JOIN projects ON projects.id = tasks.project_id
+         WHERE tasks.id = ?1 AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL",
+        Task::COLUMNS
+    ))?
+        .query_row([id], Task::from_row)
+        .or_not_found(Entity::Task, id)?;
 
     Ok(tasks)
 }
 
 
-pub fn delete_task(id: i64) -> Result<()> {
-    let conn = Connection::open(DB_PATH)?;
+// Moves the task to the trash, its item links stay in place for a restore
+pub fn delete_task(conn: &Connection, id: i64) -> Result<()> {
+    let project_id: i64 = conn.prepare_cached("SELECT project_id FROM tasks WHERE id = ?1 AND deleted_at IS NULL")?
+        .query_row([id], |row| row.get(0))
+        .or_not_found(Entity::Task, id)?;
+
+    conn.prepare_cached("UPDATE tasks SET deleted_at = datetime('now') WHERE id = ?1")?
+        .execute([id])?;
+    history::record(conn, Entity::Task, id, Action::Trashed, None, None)?;
+    project::refresh_completion(conn, project_id)?;
 
-    conn.execute("DELETE FROM tasks WHERE id = ?1", [id])?;
     Ok(())
 }
+
+// Takes the task out of the trash and appends it after the project's live tasks
+pub fn restore_task(conn: &Connection, id: i64) -> Result<()> {
+    let (project_id, project_trashed): (i64, bool) = conn.prepare_cached(
+        "SELECT tasks.project_id, projects.deleted_at IS NOT NULL
+         FROM tasks
+         JOIN projects ON projects.id = tasks.project_id
+         WHERE tasks.id = ?1 AND tasks.deleted_at IS NOT NULL"
+    )?
+        .query_row([id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .or_not_found(Entity::Task, id)?;
+
+    if project_trashed {
+        return Err(AppError::conflict(format!("project {} is in the trash, restore it first", project_id)));
+    }
+
+    conn.prepare_cached("UPDATE tasks SET deleted_at = NULL, rank = ?1 WHERE id = ?2")?
+        .execute((rank::next(conn, Scope::Tasks(project_id))?, id))?;
+    history::record(conn, Entity::Task, id, Action::Restored, None, None)?;
+    project::refresh_completion(conn, project_id)
+}
+
+// A task is complete once it has live items and all of them are completed or canceled;
+// its project is refreshed after it. Called by anything that changes a task's items.
+// A recurring task that just became complete gets its next occurrence.
+pub fn refresh_completion(conn: &Connection, id: i64) -> Result<()> {
+    let was_completed: bool = conn.prepare_cached("SELECT completed_at IS NOT NULL FROM tasks WHERE id = ?1")?
+        .query_row([id], |row| row.get(0))
+        .or_not_found(Entity::Task, id)?;
+    conn.prepare_cached(
+        "UPDATE tasks SET completed_at = CASE
+             WHEN EXISTS (
+                 SELECT 1 FROM task_item_links
+                 JOIN task_items ON task_items.id = task_item_links.item_id
+                 WHERE task_item_links.task_id = ?1 AND task_items.deleted_at IS NULL
+             )
+             AND NOT EXISTS (
+                 SELECT 1 FROM task_item_links
+                 JOIN task_items ON task_items.id = task_item_links.item_id
+                 WHERE task_item_links.task_id = ?1 AND task_items.deleted_at IS NULL
+                 AND task_items.status NOT IN (?2, ?3)
+             )
+             THEN COALESCE(completed_at, datetime('now'))
+             END
+         WHERE id = ?1"
+    )?.execute((id, TaskItemStatus::Completed, TaskItemStatus::Canceled))?;
+
+    let completed: bool = conn.prepare_cached("SELECT completed_at IS NOT NULL FROM tasks WHERE id = ?1")?
+        .query_row([id], |row| row.get(0))?;
+    if completed && !was_completed {
+        recur(conn, id)?;
+    }
+
+    let project_id: i64 = conn.prepare_cached("SELECT project_id FROM tasks WHERE id = ?1")?
+        .query_row([id], |row| row.get(0))
+        .or_not_found(Entity::Task, id)?;
+    project::refresh_completion(conn, project_id)
+}
+
+// A blank rule stops the task recurring; occurrences already made stay
+pub fn update_task_recurrence(conn: &Connection, task_id: i64, recurrence: Option<&str>) -> Result<()> {
+    let recurrence = recurrence::normalize(recurrence)?;
+    let old = get_task(conn, task_id)?;
+
+    conn.prepare_cached("UPDATE tasks SET recurrence = ?1, updated_at = datetime('now') WHERE id = ?2")?
+        .execute((&recurrence, task_id))?;
+
+    history::record(
+        conn, Entity::Task, task_id, Action::Edited,
+        Some(json!({ "recurrence": old.recurrence })), Some(json!({ "recurrence": recurrence })),
+    )
+}
+
+// The next occurrence of a recurring task is a deep copy at the end of its project with
+// every item reopened, and the items' dates moved along with the task's. As with items
+// there is only one per task: a live occurrence is left alone, a trashed one restored.
+fn recur(conn: &Connection, id: i64) -> Result<()> {
+    let task = match get_task(conn, id) {
+        Ok(task) => task,
+        // Trashed tasks and tasks of trashed projects don't recur
+        Err(AppError::NotFound { .. }) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let Some(rule) = task.recurrence.as_deref() else {
+        return Ok(());
+    };
+
+    let recurred: Option<(i64, bool)> = conn.prepare_cached(
+        "SELECT recurred.id, recurred.deleted_at IS NOT NULL
+         FROM tasks JOIN tasks AS recurred ON recurred.id = tasks.recurred_id
+         WHERE tasks.id = ?1"
+    )?
+        .query_row([id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()?;
+    match recurred {
+        Some((recurred_id, true)) => return restore_task(conn, recurred_id),
+        Some((_, false)) => return Ok(()),
+        None => {}
+    }
+
+    let today: String = conn.prepare_cached("SELECT date('now', 'localtime')")?
+        .query_row([], |row| row.get(0))?;
+    let next = recurrence::next_occurrence(&Rule::parse(rule)?, task.start_at.as_deref(), task.due_at.as_deref(), &today)?;
+    let Some(Occurrence { start_at, due_at, rule }) = next else {
+        return Ok(());
+    };
+
+    let copy = copy_task(conn, id, task.project_id, true)?;
+    // Tree order puts parents first, so no subtask is reopened under a finished item
+    let items = task_item::get_task_items(conn, copy, &TaskItemFilter::default(), SortOrder::Sequence, None, None)?.items;
+    for item in items.iter().filter(|item| item.status != TaskItemStatus::Pending) {
+        task_item::update_task_item_content(conn, item.id, &item.content, TaskItemStatus::Pending)?;
+    }
+    let anchors = (task.due_at.as_deref().or(task.start_at.as_deref()), due_at.as_deref().or(start_at.as_deref()));
+    if let (Some(from), Some(to)) = anchors {
+        let shift = format!("{:+} seconds", recurrence::offset(from, to)?);
+        conn.prepare_cached(
+            "UPDATE task_items SET start_at = datetime(start_at, ?1), due_at = datetime(due_at, ?1)
+             WHERE id IN (SELECT item_id FROM task_item_links WHERE task_id = ?2)"
+        )?.execute((&shift, copy))?;
+    }
+
+    conn.prepare_cached("UPDATE tasks SET start_at = ?1, due_at = ?2, recurrence = ?3 WHERE id = ?4")?
+        .execute((&start_at, &due_at, rule.to_string(), copy))?;
+    conn.prepare_cached("UPDATE tasks SET recurred_id = ?1 WHERE id = ?2")?
+        .execute((copy, id))?;
+    history::record(
+        conn, Entity::Task, copy, Action::Edited, None,
+        Some(json!({ "start_at": start_at, "due_at": due_at, "recurrence": rule.to_string(), "recurred_from": id })),
+    )
+}
@@ -1,21 +1,52 @@
-use rusqlite::{Connection, Result, Error};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{Connection, OptionalExtension};
 use serde::{Serialize, Deserialize};
+use serde_json::json;
 
-pub const DB_PATH: &str = "yuno-tasks.db";
+use crate::entity::Entity;
+use crate::error::{expect_changed, require_text, AppError, OrNotFound, Result};
+use crate::history::{self, Action};
+use crate::priority::{Priority, SortOrder};
+use crate::rank::{self, Placement, Scope};
+use crate::recurrence::{self, Occurrence, Rule};
+use crate::schedule;
+use crate::tag;
+use crate::task;
 
 #[derive(Debug, Serialize)]
 pub struct TaskItem {
     pub id: i64,
     pub content: String,
-    #[serde(skip_serializing)]
-    pub status: TaskItemStatus
+    pub status: TaskItemStatus,
+    // NULL for items created before timestamps were tracked
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    // Set while the status is Completed or Canceled
+    pub completed_at: Option<String>,
+    // Local wall-clock times, see schedule.rs
+    pub start_at: Option<String>,
+    pub due_at: Option<String>,
+    pub priority: Option<Priority>,
+    // Canonical rule, see recurrence.rs
+    pub recurrence: Option<String>
 }
 impl TaskItem {
-    pub fn from_row(row: &rusqlite::Row) -> Result<Self> {
+    pub const COLUMNS: &'static str = "task_items.id, task_items.content, task_items.status,
+        task_items.created_at, task_items.updated_at, task_items.completed_at, task_items.start_at, task_items.due_at,
+        task_items.priority, task_items.recurrence";
+
+    pub fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
         Ok(TaskItem {
             id: row.get(0)?,
             content: row.get(1)?,
-            status: TaskItemStatus::from(row.get::<_, i64>(2)?),
+            status: row.get(2)?,
+            created_at: row.get(3)?,
+            updated_at: row.get(4)?,
+            completed_at: row.get(5)?,
+            start_at: row.get(6)?,
+            due_at: row.get(7)?,
+            priority: row.get(8)?,
+            recurrence: row.get(9)?,
         })
     }
 }
@@ -24,21 +55,53 @@ impl TaskItem {
 pub struct TaskItemLink {
     pub task_id: i64,
     pub item_id: i64,
-    pub sequence: i64
+    // Position among the task's open items, from 1
+    pub sequence: Option<i64>
 }
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+
+// Computes TaskItemLink.sequence from the ranks, counting only the item's siblings
+// (same task and parent item); NULL for closed items
+const LINK_SEQUENCE: &str = "CASE WHEN task_item_links.rank IS NOT NULL THEN (
+    SELECT COUNT(*) FROM task_item_links AS earlier
+    WHERE earlier.task_id = task_item_links.task_id AND earlier.parent_id IS task_item_links.parent_id
+    AND earlier.rank IS NOT NULL
+    AND (earlier.rank, earlier.item_id) <= (task_item_links.rank, task_item_links.item_id)
+) END";
+// Crosses IPC as the variant name ("Pending", "InProgress", ...) and is stored as
+// its integer discriminant, so existing values must never be renumbered.
+// Open statuses keep the item's place in each task's sequence; closed ones drop
+// it (rank NULL) and reopening appends the item to the end again.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum TaskItemStatus {
     Pending = 0,
     Completed = 1,
     Canceled = 2,
+    InProgress = 3,
+    Blocked = 4,
+    Deferred = 5,
+}
+impl TaskItemStatus {
+    pub fn is_open(self) -> bool {
+        matches!(self, TaskItemStatus::Pending | TaskItemStatus::InProgress | TaskItemStatus::Blocked)
+    }
+
+    // Finished one way or the other; Deferred is neither open nor done
+    pub fn is_done(self) -> bool {
+        matches!(self, TaskItemStatus::Completed | TaskItemStatus::Canceled)
+    }
 }
-impl From<i64> for TaskItemStatus {
-    fn from(value: i64) -> Self {
+impl TryFrom<i64> for TaskItemStatus {
+    type Error = AppError;
+
+    fn try_from(value: i64) -> Result<Self> {
         match value {
-            0 => TaskItemStatus::Pending,
-            1 => TaskItemStatus::Completed,
-            2 => TaskItemStatus::Canceled,
-            _ => panic!("Invalid status value: {}", value),
+            0 => Ok(TaskItemStatus::Pending),
+            1 => Ok(TaskItemStatus::Completed),
+            2 => Ok(TaskItemStatus::Canceled),
+            3 => Ok(TaskItemStatus::InProgress),
+            4 => Ok(TaskItemStatus::Blocked),
+            5 => Ok(TaskItemStatus::Deferred),
+            _ => Err(AppError::validation("status", format!("unknown status value {}", value))),
         }
     }
 }
@@ -49,160 +112,764 @@ impl From<TaskItemStatus> for i64 {
     }
 }
 
+impl FromSql for TaskItemStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        let raw = value.as_i64()?;
+        TaskItemStatus::try_from(raw).map_err(|_| FromSqlError::OutOfRange(raw))
+    }
+}
+
+impl ToSql for TaskItemStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(i64::from(*self)))
+    }
+}
+
 
 #[derive(Debug, Serialize)]
 pub struct TaskItemWithSequence {
     pub id: i64,
     pub content: String,
     pub status: TaskItemStatus,
-    pub sequence: Option<i64>
+    // Position among its siblings, from 1
+    pub sequence: Option<i64>,
+    // The item this one is a subtask of within the task
+    pub parent_id: Option<i64>,
+    // 0 for top-level items
+    pub depth: i64,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub completed_at: Option<String>,
+    pub start_at: Option<String>,
+    pub due_at: Option<String>,
+    pub priority: Option<Priority>,
+    pub recurrence: Option<String>
 }
 
 
-pub fn create_task_item(content: &str) -> Result<i64> {
-    let conn = Connection::open(DB_PATH)?;
+pub fn create_task_item(conn: &Connection, content: &str, start_at: Option<&str>, due_at: Option<&str>) -> Result<i64> {
+    require_text("content", content)?;
+    let (start_at, due_at) = schedule::normalize_dates(conn, start_at, due_at)?;
 
-    conn.execute(
-        "INSERT INTO task_items (content) VALUES (?1)",
-        [content],
-    )?;
-    Ok(conn.last_insert_rowid())
+    conn.prepare_cached(
+        "INSERT INTO task_items (content, start_at, due_at, created_at, updated_at)
+         VALUES (?1, ?2, ?3, datetime('now'), datetime('now'))"
+    )?.execute((content, &start_at, &due_at))?;
+    let id = conn.last_insert_rowid();
+
+    history::record(conn, Entity::TaskItem, id, Action::Created, None, Some(json!({ "content": content, "start_at": start_at, "due_at": due_at })))?;
+    Ok(id)
+}
+
+pub fn update_task_item_priority(conn: &Connection, task_item_id: i64, priority: Option<Priority>) -> Result<()> {
+    let old = get_task_item(conn, task_item_id)?;
+
+    conn.prepare_cached("UPDATE task_items SET priority = ?1, updated_at = datetime('now') WHERE id = ?2")?
+        .execute((priority, task_item_id))?;
+
+    history::record(conn, Entity::TaskItem, task_item_id, Action::Edited, Some(json!({ "priority": old.priority })), Some(json!({ "priority": priority })))
+}
+
+pub fn update_task_item_schedule(conn: &Connection, task_item_id: i64, start_at: Option<&str>, due_at: Option<&str>) -> Result<()> {
+    let (start_at, due_at) = schedule::normalize_dates(conn, start_at, due_at)?;
+    let old = get_task_item(conn, task_item_id)?;
+
+    conn.prepare_cached("UPDATE task_items SET start_at = ?1, due_at = ?2, updated_at = datetime('now') WHERE id = ?3")?
+        .execute((&start_at, &due_at, task_item_id))?;
+
+    history::record(
+        conn, Entity::TaskItem, task_item_id, Action::Edited,
+        Some(json!({ "start_at": old.start_at, "due_at": old.due_at })),
+        Some(json!({ "start_at": start_at, "due_at": due_at })),
+    )
 }
 
-pub fn link_task_item(task_id: i64, item_id: i64) -> Result<i64> {
-    println!("Assigning new link_task_item to task item ID: {}", item_id);
-    let conn = Connection::open(DB_PATH)?;
+// A new, unlinked item with the same content, status, dates, priority and tags
+pub fn copy_task_item(conn: &Connection, id: i64) -> Result<i64> {
+    let item = get_task_item(conn, id)?;
+
+    conn.prepare_cached(
+        "INSERT INTO task_items (content, status, completed_at, start_at, due_at, priority, created_at, updated_at)
+         SELECT content, status, completed_at, start_at, due_at, priority, datetime('now'), datetime('now')
+         FROM task_items WHERE id = ?1"
+    )?.execute([id])?;
+    let copy_id = conn.last_insert_rowid();
 
-    // Get the max sequence for this task
-    let max_sequence: i64 = conn.query_row(
-        "SELECT COALESCE(MAX(sequence), 0) FROM task_item_links WHERE task_id = ?1",
-        [task_id],
-        |row| row.get(0)
+    history::record(
+        conn, Entity::TaskItem, copy_id, Action::Created, None,
+        Some(json!({ "content": item.content, "status": item.status, "copied_from": id })),
     )?;
-    println!("link_task_item for task item is of value: {}", max_sequence + 1);
+    tag::copy_taggings(conn, Entity::TaskItem, id, copy_id)?;
+    Ok(copy_id)
+}
 
-    match conn.execute(
-        "INSERT INTO task_item_links (task_id, item_id, sequence)
+pub fn get_task_item(conn: &Connection, id: i64) -> Result<TaskItem> {
+    let item = conn.prepare_cached(&format!("SELECT {} FROM task_items WHERE id = ?1 AND deleted_at IS NULL", TaskItem::COLUMNS))?
+        .query_row([id], TaskItem::from_row)
+        .or_not_found(Entity::TaskItem, id)?;
+    Ok(item)
+}
+
+// Where the item sits in one task: its parent item and its rank (None while closed)
+pub fn link_place(conn: &Connection, task_id: i64, item_id: i64) -> Result<(Option<i64>, Option<f64>)> {
+    let place = conn.prepare_cached("SELECT parent_id, rank FROM task_item_links WHERE task_id = ?1 AND item_id = ?2")?
+        .query_row((task_id, item_id), |row| Ok((row.get(0)?, row.get(1)?)))
+        .or_not_found(Entity::TaskItem, item_id)?;
+    Ok(place)
+}
+
+// `None` when the item isn't linked to the task, `Some(None)` when it's linked but closed
+pub fn link_sequence(conn: &Connection, task_id: i64, item_id: i64) -> Result<Option<Option<i64>>> {
+    let sequence = conn.prepare_cached(&format!("SELECT {} FROM task_item_links WHERE task_id = ?1 AND item_id = ?2", LINK_SEQUENCE))?
+        .query_row((task_id, item_id), |row| row.get(0))
+        .optional()?;
+    Ok(sequence)
+}
+
+// Open items are appended to the end of the task, closed ones are linked without a rank.
+// Linking always happens at the top level, relinking takes the item out of its parent.
+pub fn link_task_item(conn: &Connection, task_id: i64, item_id: i64) -> Result<i64> {
+    conn.prepare_cached(
+        "SELECT tasks.id FROM tasks
+         JOIN projects ON projects.id = tasks.project_id
+         WHERE tasks.id = ?1 AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL"
+    )?
+        .query_row([task_id], |row| row.get::<_, i64>(0))
+        .or_not_found(Entity::Task, task_id)?;
+    let item = get_task_item(conn, item_id)?;
+
+    let scope = Scope::Links { task_id, parent_id: None };
+    let rank = if item.status.is_open() { Some(rank::next(conn, scope)?) } else { None };
+
+    let mut upsert = conn.prepare_cached(
+        "INSERT INTO task_item_links (task_id, item_id, rank)
          VALUES (?1, ?2, ?3)
          ON CONFLICT(task_id, item_id)
-         DO UPDATE SET sequence = ?3",
-        (task_id, item_id, max_sequence + 1),
-    ) {
-        Ok(_) => {
-            println!("debugging, this is the last row? {:?}", conn.last_insert_rowid());
-            Ok(conn.last_insert_rowid())
-        }
-        Err(e) => {
-            eprintln!("Failed to insert task_item_link: {}", e);
-            Err(e)
+         DO UPDATE SET rank = ?3, parent_id = NULL"
+    )?;
+    upsert.execute((task_id, item_id, rank))?;
+    let row_id = conn.last_insert_rowid();
+    let sequence = rank::position(conn, scope, item_id)?;
+    history::record(conn, Entity::TaskItem, item_id, Action::Linked, None, Some(json!({ "task_id": task_id, "sequence": sequence })))?;
+    task::refresh_completion(conn, task_id)?;
+    Ok(row_id)
+}
+
+// Removes the item from one task only, the item itself and its other links stay.
+// Its subtasks in that task move up a level, into the place it leaves.
+pub fn unlink_task_item(conn: &Connection, task_id: i64, item_id: i64) -> Result<()> {
+    let (parent_id, _) = link_place(conn, task_id, item_id)?;
+    let sequence = link_sequence(conn, task_id, item_id)?.flatten();
+    let subtasks = get_subtasks(conn, task_id, item_id)?;
+    let changed = conn.prepare_cached("DELETE FROM task_item_links WHERE task_id = ?1 AND item_id = ?2")?
+        .execute((task_id, item_id))?;
+    expect_changed(changed, Entity::TaskItem, item_id)?;
+
+    let mut position = sequence;
+    for (subtask_id, subtask_sequence) in subtasks {
+        // Open subtasks take the item's place in their order, closed ones have none
+        let at = subtask_sequence.and(position);
+        nest_task_item(conn, task_id, subtask_id, parent_id, at)?;
+        if at.is_some() {
+            position = position.map(|position| position + 1);
         }
     }
+
+    history::record(conn, Entity::TaskItem, item_id, Action::Unlinked, Some(json!({ "task_id": task_id, "sequence": sequence })), None)?;
+    task::refresh_completion(conn, task_id)
+}
+
+// The item's direct subtasks in one task, in order, with their position (None while closed)
+pub fn get_subtasks(conn: &Connection, task_id: i64, item_id: i64) -> Result<Vec<(i64, Option<i64>)>> {
+    let subtasks = conn.prepare_cached(&format!(
+        "SELECT item_id, {} FROM task_item_links
+         WHERE task_id = ?1 AND parent_id = ?2
+         ORDER BY rank IS NULL, rank, item_id",
+        LINK_SEQUENCE
+    ))?
+        .query_map((task_id, item_id), |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(subtasks)
 }
 
-pub fn get_task_items(task_id: i64) -> Result<Vec<TaskItemWithSequence>> {
-    let conn = Connection::open(DB_PATH)?;
+pub fn get_item_links(conn: &Connection, item_id: i64) -> Result<Vec<TaskItemLink>> {
+    let links = conn.prepare_cached(&format!("SELECT task_id, item_id, {} FROM task_item_links WHERE item_id = ?1 ORDER BY task_id", LINK_SEQUENCE))?
+        .query_map([item_id], |row| {
+            Ok(TaskItemLink {
+                task_id: row.get(0)?,
+                item_id: row.get(1)?,
+                sequence: row.get(2)?
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<TaskItemLink>>>()?;
+    Ok(links)
+}
 
-    let mut stmt = conn.prepare(
-        "SELECT task_items.id, task_items.content, task_items.status, task_item_links.sequence
-         FROM task_items
-         JOIN task_item_links ON task_items.id = task_item_links.item_id
-         WHERE task_item_links.task_id = ?1
-         ORDER BY task_item_links.sequence"
-    )?;
+// A live task the item shows up in
+#[derive(Debug, Serialize)]
+pub struct LinkedTask {
+    pub task_id: i64,
+    pub title: String,
+    pub project_id: i64,
+    pub project_title: String,
+    // Position among the task's open items, NULL while the item is closed
+    pub sequence: Option<i64>
+}
+
+// Where a shared item lives, in the order the projects and tasks are shown
+pub fn get_linked_tasks(conn: &Connection, item_id: i64) -> Result<Vec<LinkedTask>> {
+    get_task_item(conn, item_id)?;
+
+    let tasks = conn.prepare_cached(&format!(
+        "SELECT tasks.id, tasks.title, projects.id, projects.title, {}
+         FROM task_item_links
+         JOIN tasks ON tasks.id = task_item_links.task_id
+         JOIN projects ON projects.id = tasks.project_id
+         WHERE task_item_links.item_id = ?1 AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL
+         ORDER BY projects.rank, projects.id, tasks.rank, tasks.id",
+        LINK_SEQUENCE
+    ))?
+        .query_map([item_id], |row| {
+            Ok(LinkedTask {
+                task_id: row.get(0)?,
+                title: row.get(1)?,
+                project_id: row.get(2)?,
+                project_title: row.get(3)?,
+                sequence: row.get(4)?
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<LinkedTask>>>()?;
+    Ok(tasks)
+}
+
+// Live items that no live task links to, so no task view shows them
+pub fn list_orphan_items(conn: &Connection) -> Result<Vec<TaskItem>> {
+    let items = conn.prepare_cached(&format!(
+        "SELECT {} FROM task_items
+         WHERE task_items.deleted_at IS NULL AND NOT EXISTS (
+             SELECT 1 FROM task_item_links
+             JOIN tasks ON tasks.id = task_item_links.task_id
+             JOIN projects ON projects.id = tasks.project_id
+             WHERE task_item_links.item_id = task_items.id
+             AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL
+         )
+         ORDER BY task_items.id",
+        TaskItem::COLUMNS
+    ))?
+        .query_map([], TaskItem::from_row)?
+        .collect::<rusqlite::Result<Vec<TaskItem>>>()?;
+    Ok(items)
+}
+
+// What deleting an item from one of its tasks does
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Removal {
+    // Other live tasks still show the item, only this task's link goes
+    Unlinked,
+    // This was its last live task, the item goes to the trash
+    Trashed,
+}
+
+pub fn removal_from_task(conn: &Connection, task_id: i64, item_id: i64) -> Result<Removal> {
+    get_task_item(conn, item_id)?;
+    conn.prepare_cached("SELECT 1 FROM task_item_links WHERE task_id = ?1 AND item_id = ?2")?
+        .query_row((task_id, item_id), |_| Ok(()))
+        .or_not_found(Entity::TaskItem, item_id)?;
+
+    let other_tasks: i64 = conn.prepare_cached(
+        "SELECT COUNT(*) FROM task_item_links
+         JOIN tasks ON tasks.id = task_item_links.task_id
+         JOIN projects ON projects.id = tasks.project_id
+         WHERE task_item_links.item_id = ?2 AND task_item_links.task_id != ?1
+         AND tasks.deleted_at IS NULL AND projects.deleted_at IS NULL"
+    )?.query_row((task_id, item_id), |row| row.get(0))?;
+
+    Ok(if other_tasks > 0 { Removal::Unlinked } else { Removal::Trashed })
+}
+
+// Every field is optional and they combine with AND; an empty list means no filter
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TaskItemFilter {
+    pub statuses: Vec<TaskItemStatus>,
+    // Case-insensitive substring of the content
+    pub text: Option<String>,
+    // Items carrying all of these tags
+    pub tags: Vec<i64>,
+    // Due date range, inclusive; bare dates cover the whole day
+    pub due_from: Option<String>,
+    pub due_to: Option<String>
+}
+
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Serialize)]
+pub struct TaskItemPage {
+    pub items: Vec<TaskItemWithSequence>,
+    // Matching items across all pages
+    pub total: usize
+}
+
+// Items come depth first, each followed by its subtasks. Among siblings open items come
+// first in the requested order, then the rest (no rank) with the most recently completed
+// or canceled first. Filters keep matching rows in their place in the tree, and subtasks
+// of an item that isn't shown (trashed, or unlinked from this task) show at the top level.
+pub fn get_task_items(conn: &Connection, task_id: i64, filter: &TaskItemFilter, order: SortOrder, limit: Option<u32>, offset: Option<u32>) -> Result<TaskItemPage> {
+    if limit.is_some_and(|limit| limit == 0 || limit > MAX_PAGE_SIZE) {
+        return Err(AppError::validation("limit", format!("must be between 1 and {}", MAX_PAGE_SIZE)));
+    }
+    let statuses = (!filter.statuses.is_empty())
+        .then(|| json!(filter.statuses.iter().map(|status| i64::from(*status)).collect::<Vec<i64>>()).to_string());
+    let text = filter.text.as_deref().map(str::trim).filter(|text| !text.is_empty());
+    let due_from = schedule::normalize(conn, "due_from", filter.due_from.as_deref(), false)?;
+    let due_to = schedule::normalize(conn, "due_to", filter.due_to.as_deref(), true)?;
+
+    // The items the filters keep, as a prefix shared by the page and its count
+    let matching = format!(
+        "WITH RECURSIVE siblings AS (
+             SELECT task_item_links.item_id, parent.id AS parent_id, ROW_NUMBER() OVER (
+                 PARTITION BY parent.id
+                 ORDER BY task_item_links.rank IS NULL, {order}, task_items.completed_at DESC, task_items.id
+             ) AS ordinal
+             FROM task_item_links
+             JOIN task_items ON task_items.id = task_item_links.item_id
+             LEFT JOIN task_items AS parent ON parent.id = task_item_links.parent_id AND parent.deleted_at IS NULL
+                 AND parent.id IN (SELECT item_id FROM task_item_links AS parent_links WHERE parent_links.task_id = ?1)
+             WHERE task_item_links.task_id = ?1 AND task_items.deleted_at IS NULL
+         ),
+         tree (item_id, depth, path) AS (
+             SELECT item_id, 0, printf('%08d', ordinal) FROM siblings WHERE parent_id IS NULL
+             UNION ALL
+             SELECT siblings.item_id, tree.depth + 1, tree.path || printf('%08d', siblings.ordinal)
+             FROM siblings
+             JOIN tree ON siblings.parent_id = tree.item_id
+         ),
+         matching AS (
+             SELECT tree.* FROM tree
+             JOIN task_items ON task_items.id = tree.item_id
+             WHERE {tags}
+             AND (?3 IS NULL OR task_items.status IN (SELECT value FROM json_each(?3)))
+             AND (?4 IS NULL OR instr(lower(task_items.content), lower(?4)) > 0)
+             AND (?5 IS NULL OR task_items.due_at >= ?5)
+             AND (?6 IS NULL OR task_items.due_at <= ?6)
+         )",
+        order = order.order_by("task_items", "task_item_links"),
+        tags = tag::filter_condition(Entity::TaskItem, "task_items.id", "?2")
+    );
+    let tags = tag::filter_param(&filter.tags);
+    let filters = (task_id, &tags, &statuses, &text, &due_from, &due_to);
+
+    // Counted on its own, since a page past the end has no rows to carry a total
+    let total: usize = conn.prepare_cached(&format!("{} SELECT COUNT(*) FROM matching", matching))?
+        .query_row(filters, |row| row.get(0))?;
 
+    let mut stmt = conn.prepare_cached(&format!(
+        "{matching}
+         SELECT task_items.id, task_items.content, task_items.status, {sequence},
+                task_items.created_at, task_items.updated_at, task_items.completed_at,
+                task_items.start_at, task_items.due_at, task_items.priority, task_items.recurrence,
+                task_item_links.parent_id, matching.depth
+         FROM matching
+         JOIN task_items ON task_items.id = matching.item_id
+         JOIN task_item_links ON task_item_links.task_id = ?1 AND task_item_links.item_id = matching.item_id
+         ORDER BY matching.path
+         LIMIT ?7 OFFSET ?8",
+        matching = matching,
+        sequence = LINK_SEQUENCE
+    ))?;
+
+    let params = (
+        task_id, &tags, &statuses, &text, &due_from, &due_to,
+        limit.map_or(-1, i64::from), offset.unwrap_or(0),
+    );
     let items = stmt
-        .query_map([task_id], |row| {
+        .query_map(params, |row| {
             Ok(TaskItemWithSequence {
                 id: row.get(0)?,
                 content: row.get(1)?,
-                status: TaskItemStatus::from(row.get::<_, i64>(2)?),
-                sequence: row.get(3)?
+                status: row.get(2)?,
+                sequence: row.get(3)?,
+                created_at: row.get(4)?,
+                updated_at: row.get(5)?,
+                completed_at: row.get(6)?,
+                start_at: row.get(7)?,
+                due_at: row.get(8)?,
+                priority: row.get(9)?,
+                recurrence: row.get(10)?,
+                parent_id: row.get(11)?,
+                depth: row.get(12)?
             })
         })?
-        .collect::<Result<Vec<TaskItemWithSequence>, _>>()?;
+        .collect::<rusqlite::Result<Vec<TaskItemWithSequence>>>()?;
 
-    Ok(items)
+    Ok(TaskItemPage { items, total })
 }
 
-pub fn update_task_item_content(task_item_id: i64, content: &str, status: TaskItemStatus) -> Result<()> {
-    println!("Trying to update task item of id {} with content {} and status {:?}", task_item_id, content, status);
-    let mut conn = Connection::open(DB_PATH)?;
-    let tx = conn.transaction()?;
-    let status_int = i64::from(status);
+pub fn update_task_item_content(conn: &Connection, task_item_id: i64, content: &str, status: TaskItemStatus) -> Result<()> {
+    require_text("content", content)?;
+    let old = get_task_item(conn, task_item_id)?;
+    require_consistent_subtasks(conn, task_item_id, status)?;
+
+    let changed = conn.prepare_cached(
+        "UPDATE task_items
+         SET content = ?1, status = ?2, updated_at = datetime('now'),
+             completed_at = CASE WHEN ?4 THEN COALESCE(completed_at, datetime('now')) END
+         WHERE id = ?3 AND deleted_at IS NULL"
+    )?.execute((content, status, task_item_id, status.is_done()))?;
+    expect_changed(changed, Entity::TaskItem, task_item_id)?;
+
+    if status.is_open() {
+        append_links(conn, task_item_id)?;
+    } else {
+        release_links(conn, task_item_id)?;
+    }
 
-    tx.execute(
-        "UPDATE task_items SET content = ?1, status = ?2 WHERE id = ?3",
-        (content, status_int, task_item_id)
+    history::record(
+        conn, Entity::TaskItem, task_item_id, Action::Edited,
+        Some(json!({ "content": old.content, "status": old.status })),
+        Some(json!({ "content": content, "status": status })),
     )?;
+    if status == TaskItemStatus::Completed && old.status != TaskItemStatus::Completed {
+        recur(conn, task_item_id)?;
+    }
+    refresh_tasks(conn, task_item_id)
+}
+
+// A blank rule stops the item recurring; occurrences already made stay
+pub fn update_task_item_recurrence(conn: &Connection, task_item_id: i64, recurrence: Option<&str>) -> Result<()> {
+    let recurrence = recurrence::normalize(recurrence)?;
+    let old = get_task_item(conn, task_item_id)?;
 
-    // If new status is not Pending, remove it from task_item_links
-    if status_int != 0 {
-        tx.execute(
-            "UPDATE task_item_links SET sequence = NULL WHERE item_id = ?1",
-            [task_item_id],
-        )?;
+    conn.prepare_cached("UPDATE task_items SET recurrence = ?1, updated_at = datetime('now') WHERE id = ?2")?
+        .execute((&recurrence, task_item_id))?;
+
+    history::record(
+        conn, Entity::TaskItem, task_item_id, Action::Edited,
+        Some(json!({ "recurrence": old.recurrence })), Some(json!({ "recurrence": recurrence })),
+    )
+}
+
+// Completing a recurring item adds its next occurrence, pending, to the end of every
+// live task it's linked to (under the same parent). There is only one per item:
+// completing it again after a reopen or an undo leaves a live occurrence alone and
+// takes a trashed one out of the trash.
+fn recur(conn: &Connection, item_id: i64) -> Result<()> {
+    let item = get_task_item(conn, item_id)?;
+    let Some(rule) = item.recurrence.as_deref() else {
+        return Ok(());
+    };
+
+    let recurred: Option<(i64, bool)> = conn.prepare_cached(
+        "SELECT recurred.id, recurred.deleted_at IS NOT NULL
+         FROM task_items JOIN task_items AS recurred ON recurred.id = task_items.recurred_id
+         WHERE task_items.id = ?1"
+    )?
+        .query_row([item_id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()?;
+    match recurred {
+        Some((recurred_id, true)) => return restore_task_item(conn, recurred_id),
+        Some((_, false)) => return Ok(()),
+        None => {}
     }
 
-    tx.commit()?;
+    let today: String = conn.prepare_cached("SELECT date('now', 'localtime')")?
+        .query_row([], |row| row.get(0))?;
+    let next = recurrence::next_occurrence(&Rule::parse(rule)?, item.start_at.as_deref(), item.due_at.as_deref(), &today)?;
+    let Some(Occurrence { start_at, due_at, rule }) = next else {
+        return Ok(());
+    };
+
+    conn.prepare_cached(
+        "INSERT INTO task_items (content, start_at, due_at, priority, recurrence, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'), datetime('now'))"
+    )?.execute((&item.content, &start_at, &due_at, item.priority, rule.to_string()))?;
+    let id = conn.last_insert_rowid();
+    conn.prepare_cached("UPDATE task_items SET recurred_id = ?1 WHERE id = ?2")?
+        .execute((id, item_id))?;
+
+    history::record(
+        conn, Entity::TaskItem, id, Action::Created, None,
+        Some(json!({ "content": item.content, "start_at": start_at, "due_at": due_at, "recurred_from": item_id })),
+    )?;
+    tag::copy_taggings(conn, Entity::TaskItem, item_id, id)?;
+
+    for linked in get_linked_tasks(conn, item_id)? {
+        link_task_item(conn, linked.task_id, id)?;
+        let (parent_id, _) = link_place(conn, linked.task_id, item_id)?;
+        if parent_id.is_some() {
+            nest_task_item(conn, linked.task_id, id, parent_id, None)?;
+        }
+    }
     Ok(())
 }
 
-pub fn update_task_item_sequence(task_id: i64, item_id: Option<i64>, new_sequence: Option<i64>) -> Result<(), Error> {
-    println!("Updating task item sequence for task: {}, item: {:?}, new sequence: {:?}", task_id, item_id, new_sequence);
-    let mut conn = Connection::open(DB_PATH)?;
-    let tx = conn.transaction()?;
-
-    // Get all items for this task, ordered by sequence (NULLs last)
-    let mut items = tx
-        .prepare(
-            "SELECT item_id, sequence FROM task_item_links
-             WHERE task_id = ?1 AND sequence IS NOT NULL
-             ORDER BY sequence",
-        )?
-        .query_map([task_id], |row| {
-            Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
-        })?
-        .collect::<Result<Vec<(i64, i64)>>>()?;
-
-    // If item_id and new_sequence are provided, update the sequence for that item
-    if let (Some(item_id), Some(new_sequence)) = (item_id, new_sequence) {
-        // Find the item in the list
-        if let Some(index) = items.iter().position(|(id, _)| *id == item_id) {
-            let (_, old_sequence) = items.remove(index);
-            // Ensure new_sequence is within bounds
-            let new_index = (new_sequence - 1) as usize;
-            if new_index > items.len() {
-                return Err(Error::InvalidQuery);
-            }
-            items.insert(new_index, (item_id, new_sequence));
+// A completed or canceled item can't have unfinished subtasks in any of its tasks:
+// subtasks are finished before their parent, and a parent is reopened before them
+fn require_consistent_subtasks(conn: &Connection, item_id: i64, status: TaskItemStatus) -> Result<()> {
+    if status.is_done() {
+        let unfinished: i64 = conn.prepare_cached(
+            "SELECT COUNT(*) FROM task_item_links
+             JOIN task_items ON task_items.id = task_item_links.item_id
+             WHERE task_item_links.parent_id = ?1 AND task_items.deleted_at IS NULL
+             AND task_items.status NOT IN (?2, ?3)"
+        )?.query_row((item_id, TaskItemStatus::Completed, TaskItemStatus::Canceled), |row| row.get(0))?;
+        if unfinished > 0 {
+            return Err(AppError::conflict(format!("task item {} has {} unfinished subtasks", item_id, unfinished)));
         }
+    } else {
+        let finished_parents: i64 = conn.prepare_cached(
+            "SELECT COUNT(*) FROM task_item_links
+             JOIN task_items AS parent ON parent.id = task_item_links.parent_id
+             WHERE task_item_links.item_id = ?1 AND parent.deleted_at IS NULL
+             AND parent.status IN (?2, ?3)"
+        )?.query_row((item_id, TaskItemStatus::Completed, TaskItemStatus::Canceled), |row| row.get(0))?;
+        if finished_parents > 0 {
+            return Err(AppError::conflict(format!("task item {} is a subtask of a finished item, reopen that first", item_id)));
+        }
+    }
+    Ok(())
+}
+
+// Recomputes completion for every task the item is linked to
+fn refresh_tasks(conn: &Connection, item_id: i64) -> Result<()> {
+    for link in get_item_links(conn, item_id)? {
+        task::refresh_completion(conn, link.task_id)?;
     }
+    Ok(())
+}
+
+// Puts the item back at the end of its siblings in every task it's linked to;
+// links that already hold a rank keep their place
+fn append_links(conn: &Connection, item_id: i64) -> Result<()> {
+    conn.prepare_cached(
+        "UPDATE task_item_links
+         SET rank = (
+             SELECT COALESCE(MAX(other.rank), 0) + 1
+             FROM task_item_links AS other
+             WHERE other.task_id = task_item_links.task_id AND other.parent_id IS task_item_links.parent_id
+         )
+         WHERE item_id = ?1 AND rank IS NULL"
+    )?.execute([item_id])?;
+    Ok(())
+}
+
+// Takes the item out of every task's ordering
+fn release_links(conn: &Connection, item_id: i64) -> Result<()> {
+    conn.prepare_cached("UPDATE task_item_links SET rank = NULL WHERE item_id = ?1")?
+        .execute([item_id])?;
+    Ok(())
+}
+
+// Moves an open item among its siblings in one of its tasks
+pub fn move_task_item(conn: &Connection, task_id: i64, item_id: i64, to: Placement) -> Result<()> {
+    let (parent_id, linked_rank) = link_place(conn, task_id, item_id)?;
+    if linked_rank.is_none() {
+        return Err(AppError::validation("task_item_id", "closed items have no place in the task's order"));
+    }
+
+    let scope = Scope::Links { task_id, parent_id };
+    let old_sequence = rank::position(conn, scope, item_id)?;
+    rank::move_row(conn, scope, item_id, to)?;
+
+    conn.prepare_cached("UPDATE task_items SET updated_at = datetime('now') WHERE id = ?1")?
+        .execute([item_id])?;
+    history::record(
+        conn, Entity::TaskItem, item_id, Action::Moved,
+        Some(json!({ "task_id": task_id, "sequence": old_sequence })),
+        Some(json!({ "task_id": task_id, "sequence": rank::position(conn, scope, item_id)? })),
+    )
+}
+
+// Makes the item a subtask of `parent_id` within one task (or a top-level item for
+// None), taking its own subtasks along. Open items are appended to their new
+// siblings unless a `position` is given; closed ones have no position.
+pub fn nest_task_item(conn: &Connection, task_id: i64, item_id: i64, parent_id: Option<i64>, position: Option<i64>) -> Result<()> {
+    let item = get_task_item(conn, item_id)?;
+    let (old_parent_id, linked_rank) = link_place(conn, task_id, item_id)?;
 
-    // Step 1: Temporarily set sequences to negative values
-    for (new_index, (id, _)) in items.iter().enumerate() {
-        let temp_seq = -(new_index as i64 + 1);
-        tx.execute(
-            "UPDATE task_item_links SET sequence = ?1 WHERE task_id = ?2 AND item_id = ?3",
-            (temp_seq, task_id, id),
-        )?;
+    if let Some(parent_id) = parent_id {
+        if parent_id == item_id || is_subtask(conn, task_id, parent_id, item_id)? {
+            return Err(AppError::validation("parent_id", "an item can't be nested under itself or its own subtasks"));
+        }
+        link_place(conn, task_id, parent_id)?;
+        if get_task_item(conn, parent_id)?.status.is_done() && !item.status.is_done() {
+            return Err(AppError::conflict(format!("task item {} is finished, an unfinished subtask can't go under it", parent_id)));
+        }
     }
 
-    // Step 2: Set sequences to their final positive values
-    for (new_index, (id, _)) in items.iter().enumerate() {
-        let final_seq = (new_index + 1) as i64;
-        tx.execute(
-            "UPDATE task_item_links SET sequence = ?1 WHERE task_id = ?2 AND item_id = ?3",
-            (final_seq, task_id, id),
-        )?;
+    let old_scope = Scope::Links { task_id, parent_id: old_parent_id };
+    let scope = Scope::Links { task_id, parent_id };
+    let old_sequence = rank::position(conn, old_scope, item_id)?;
+    let rank = match (linked_rank, position) {
+        (None, Some(_)) => return Err(AppError::validation("sequence", "closed items have no place in the task's order")),
+        (None, None) => None,
+        (Some(_), Some(position)) => {
+            rank::require_position(conn, scope, "sequence", position, old_parent_id != parent_id)?;
+            Some(rank::next(conn, scope)?)
+        }
+        (Some(_), None) => Some(rank::next(conn, scope)?),
+    };
+
+    conn.prepare_cached("UPDATE task_item_links SET parent_id = ?1, rank = ?2 WHERE task_id = ?3 AND item_id = ?4")?
+        .execute((parent_id, rank, task_id, item_id))?;
+    if let Some(position) = position {
+        rank::move_row(conn, scope, item_id, Placement::Position(position))?;
     }
 
-    tx.commit()?;
+    conn.prepare_cached("UPDATE task_items SET updated_at = datetime('now') WHERE id = ?1")?
+        .execute([item_id])?;
+    history::record(
+        conn, Entity::TaskItem, item_id, Action::Moved,
+        Some(json!({ "task_id": task_id, "parent_id": old_parent_id, "sequence": old_sequence })),
+        Some(json!({ "task_id": task_id, "parent_id": parent_id, "sequence": rank::position(conn, scope, item_id)? })),
+    )
+}
+
+// Whether `item_id` is nested somewhere below `ancestor_id` in the task
+fn is_subtask(conn: &Connection, task_id: i64, item_id: i64, ancestor_id: i64) -> Result<bool> {
+    let nested = conn.prepare_cached(
+        "WITH RECURSIVE ancestors (id) AS (
+             SELECT parent_id FROM task_item_links WHERE task_id = ?1 AND item_id = ?2
+             UNION
+             SELECT task_item_links.parent_id FROM task_item_links
+             JOIN ancestors ON task_item_links.task_id = ?1 AND task_item_links.item_id = ancestors.id
+         )
+         SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = ?3)"
+    )?.query_row((task_id, item_id, ancestor_id), |row| row.get(0))?;
+    Ok(nested)
+}
+
+// Makes the item the last subtask of the open sibling just above it
+pub fn indent_task_item(conn: &Connection, task_id: i64, item_id: i64) -> Result<()> {
+    let (parent_id, linked_rank) = link_place(conn, task_id, item_id)?;
+    let Some(linked_rank) = linked_rank else {
+        return Err(AppError::validation("task_item_id", "closed items have no place in the task's order"));
+    };
+
+    let above: Option<i64> = conn.prepare_cached(
+        "SELECT item_id FROM task_item_links
+         WHERE task_id = ?1 AND parent_id IS ?2 AND rank IS NOT NULL AND (rank, item_id) < (?3, ?4)
+         ORDER BY rank DESC, item_id DESC LIMIT 1"
+    )?
+        .query_row((task_id, parent_id, linked_rank, item_id), |row| row.get(0))
+        .optional()?;
+    let above = above.ok_or(AppError::validation("task_item_id", "the first item has nothing above it to go under"))?;
+
+    nest_task_item(conn, task_id, item_id, Some(above), None)
+}
+
+// Moves a subtask up a level, just after the item it was under
+pub fn outdent_task_item(conn: &Connection, task_id: i64, item_id: i64) -> Result<()> {
+    let (parent_id, linked_rank) = link_place(conn, task_id, item_id)?;
+    let parent_id = parent_id.ok_or(AppError::validation("task_item_id", "top-level items can't be outdented"))?;
+    let (grandparent_id, parent_rank) = link_place(conn, task_id, parent_id)?;
+
+    let position = match (linked_rank, parent_rank) {
+        (Some(_), Some(_)) => rank::position(conn, Scope::Links { task_id, parent_id: grandparent_id }, parent_id)?.map(|position| position + 1),
+        // A closed item or one under a closed parent has nothing to line up with
+        _ => None,
+    };
+    nest_task_item(conn, task_id, item_id, grandparent_id, position)
+}
+
+// Moves the item to the trash, hiding it from every task it's linked to
+pub fn delete_task_item(conn: &Connection, id: i64) -> Result<()> {
+    let changed = conn.prepare_cached("UPDATE task_items SET deleted_at = datetime('now') WHERE id = ?1 AND deleted_at IS NULL")?
+        .execute([id])?;
+    expect_changed(changed, Entity::TaskItem, id)?;
+    release_links(conn, id)?;
+    history::record(conn, Entity::TaskItem, id, Action::Trashed, None, None)?;
+    refresh_tasks(conn, id)?;
+
     Ok(())
 }
 
-pub fn delete_task_item(id: i64) -> Result<()> {
-    let conn = Connection::open(DB_PATH)?;
+pub fn restore_task_item(conn: &Connection, id: i64) -> Result<()> {
+    let status: TaskItemStatus = conn.prepare_cached("SELECT status FROM task_items WHERE id = ?1 AND deleted_at IS NOT NULL")?
+        .query_row([id], |row| row.get(0))
+        .or_not_found(Entity::TaskItem, id)?;
+
+    conn.prepare_cached("UPDATE task_items SET deleted_at = NULL WHERE id = ?1")?
+        .execute([id])?;
+    if status.is_open() {
+        append_links(conn, id)?;
+    }
+    history::record(conn, Entity::TaskItem, id, Action::Restored, None, None)?;
+    refresh_tasks(conn, id)?;
 
-    conn.execute("DELETE FROM task_items WHERE id = ?1", [id])?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, project, task};
+
+    fn task_with_items(conn: &Connection, count: usize) -> i64 {
+        let project_id = project::create_project(conn, "Project", None).unwrap();
+        let task_id = task::create_task(conn, project_id, "Task", None, None, None).unwrap();
+        for index in 0..count {
+            let item_id = create_task_item(conn, &format!("Item {}", index), None, None).unwrap();
+            link_task_item(conn, task_id, item_id).unwrap();
+        }
+        task_id
+    }
+
+    fn contents(conn: &Connection, task_id: i64) -> Vec<(String, Option<i64>)> {
+        page(conn, task_id, &TaskItemFilter::default(), 50, 0).items.into_iter()
+            .map(|item| (item.content, item.parent_id))
+            .collect()
+    }
+
+    fn page(conn: &Connection, task_id: i64, filter: &TaskItemFilter, limit: u32, offset: u32) -> TaskItemPage {
+        get_task_items(conn, task_id, filter, SortOrder::default(), Some(limit), Some(offset)).unwrap()
+    }
+
+    #[test]
+    fn pages_count_every_match() {
+        let conn = db::test_connection();
+        let task_id = task_with_items(&conn, 5);
+
+        let first = page(&conn, task_id, &TaskItemFilter::default(), 2, 0);
+        assert_eq!(first.items.iter().map(|item| item.content.as_str()).collect::<Vec<_>>(), ["Item 0", "Item 1"]);
+        assert_eq!(first.total, 5);
+
+        let last = page(&conn, task_id, &TaskItemFilter::default(), 2, 4);
+        assert_eq!(last.items.len(), 1);
+        assert_eq!(last.total, 5);
+    }
+
+    #[test]
+    fn page_past_the_end_keeps_the_total() {
+        let conn = db::test_connection();
+        let task_id = task_with_items(&conn, 3);
+
+        let past = page(&conn, task_id, &TaskItemFilter::default(), 10, 3);
+        assert!(past.items.is_empty());
+        assert_eq!(past.total, 3);
+
+        let filter = TaskItemFilter { text: Some("item 1".to_string()), ..Default::default() };
+        let past = page(&conn, task_id, &filter, 10, 50);
+        assert!(past.items.is_empty());
+        assert_eq!(past.total, 1);
+    }
+
+    #[test]
+    fn unlink_moves_subtasks_into_its_place() {
+        let conn = db::test_connection();
+        let task_id = task_with_items(&conn, 4);
+        let ids: Vec<i64> = page(&conn, task_id, &TaskItemFilter::default(), 50, 0).items.iter().map(|item| item.id).collect();
+        // Item 1 and Item 2 under Item 0, Item 3 under Item 1
+        nest_task_item(&conn, task_id, ids[1], Some(ids[0]), None).unwrap();
+        nest_task_item(&conn, task_id, ids[2], Some(ids[0]), None).unwrap();
+        nest_task_item(&conn, task_id, ids[3], Some(ids[1]), None).unwrap();
+
+        unlink_task_item(&conn, task_id, ids[1]).unwrap();
+        assert_eq!(contents(&conn, task_id), [
+            ("Item 0".to_string(), None),
+            ("Item 3".to_string(), Some(ids[0])),
+            ("Item 2".to_string(), Some(ids[0])),
+        ]);
+    }
+}
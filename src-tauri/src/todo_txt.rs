@@ -0,0 +1,464 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+
+use crate::entity::Entity;
+use crate::error::{AppError, Result};
+use crate::priority::{Priority, SortOrder};
+use crate::project::{self, Project};
+use crate::recurrence;
+use crate::schedule;
+use crate::tag::{self, Tag};
+use crate::task;
+use crate::task_item::{self, LinkedTask, TaskItemFilter, TaskItemStatus, TaskItemWithSequence};
+
+// Task items as todo.txt lines (http://todotxt.org), one per item:
+//
+//     x 2026-10-18 2026-10-01 Book flights +Travel task:Summer @phone pri:B due:2026-10-20 id:12
+//     (A) 2026-10-02 Renew passport +Travel task:Documents t:2026-10-05 due:2026-10-31T12:00 id:14
+//     2026-10-02 Find old passport +Travel task:Documents id:15 parent:14
+//
+// A leading `x` and completion date mark Completed items, the other statuses go in a
+// `status:` tag. Priorities P0..P3 are (A)..(D), written as `pri:` on completed lines
+// where todo.txt has no room for them. `+project` and the `task:` after it say which
+// task the item is in, repeated for items shared between tasks; `@context` is a tag.
+// `t:` and `due:` hold the start and due date (with a time when it isn't the default
+// for a bare date) and `rec:` the recurrence rule. `id:` is the item's id, and a subtask
+// names its item with `parent:`, coming after it in the file; on import ids only tie
+// the lines together, and a subtask is nested under its parent in the tasks they share.
+// Names are written with spaces as underscores and matched that way on import, so
+// existing projects, tasks and tags are found again; lines without a project or task go
+// to one called "Inbox". Line breaks in the content are written as spaces, and words in
+// the content that look like +project, @context or one of these tags are read back as such.
+
+pub const INBOX: &str = "Inbox";
+
+// The project's items, or every live task's, each once in the order they're shown
+pub fn export_todo_txt(conn: &Connection, project_id: Option<i64>) -> Result<String> {
+    let projects = match project_id {
+        Some(project_id) => vec![project::get_project(conn, project_id)?],
+        None => project::get_all_projects(conn, &[])?,
+    };
+
+    let mut seen = HashSet::new();
+    let mut out = String::new();
+    for project in &projects {
+        for task in task::get_tasks(conn, project.id, SortOrder::Sequence, &[])? {
+            let items = task_item::get_task_items(conn, task.id, &TaskItemFilter::default(), SortOrder::Sequence, None, None)?.items;
+            for item in items.iter().filter(|item| seen.insert(item.id)) {
+                let links = task_item::get_linked_tasks(conn, item.id)?;
+                let tags = tag::get_entity_tags(conn, Entity::TaskItem, item.id)?;
+                out.push_str(&line(item, &links, &tags));
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn line(item: &TaskItemWithSequence, links: &[LinkedTask], tags: &[Tag]) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let created = item.created_at.as_deref().and_then(|created| created.get(..10)).map(str::to_string);
+    if item.status == TaskItemStatus::Completed {
+        words.push("x".to_string());
+        // todo.txt only has room for a creation date after a completion date
+        if let Some(completed) = item.completed_at.as_deref().and_then(|completed| completed.get(..10)) {
+            words.push(completed.to_string());
+            words.extend(created);
+        }
+    } else {
+        words.extend(item.priority.map(|priority| format!("({})", letter(priority))));
+        words.extend(created);
+    }
+    words.extend(item.content.split_whitespace().map(str::to_string));
+
+    for link in links {
+        words.push(format!("+{}", encode(&link.project_title)));
+        words.push(format!("task:{}", encode(&link.title)));
+    }
+    words.extend(tags.iter().map(|tag| format!("@{}", encode(&tag.name))));
+    if let (TaskItemStatus::Completed, Some(priority)) = (item.status, item.priority) {
+        words.push(format!("pri:{}", letter(priority)));
+    }
+    words.extend(item.start_at.as_deref().map(|start_at| format!("t:{}", date(start_at, "00:00:00"))));
+    words.extend(item.due_at.as_deref().map(|due_at| format!("due:{}", date(due_at, "23:59:59"))));
+    words.extend(item.recurrence.as_ref().map(|rule| format!("rec:{}", rule)));
+    words.push(format!("id:{}", item.id));
+    // Subtasks of an item that isn't shown are exported at the top level, like they're listed
+    words.extend(item.parent_id.filter(|_| item.depth > 0).map(|parent_id| format!("parent:{}", parent_id)));
+    if !matches!(item.status, TaskItemStatus::Pending | TaskItemStatus::Completed) {
+        words.push(format!("status:{}", format!("{:?}", item.status).to_lowercase()));
+    }
+    words.join(" ")
+}
+
+fn letter(priority: Priority) -> char {
+    (b'A' + i64::from(priority) as u8) as char
+}
+
+// Bare dates stand for the whole day, see schedule.rs
+fn date(value: &str, default_time: &str) -> String {
+    match value.strip_suffix(default_time) {
+        Some(date) => date.trim_end().to_string(),
+        None => value.replacen(' ', "T", 1),
+    }
+}
+
+fn encode(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<&str>>().join("_")
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TodoImport {
+    // Projects, tasks and tags created for names that didn't match one
+    pub project_ids: Vec<i64>,
+    pub task_ids: Vec<i64>,
+    pub task_item_ids: Vec<i64>,
+    pub tag_ids: Vec<i64>
+}
+
+#[derive(Default)]
+struct Line {
+    // In the file, from 1
+    number: usize,
+    content: Vec<String>,
+    status: Option<TaskItemStatus>,
+    priority: Option<Priority>,
+    created_at: Option<String>,
+    completed_at: Option<String>,
+    // Project and task names as written
+    links: Vec<(Option<String>, Option<String>)>,
+    tags: Vec<String>,
+    start_at: Option<String>,
+    due_at: Option<String>,
+    recurrence: Option<String>,
+    // `id:` and `parent:` as written
+    key: Option<String>,
+    parent: Option<String>,
+}
+
+impl Line {
+    // Project and task names of the tasks the item goes in
+    fn tasks(&self) -> Vec<(&str, &str)> {
+        if self.links.is_empty() {
+            return vec![(INBOX, INBOX)];
+        }
+        self.links.iter()
+            .map(|(project, task)| (project.as_deref().unwrap_or(INBOX), task.as_deref().unwrap_or(INBOX)))
+            .collect()
+    }
+
+    fn is_done(&self) -> bool {
+        self.status.is_some_and(TaskItemStatus::is_done)
+    }
+}
+
+fn invalid_line(number: usize, reason: impl std::fmt::Display) -> AppError {
+    AppError::validation("text", format!("line {}: {}", number, reason))
+}
+
+// Adds an item per line; the whole file is checked before anything is written
+pub fn import_todo_txt(conn: &Connection, text: &str) -> Result<TodoImport> {
+    let lines = text.lines().enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| parse(conn, line).map(|line| Line { number: index + 1, ..line }).map_err(|e| invalid_line(index + 1, e)))
+        .collect::<Result<Vec<Line>>>()?;
+
+    let mut keys: HashMap<&str, &Line> = HashMap::new();
+    for line in &lines {
+        if let Some(parent_key) = &line.parent {
+            let Some(parent) = keys.get(parent_key.as_str()) else {
+                return Err(invalid_line(line.number, format!("parent:{} isn't the id: of an earlier line", parent_key)));
+            };
+            if !line.tasks().iter().any(|task| parent.tasks().contains(task)) {
+                return Err(invalid_line(line.number, format!("parent:{} isn't in any of the item's tasks", parent_key)));
+            }
+            if parent.is_done() && !line.is_done() {
+                return Err(invalid_line(line.number, "an unfinished item can't be a subtask of a finished one"));
+            }
+        }
+        if let Some(key) = &line.key {
+            if keys.insert(key, line).is_some() {
+                return Err(invalid_line(line.number, format!("id:{} is used by an earlier line", key)));
+            }
+        }
+    }
+
+    let mut importer = Importer {
+        conn,
+        projects: project::get_all_projects(conn, &[])?,
+        tasks: HashMap::new(),
+        tags: tag::get_tags(conn)?,
+        items: HashMap::new(),
+        report: TodoImport::default(),
+    };
+    // Every item is placed while open, then closed subtasks go before their parents
+    let mut ids = Vec::with_capacity(lines.len());
+    for line in &lines {
+        ids.push(importer.write(line)?);
+    }
+    for (line, &id) in lines.iter().zip(&ids).rev() {
+        importer.finish(line, id)?;
+    }
+    Ok(importer.report)
+}
+
+fn parse(conn: &Connection, text: &str) -> Result<Line> {
+    let mut line = Line::default();
+    let mut words = text.split_whitespace().peekable();
+
+    if words.next_if_eq(&"x").is_some() {
+        line.status = Some(TaskItemStatus::Completed);
+        if let Some(completed) = words.next_if(|word| is_date(word)) {
+            line.completed_at = schedule::normalize(conn, "completion date", Some(completed), false)?;
+            if let Some(created) = words.next_if(|word| is_date(word)) {
+                line.created_at = schedule::normalize(conn, "creation date", Some(created), false)?;
+            }
+        }
+    } else {
+        if let Some(priority) = words.next_if(|word| word.len() == 3 && word.starts_with('(') && word.ends_with(')')) {
+            line.priority = Some(priority_from(&priority[1..2])?);
+        }
+        if let Some(created) = words.next_if(|word| is_date(word)) {
+            line.created_at = schedule::normalize(conn, "creation date", Some(created), false)?;
+        }
+    }
+
+    let (mut start_at, mut due_at) = (None, None);
+    for word in words {
+        if let Some(project) = word.strip_prefix('+').filter(|name| !name.is_empty()) {
+            line.links.push((Some(project.to_string()), None));
+        } else if let Some(context) = word.strip_prefix('@').filter(|name| !name.is_empty()) {
+            line.tags.push(context.to_string());
+        } else if let Some(task) = word.strip_prefix("task:").filter(|name| !name.is_empty()) {
+            match line.links.last_mut() {
+                Some((_, linked @ None)) => *linked = Some(task.to_string()),
+                _ => line.links.push((None, Some(task.to_string()))),
+            }
+        } else if let Some(priority) = word.strip_prefix("pri:") {
+            line.priority = Some(priority_from(priority)?);
+        } else if let Some(start) = word.strip_prefix("t:") {
+            start_at = Some(start);
+        } else if let Some(due) = word.strip_prefix("due:") {
+            due_at = Some(due);
+        } else if let Some(rule) = word.strip_prefix("rec:") {
+            line.recurrence = recurrence::normalize(Some(rule))?;
+        } else if let Some(key) = word.strip_prefix("id:").filter(|key| !key.is_empty()) {
+            line.key = Some(key.to_string());
+        } else if let Some(parent) = word.strip_prefix("parent:").filter(|parent| !parent.is_empty()) {
+            line.parent = Some(parent.to_string());
+        } else if let Some(status) = word.strip_prefix("status:") {
+            if line.status.is_some() {
+                return Err(AppError::validation("status", "completed lines can't have another status"));
+            }
+            line.status = Some(status_from(status)?);
+        } else {
+            line.content.push(word.to_string());
+        }
+    }
+
+    (line.start_at, line.due_at) = schedule::normalize_dates(conn, start_at, due_at)?;
+    if line.content.is_empty() {
+        return Err(AppError::validation("content", "must not be empty"));
+    }
+    Ok(line)
+}
+
+fn is_date(word: &str) -> bool {
+    word.len() == 10 && word.char_indices().all(|(index, c)| if index == 4 || index == 7 { c == '-' } else { c.is_ascii_digit() })
+}
+
+// Letters past D are less urgent than any priority here, so they all become P3
+fn priority_from(letter: &str) -> Result<Priority> {
+    match letter.as_bytes() {
+        [letter @ b'A'..=b'Z'] => Priority::try_from(i64::from((letter - b'A').min(3))),
+        _ => Err(AppError::validation("priority", format!("{:?} is not a letter A-Z", letter))),
+    }
+}
+
+fn status_from(status: &str) -> Result<TaskItemStatus> {
+    match status.to_lowercase().as_str() {
+        "pending" => Ok(TaskItemStatus::Pending),
+        "inprogress" => Ok(TaskItemStatus::InProgress),
+        "blocked" => Ok(TaskItemStatus::Blocked),
+        "deferred" => Ok(TaskItemStatus::Deferred),
+        "canceled" => Ok(TaskItemStatus::Canceled),
+        _ => Err(AppError::validation("status", format!("{:?} is not one of inprogress, blocked, deferred, canceled", status))),
+    }
+}
+
+struct Importer<'a> {
+    conn: &'a Connection,
+    projects: Vec<Project>,
+    // Each project's live tasks, read once it's first needed
+    tasks: HashMap<i64, Vec<(String, i64)>>,
+    tags: Vec<Tag>,
+    // Items made for lines with an `id:`
+    items: HashMap<String, i64>,
+    report: TodoImport,
+}
+
+impl Importer<'_> {
+    // Adds the line's item, still open, to its tasks
+    fn write(&mut self, line: &Line) -> Result<i64> {
+        let mut task_ids = Vec::new();
+        for (project, task) in line.tasks() {
+            let project_id = self.project(project)?;
+            let task_id = self.task(project_id, task)?;
+            if !task_ids.contains(&task_id) {
+                task_ids.push(task_id);
+            }
+        }
+
+        let conn = self.conn;
+        let id = task_item::create_task_item(conn, &line.content.join(" "), line.start_at.as_deref(), line.due_at.as_deref())?;
+        let parent_id = line.parent.as_ref().map(|parent| self.items[parent]);
+        for &task_id in &task_ids {
+            task_item::link_task_item(conn, task_id, id)?;
+            if let Some(parent_id) = parent_id {
+                if task_item::link_sequence(conn, task_id, parent_id)?.is_some() {
+                    task_item::nest_task_item(conn, task_id, id, Some(parent_id), None)?;
+                }
+            }
+        }
+        if line.priority.is_some() {
+            task_item::update_task_item_priority(conn, id, line.priority)?;
+        }
+        for name in &line.tags {
+            let tag_id = self.tag(name)?;
+            tag::tag_entity(conn, tag_id, Entity::TaskItem, id)?;
+        }
+        if let Some(key) = &line.key {
+            self.items.insert(key.clone(), id);
+        }
+
+        self.report.task_item_ids.push(id);
+        Ok(id)
+    }
+
+    // Sets what only applies once every item is in place
+    fn finish(&mut self, line: &Line, id: i64) -> Result<()> {
+        let conn = self.conn;
+        // Closed before the rule is set, so completing doesn't add the next occurrence
+        if let Some(status) = line.status.filter(|&status| status != TaskItemStatus::Pending) {
+            task_item::update_task_item_content(conn, id, &line.content.join(" "), status)?;
+        }
+        if line.recurrence.is_some() {
+            task_item::update_task_item_recurrence(conn, id, line.recurrence.as_deref())?;
+        }
+        conn.prepare_cached(
+            "UPDATE task_items
+             SET created_at = COALESCE(?1, created_at), completed_at = CASE WHEN completed_at IS NOT NULL THEN COALESCE(?2, completed_at) END
+             WHERE id = ?3"
+        )?.execute((&line.created_at, &line.completed_at, id))?;
+        Ok(())
+    }
+
+    fn project(&mut self, name: &str) -> Result<i64> {
+        if let Some(project) = self.projects.iter().find(|project| encode(&project.title) == name) {
+            return Ok(project.id);
+        }
+        let id = project::create_project(self.conn, &name.replace('_', " "), None)?;
+        self.projects.push(project::get_project(self.conn, id)?);
+        self.report.project_ids.push(id);
+        Ok(id)
+    }
+
+    fn task(&mut self, project_id: i64, name: &str) -> Result<i64> {
+        let tasks = match self.tasks.entry(project_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                task::get_tasks(self.conn, project_id, SortOrder::Sequence, &[])?
+                    .into_iter()
+                    .map(|task| (encode(&task.title), task.id))
+                    .collect(),
+            ),
+        };
+        if let Some(&(_, id)) = tasks.iter().find(|(title, _)| title == name) {
+            return Ok(id);
+        }
+        let id = task::create_task(self.conn, project_id, &name.replace('_', " "), None, None, None)?;
+        tasks.push((name.to_string(), id));
+        self.report.task_ids.push(id);
+        Ok(id)
+    }
+
+    // Tag names are unique regardless of case
+    fn tag(&mut self, name: &str) -> Result<i64> {
+        if let Some(tag) = self.tags.iter().find(|tag| encode(&tag.name).eq_ignore_ascii_case(name)) {
+            return Ok(tag.id);
+        }
+        let id = tag::create_tag(self.conn, &name.replace('_', " "), None)?;
+        self.tags.push(tag::get_tag(self.conn, id)?);
+        self.report.tag_ids.push(id);
+        Ok(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    // In export order, with ids as a fresh database gives them out
+    const TODO: &str = "\
+(A) 2026-10-02 Renew passport +Travel task:Documents t:2026-10-05 due:2026-10-31T12:00:00 id:1
+2026-10-02 Find old passport +Travel task:Documents @home due:2026-10-20 id:2 parent:1 status:inprogress
+2026-10-03 Print photos +Travel task:Documents +Travel task:Summer_trip rec:FREQ=MONTHLY id:3
+x 2026-10-18 2026-10-01 Book flights +Travel task:Documents @phone pri:B id:4
+2026-10-04 Old visa form +Travel task:Summer_trip id:5 status:canceled
+2026-10-06 Call the embassy +Inbox task:Inbox @phone id:6
+";
+
+    fn rejects(conn: &Connection, text: &str) -> String {
+        match import_todo_txt(conn, text) {
+            Err(AppError::Validation { field: "text", reason }) => reason,
+            other => panic!("expected {:?} to be rejected, got {:?}", text, other),
+        }
+    }
+
+    #[test]
+    fn import_then_export_round_trips() {
+        let conn = db::test_connection();
+        let report = import_todo_txt(&conn, TODO).unwrap();
+        assert_eq!(report.task_item_ids, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(report.project_ids.len(), 2);
+        assert_eq!(report.task_ids.len(), 3);
+        assert_eq!(report.tag_ids.len(), 2);
+        assert_eq!(export_todo_txt(&conn, None).unwrap(), TODO);
+
+        let renew = task_item::get_task_item(&conn, 1).unwrap();
+        assert_eq!((renew.priority, renew.start_at.as_deref()), (Some(Priority::P0), Some("2026-10-05 00:00:00")));
+        let flights = task_item::get_task_item(&conn, 4).unwrap();
+        assert_eq!((flights.status, flights.priority), (TaskItemStatus::Completed, Some(Priority::P1)));
+        assert_eq!(flights.completed_at.as_deref(), Some("2026-10-18 00:00:00"));
+        assert_eq!(flights.created_at.as_deref(), Some("2026-10-01 00:00:00"));
+        let linked: Vec<String> = task_item::get_linked_tasks(&conn, 3).unwrap().into_iter().map(|task| task.title).collect();
+        assert_eq!(linked, ["Documents", "Summer trip"]);
+        assert_eq!(task_item::link_place(&conn, report.task_ids[0], 2).unwrap().0, Some(1));
+    }
+
+    #[test]
+    fn reimporting_matches_existing_names() {
+        let conn = db::test_connection();
+        import_todo_txt(&conn, TODO).unwrap();
+        let report = import_todo_txt(&conn, &export_todo_txt(&conn, None).unwrap()).unwrap();
+        assert!(report.project_ids.is_empty() && report.task_ids.is_empty() && report.tag_ids.is_empty());
+        assert_eq!(report.task_item_ids.len(), 6);
+        // The copy of the subtask goes under the copy of its parent
+        let documents = task::get_tasks(&conn, 1, SortOrder::Sequence, &[]).unwrap()[0].id;
+        assert_eq!(task_item::link_place(&conn, documents, report.task_item_ids[1]).unwrap().0, Some(report.task_item_ids[0]));
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let conn = db::test_connection();
+        assert_eq!(rejects(&conn, "Renew id:1\nFind parent:9\n"), "line 2: parent:9 isn't the id: of an earlier line");
+        assert_eq!(rejects(&conn, "Renew id:1\n\nFind id:1\n"), "line 3: id:1 is used by an earlier line");
+        assert_eq!(rejects(&conn, "x Renew id:1\nFind parent:1\n"), "line 2: an unfinished item can't be a subtask of a finished one");
+        assert_eq!(rejects(&conn, "Renew +Travel id:1\nFind +Home parent:1\n"), "line 2: parent:1 isn't in any of the item's tasks");
+        assert!(rejects(&conn, "Renew\nFind due:soon\n").starts_with("line 2: "));
+        assert!(rejects(&conn, "Renew\n+Travel @phone\n").starts_with("line 2: "));
+        assert!(project::get_all_projects(&conn, &[]).unwrap().is_empty());
+    }
+}
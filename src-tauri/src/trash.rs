@@ -0,0 +1,69 @@
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::entity::Entity;
+use crate::error::Result;
+use crate::journal;
+use crate::tag;
+
+// A row the user deleted; `title` is the task item content for items
+#[derive(Debug, Serialize)]
+pub struct TrashEntry {
+    pub entity: Entity,
+    pub id: i64,
+    pub title: String,
+    pub deleted_at: String
+}
+
+#[derive(Debug, Serialize)]
+pub struct PurgedTrash {
+    pub projects: usize,
+    pub tasks: usize,
+    pub task_items: usize
+}
+
+pub fn list_trash(conn: &Connection) -> Result<Vec<TrashEntry>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT 'project', id, title, deleted_at FROM projects WHERE deleted_at IS NOT NULL
+         UNION ALL
+         SELECT 'task', id, title, deleted_at FROM tasks WHERE deleted_at IS NOT NULL
+         UNION ALL
+         SELECT 'task_item', id, content, deleted_at FROM task_items WHERE deleted_at IS NOT NULL
+         ORDER BY 4 DESC"
+    )?;
+
+    let entries = stmt
+        .query_map([], |row| {
+            Ok(TrashEntry {
+                entity: row.get(0)?,
+                id: row.get(1)?,
+                title: row.get(2)?,
+                deleted_at: row.get(3)?
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<TrashEntry>>>()?;
+
+    Ok(entries)
+}
+
+// Permanently deletes trashed rows, or only those trashed more than `older_than_days` ago.
+// Children of purged projects and tasks go with them through the foreign key cascades,
+// and undo entries that refer to purged rows are dropped.
+pub fn purge_trash(conn: &Connection, older_than_days: Option<u32>) -> Result<PurgedTrash> {
+    let cutoff = format!("-{} days", older_than_days.unwrap_or(0));
+
+    let purged = PurgedTrash {
+        projects: conn.prepare_cached("DELETE FROM projects WHERE deleted_at <= datetime('now', ?1)")?
+            .execute([&cutoff])?,
+        tasks: conn.prepare_cached("DELETE FROM tasks WHERE deleted_at <= datetime('now', ?1)")?
+            .execute([&cutoff])?,
+        task_items: conn.prepare_cached("DELETE FROM task_items WHERE deleted_at <= datetime('now', ?1)")?
+            .execute([&cutoff])?,
+    };
+
+    if purged.projects + purged.tasks + purged.task_items > 0 {
+        tag::remove_orphaned_taggings(conn)?;
+        journal::forget_missing(conn)?;
+    }
+    Ok(purged)
+}
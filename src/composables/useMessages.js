@@ -1,5 +1,41 @@
 import { watch } from 'vue';
 
+// Backend commands reject with an AppError tagged by `kind`
+// (not_found, validation, conflict, busy, storage)
+export function errorMessage(e) {
+  if (e === null || typeof e !== 'object') {
+    return String(e);
+  }
+
+  switch (e.kind) {
+    case 'not_found':
+      return `${e.entity.replace('_', ' ')} ${e.id} no longer exists`;
+    case 'validation':
+      return `Invalid ${e.field}: ${e.reason}`;
+    case 'conflict':
+      return `Conflict: ${e.reason}`;
+    case 'busy':
+      return 'The database is busy, please try again';
+    case 'storage':
+      return `Storage error: ${e.message}`;
+    default:
+      return JSON.stringify(e);
+  }
+}
+
+export function isErrorKind(e, kind) {
+  return e !== null && typeof e === 'object' && e.kind === kind;
+}
+
+// Someone else removed the row being edited, so leave edit mode and reload the list
+// instead of keeping the stale row around
+export async function dropIfGone(e, editIdRef, refresh) {
+  if (isErrorKind(e, 'not_found')) {
+    editIdRef.value = null;
+    await refresh();
+  }
+}
+
 export function useMessages(errorRef, successRef) {
   watch(errorRef, (newError) => {
     if (newError !== null) {